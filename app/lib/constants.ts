@@ -53,6 +53,7 @@ module.exports = {
       uerr: { ucode: 1004, message: "Already up-to-date" },
     },
     WRONG_DOCUMENT: CommonConstants.ERRORS.WRONG_DOCUMENT,
+    WRONG_CURRENCY: CommonConstants.ERRORS.WRONG_CURRENCY,
     SANDBOX_FOR_IDENTITY_IS_FULL: {
       httpCode: 503,
       uerr: {
@@ -85,6 +86,22 @@ module.exports = {
           "The transactions' sandbox is full. Please retry with another document or retry later.",
       },
     },
+    TX_ISSUER_QUOTA_EXCEEDED: {
+      httpCode: 503,
+      uerr: {
+        ucode: 1016,
+        message:
+          "Too many pending transactions for this issuer. Please retry later.",
+      },
+    },
+    TX_TOO_LOW_PRIORITY: {
+      httpCode: 503,
+      uerr: {
+        ucode: 1017,
+        message:
+          "The transactions' sandbox is full of transactions with a higher priority. Please retry later.",
+      },
+    },
     NO_POTENTIAL_FORK_AS_NEXT: {
       httpCode: 503,
       uerr: {
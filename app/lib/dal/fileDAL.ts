@@ -251,6 +251,10 @@ export class FileDAL implements ServerDAO {
       selfKeypair,
       txsMempoolSize:
         conf.txsMempoolSize || constants.SANDBOX_SIZE_TRANSACTIONS,
+      txsMempoolEviction: conf.txsMempoolEviction,
+      txsMempoolMaxPerIssuer: conf.txsMempoolMaxPerIssuer,
+      txsMempoolMembersPriority: conf.txsMempoolMembersPriority,
+      forkWindowSize: conf.forksize,
     };
     if (conf.memory) {
       this.rustServer = new RustServer(rustServerConf, null);
@@ -881,7 +885,7 @@ export class FileDAL implements ServerDAO {
     return this.rustServer.removePendingTxByHash(hash);
   }
 
-  getTransactionsPending(versionMin = 0, medianTime = 0) {
+  getTransactionsPending(versionMin = 0, medianTime?: number) {
     return this.rustServer.getTransactionsPending(versionMin, medianTime);
   }
 
@@ -1199,14 +1203,21 @@ export class FileDAL implements ServerDAO {
     return await this.idtyDAL.setRevoked(pubkey);
   }
 
-  setRevocating = (idty: BasicRevocableIdentity, revocation_sig: string) => {
+  setRevocating = async (
+    idty: BasicRevocableIdentity,
+    revocation_sig: string
+  ) => {
     const dbIdentity = IdentityDTO.fromBasicIdentity(idty);
     dbIdentity.member = idty.member;
     dbIdentity.wasMember = idty.wasMember;
     dbIdentity.expires_on = idty.expires_on;
     dbIdentity.revocation_sig = revocation_sig;
     dbIdentity.revoked = false;
-    return this.idtyDAL.saveIdentity(dbIdentity);
+    const saved = await this.idtyDAL.saveIdentity(dbIdentity);
+    this.rustServer.addPendingRevocation(
+      [dbIdentity.pubkey, revocation_sig].join(":")
+    );
+    return saved;
   };
 
   async getPeerOrNull(pubkey: string) {
@@ -1389,14 +1400,17 @@ export class FileDAL implements ServerDAO {
     await this.certDAL.trimExpiredCerts(block.medianTime);
     await this.msDAL.trimExpiredMemberships(block.medianTime);
     await this.idtyDAL.trimExpiredIdentities(block.medianTime);
-    await this.rustServer.trimExpiredNonWrittenTxs(
-      block.medianTime - CommonConstants.TX_WINDOW
-    );
+    await this.rustServer.trimExpiredNonWrittenTxs();
     return true;
   }
 
-  savePendingMembership(ms: DBMembership) {
-    return this.msDAL.savePendingMembership(ms);
+  async savePendingMembership(ms: DBMembership) {
+    const saved = await this.msDAL.savePendingMembership(ms);
+    this.rustServer.addPendingMembership(
+      [ms.issuer, ms.signature, ms.block, ms.certts, ms.userid].join(":"),
+      ms.membership === "IN"
+    );
+    return saved;
   }
 
   async saveBlockInFile(block: DBBlock) {
@@ -1415,8 +1429,17 @@ export class FileDAL implements ServerDAO {
     return merkle;
   }
 
-  savePendingIdentity(idty: DBIdentity) {
-    return this.idtyDAL.saveIdentity(idty);
+  async savePendingIdentity(idty: DBIdentity) {
+    const saved = await this.idtyDAL.saveIdentity(idty);
+    this.rustServer.addPendingIdentity(
+      [idty.pubkey, idty.sig, idty.buid, idty.uid].join(":")
+    );
+    if (idty.revocation_sig) {
+      this.rustServer.addPendingRevocation(
+        [idty.pubkey, idty.revocation_sig].join(":")
+      );
+    }
+    return saved;
   }
 
   revokeIdentity(pubkey: string) {
@@ -1431,8 +1454,12 @@ export class FileDAL implements ServerDAO {
     return await this.idtyDAL.removeUnWrittenWithUID(pubkey);
   }
 
-  registerNewCertification(cert: DBCert) {
-    return this.certDAL.saveNewCertification(cert);
+  async registerNewCertification(cert: DBCert) {
+    const saved = await this.certDAL.saveNewCertification(cert);
+    this.rustServer.addPendingCert(
+      [cert.from, cert.to, cert.block_number, cert.sig].join(":")
+    );
+    return saved;
   }
 
   saveTransaction(tx: TransactionDTO) {
@@ -203,7 +203,10 @@ export class ConfDTO
       transactions: false,
       wotwizard: false,
     },
-    public txsMempoolSize?: number
+    public txsMempoolSize?: number,
+    public txsMempoolEviction?: "none" | "oldest" | "largest",
+    public txsMempoolMaxPerIssuer?: number,
+    public txsMempoolMembersPriority?: boolean
   ) {}
 
   static mock() {
@@ -72,8 +72,27 @@ export class TransactionService extends FIFOService {
           await this.dal.getTxByHash.bind(this.dal)
         );
         const server_pubkey = this.conf.pair && this.conf.pair.pub;
-        if (!(await this.dal.rustServer.acceptNewTx(tx, server_pubkey))) {
-          throw constants.ERRORS.SANDBOX_FOR_TRANSACTION_IS_FULL;
+        switch (this.dal.rustServer.acceptNewTx(tx, server_pubkey)) {
+          case "ACCEPTED":
+            break;
+          case "ALREADY_KNOWN":
+            throw constants.ERRORS.TX_ALREADY_PROCESSED;
+          case "UNKNOWN_SOURCE":
+            throw constants.ERRORS.SOURCE_ALREADY_CONSUMED;
+          case "INVALID_SIGNATURE":
+            throw constants.ERRORS.SIGNATURE_DOES_NOT_MATCH;
+          case "WRONG_CURRENCY":
+            throw constants.ERRORS.WRONG_CURRENCY;
+          case "INVALID_DOCUMENT":
+            throw constants.ERRORS.WRONG_DOCUMENT;
+          case "ISSUER_QUOTA_EXCEEDED":
+            throw constants.ERRORS.TX_ISSUER_QUOTA_EXCEEDED;
+          case "TOO_LOW_PRIORITY":
+            throw constants.ERRORS.TX_TOO_LOW_PRIORITY;
+          case "MEMPOOL_FULL":
+            throw constants.ERRORS.SANDBOX_FOR_TRANSACTION_IS_FULL;
+          default:
+            throw constants.ERRORS.WRONG_DOCUMENT;
         }
         await this.dal.saveTransaction(tx);
         this.logger.info(
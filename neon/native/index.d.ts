@@ -14,10 +14,25 @@ export import verify = _crypto.verify;
 
 export import RustLogger = _logger.RustLogger;
 
+export import MempoolEvent = _server.MempoolEvent;
+export import RuleViolation = _server.RuleViolation;
+export import PersonalizedDifficulty = _server.PersonalizedDifficulty;
+export import PowConf = _server.PowConf;
 export import RustDbTx = _server.RustDbTx;
+export import RustIdentity = _server.RustIdentity;
 export import RustServer = _server.RustServer;
 export import RustServerConf = _server.RustServerConf;
+export import RustServerError = _server.RustServerError;
+export import RustUd = _server.RustUd;
+export import RustUdProjection = _server.RustUdProjection;
+export import RustWrittenCert = _server.RustWrittenCert;
+export import TxAcceptance = _server.TxAcceptance;
+export import SourceAmount = _server.SourceAmount;
 export import TxsHistory = _server.TxsHistory;
+export import TxsHistoryOptions = _server.TxsHistoryOptions;
+export import TxsHistoryPage = _server.TxsHistoryPage;
+export import UtxosPage = _server.UtxosPage;
+export import WalletSource = _server.WalletSource;
 
 export import TransactionDTOV10 = _transactions.TransactionDTOV10;
 export import rawTxParseAndVerify = _transactions.rawTxParseAndVerify;
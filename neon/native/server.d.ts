@@ -105,6 +105,90 @@ export class RustServerConf {
     currency: string
     selfKeypair: string | null
     txsMempoolSize: number
+    txsMempoolEviction?: 'none' | 'oldest' | 'largest'
+    txsMempoolMaxPerIssuer?: number
+    txsMempoolMembersPriority?: boolean
+    forkWindowSize?: number
+}
+
+export type TxAcceptance = 'ACCEPTED' | 'MEMPOOL_FULL' | 'ALREADY_KNOWN' | 'UNKNOWN_SOURCE' | 'INVALID_SIGNATURE' | 'WRONG_CURRENCY' | 'INVALID_DOCUMENT' | 'TOO_LOW_PRIORITY' | 'ISSUER_QUOTA_EXCEEDED';
+
+export interface MempoolEvent {
+    kind: 'TX_ADDED' | 'TX_REMOVED' | 'TX_INCLUDED_IN_BLOCK' | 'TX_EXPIRED';
+    hash: string;
+    tx?: TransactionDTOV10;
+    blockNumber?: number;
+}
+
+export interface TxsHistoryOptions {
+    fromBlock?: number;
+    toBlock?: number;
+    fromTime?: number;
+    toTime?: number;
+    cursor?: string;
+    pageSize?: number;
+}
+
+export interface SourceAmount {
+    amount: number;
+    base: number;
+}
+
+export class TxsHistoryPage {
+    sent: RustDbTx[];
+    received: RustDbTx[];
+    pendingSent: RustPendingTx[];
+    pendingReceived: RustPendingTx[];
+    balanceDelta: SourceAmount;
+    pendingBalanceDelta: SourceAmount;
+    nextCursor?: string;
+}
+
+export interface RuleViolation {
+    rule: string;
+    message: string;
+}
+
+export interface RustIdentity {
+    pubkey: string;
+    uid: string;
+    isMember: boolean;
+}
+
+export interface RustWrittenCert {
+    issuer: string;
+    receiver: string;
+    writtenBlock: number;
+    writtenTime: number;
+    expiresOn: number;
+}
+
+export interface RustUd {
+    blockNumber: number;
+    medianTime: number;
+    amount: number;
+    base: number;
+}
+
+export interface RustUdProjection {
+    medianTime: number;
+    amount: number;
+    base: number;
+    reevaluation: boolean;
+}
+
+export interface WalletSource {
+    type: 'D' | 'T';
+    noffset: number;
+    identifier: string;
+    amount: number;
+    base: number;
+    conditions: string;
+}
+
+export class UtxosPage {
+    sources: WalletSource[];
+    hasNextPage: boolean;
 }
 
 export class TxsHistory {
@@ -114,30 +198,88 @@ export class TxsHistory {
     pending: RustPendingTx[];
 }
 
+export interface PersonalizedDifficulty {
+    blockNumber: number;
+    powMin: number;
+    issuersFrame: number;
+    issuersFrameVar: number;
+    issuersCount: number;
+    personalBlocksInFrame: number;
+    medianBlocksInFrame: number;
+    lastBlockNumber: number | null;
+    difficulty: number;
+    powZeros: number;
+    powRemainder: number;
+    blocksToWait: number;
+}
+
+export interface PowConf {
+    cpu?: number;
+    nbCores?: number;
+    prefix?: number;
+}
+
+export interface RustServerError extends Error {
+    code: 'DESER_ERROR' | 'VALIDATION_ERROR' | 'DB_ERROR' | 'IO_ERROR' | 'MODULE_ERROR';
+}
+
 export class RustServer {
     constructor(conf: RustServerConf, home: string | null);
 
     // Indexing blockchain
     revertBlock(block: BlockDTOV10): void;
+    revertChunkOfBlocks(blocks: BlockDTOV10[]): void;
     applyBlock(block: BlockDTOV10): void;
     applyChunkOfBlocks(blocks: BlockDTOV10[]): void;
+    validateBlock(block: BlockDTOV10): RuleViolation[];
+
+    // Block generation
+    generateBlockCandidate(issuer: string, time: number): BlockDTOV10;
+    proveBlock(block: BlockDTOV10, difficulty: number, conf: PowConf, listener: (block: BlockDTOV10 | null) => void): void;
+    getPersonalizedDifficulty(pubkey: string): PersonalizedDifficulty | null;
+    cancelBlockProof(): void;
     
     // Rust Endpoints (GVA, etc)
     getSelfEndpoints(): string[];
 
+    // WoT mempool, documents in the compact format of blocks
+    addPendingIdentity(idty: string): void;
+    addPendingMembership(ms: string, isIn: boolean): void;
+    addPendingCert(cert: string): void;
+    addPendingRevocation(revocation: string): void;
+
     // Txs mempool
-    acceptNewTx(tx: TransactionDTOV10, serverPubkey: string): boolean;
+    acceptNewTx(tx: TransactionDTOV10, serverPubkey: string): TxAcceptance;
     addPendingTx(tx: TransactionDTOV10): void;
     getMempoolTxsFreeRooms(): number;
     getNewPendingTxs(): TransactionDTOV10[];
-    getTransactionsPending(versionMin: number, medianTime: number): TransactionDTOV10[];
+    // Return a subscription id, to give to offMempoolEvent
+    onMempoolEvent(listener: (event: MempoolEvent) => void): number;
+    offMempoolEvent(subscriptionId: number): void;
+    getTransactionsPending(versionMin: number, medianTime?: number): TransactionDTOV10[];
     removeAllPendingTxs(): void;
     removePendingTxByHash(hash: string): void;
-    trimExpiredNonWrittenTxs(limitTime: number): void;
+    trimExpiredNonWrittenTxs(): void;
 
     // Transactions history (for BMA only)
     getTransactionsHistory(pubkey: string): TxsHistory;
+    getTransactionsHistory(pubkey: string, options: TxsHistoryOptions): TxsHistoryPage;
     getTxByHash(hash: string): TransactionDTOV10 | null;
+
+    // Web of trust
+    getIdentityByPubkey(pubkey: string): RustIdentity | null;
+    getIdentityByUid(uid: string): RustIdentity | null;
+    getCertsFrom(pubkey: string): RustWrittenCert[];
+    getCertsTo(pubkey: string): RustWrittenCert[];
+    getMembershipExpiry(pubkey: string): number | null;
+
+    // Universal dividend
+    getUdHistory(from: number, to: number): RustUd[];
+    getNextUdProjection(): RustUdProjection | null;
+
+    // Wallets
+    getBalance(script: string): SourceAmount;
+    getUtxos(script: string, page?: number, pageSize?: number): UtxosPage;
     
     // WS2Pv1: HEADs and peers
     receiveNewHeads(heads: HeadWS2Pv1[]): void;
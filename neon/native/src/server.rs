@@ -31,13 +31,23 @@ use duniter_core::{
     },
     documents_parser::prelude::*,
     peer::PeerV10,
+    wallet::prelude::SourceAmount,
+};
+use duniter_server::{
+    DuniterCoreConf, DuniterMode, DuniterServer, DuniterServerError, DuniterServerResult, Identity,
+    MempoolEvent, MempoolSubscriptionId, PersonalizedDifficulty, PowConf, RuleViolation,
+    TxsHistoryCursor, TxsHistoryRange, TxsMempoolEviction, TxsMempoolPolicy, WalletSource,
+    WrittenCert, DEFAULT_FORK_WINDOW_SIZE,
 };
-use duniter_server::{DuniterCoreConf, DuniterMode, DuniterServer};
 use neon::declare_types;
+use neon::event::EventHandler;
 use neon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{path::PathBuf, str::FromStr};
 
+const DEFAULT_TXS_HISTORY_PAGE_SIZE: usize = 100;
+const DEFAULT_UTXOS_PAGE_SIZE: usize = 1_000;
+
 pub struct RustServer {
     server: DuniterServer,
 }
@@ -61,6 +71,17 @@ declare_types! {
                 self_key_pair,
                 txs_mempool_size
             };
+            let txs_mempool_policy = TxsMempoolPolicy {
+                eviction: if let Some(ref eviction_str) = rust_server_conf_stringified.txs_mempool_eviction {
+                    into_neon_res(&mut cx, TxsMempoolEviction::from_str(eviction_str))?
+                } else {
+                    TxsMempoolEviction::None
+                },
+                max_txs_per_issuer: rust_server_conf_stringified.txs_mempool_max_per_issuer.map(|max| max as usize),
+                members_priority: rust_server_conf_stringified.txs_mempool_members_priority.unwrap_or_default(),
+            };
+
+            let fork_window_size = rust_server_conf_stringified.fork_window_size.unwrap_or(DEFAULT_FORK_WINDOW_SIZE);
 
             let home_path_opt = if let Some(arg1) = arg1_opt {
                 if arg1.is_a::<JsString>() {
@@ -97,7 +118,11 @@ declare_types! {
                     DuniterServer::start(conf, currency, duniter_mode, Some(home_path.as_path()), std::env!("CARGO_PKG_VERSION"))
                 } else {
                     DuniterServer::start(conf, currency, duniter_mode, None, std::env!("CARGO_PKG_VERSION"))
-                }.map(|server| RustServer { server })
+                }.and_then(|mut server| {
+                    server.set_txs_mempool_policy(txs_mempool_policy);
+                    server.set_fork_window_size(fork_window_size)?;
+                    Ok(RustServer { server })
+                })
             )
         }
 
@@ -112,8 +137,21 @@ declare_types! {
                 let guard = cx.lock();
                 let mut server = this.borrow_mut(&guard);
                 server.server.revert_block(block_stringified)
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
+        }
+        method revertChunkOfBlocks(mut cx) {
+            let blocks_js = cx.argument::<JsValue>(0)?;
+
+            let blocks_stringified: Vec<duniter_core::block::DubpBlockV10Stringified> = neon_serde::from_value(&mut cx, blocks_js)?;
+
+            let mut this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let mut server = this.borrow_mut(&guard);
+                server.server.revert_chunk_of_blocks(blocks_stringified)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method applyBlock(mut cx) {
             let block_js = cx.argument::<JsValue>(0)?;
@@ -125,8 +163,8 @@ declare_types! {
                 let guard = cx.lock();
                 let mut server = this.borrow_mut(&guard);
                 server.server.apply_block(block_stringified)
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method applyChunkOfBlocks(mut cx) {
             let blocks_js = cx.argument::<JsValue>(0)?;
@@ -138,8 +176,107 @@ declare_types! {
                 let guard = cx.lock();
                 let mut server = this.borrow_mut(&guard);
                 server.server.apply_chunk_of_blocks(blocks_stringified)
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
+        }
+        method validateBlock(mut cx) {
+            let block_js = cx.argument::<JsValue>(0)?;
+
+            let block_stringified: duniter_core::block::DubpBlockV10Stringified = neon_serde::from_value(&mut cx, block_js)?;
+
+            let this = cx.this();
+            let violations = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.validate_block(&block_stringified).err().unwrap_or_default()
+            };
+            let violations: Vec<_> = violations.into_iter().map(|RuleViolation { rule, message }| RuleViolationStringified { rule, message }).collect();
+            Ok(neon_serde::to_value(&mut cx, &violations)?)
+        }
+
+        // Block generation
+        method generateBlockCandidate(mut cx) {
+            let issuer_str = cx.argument::<JsString>(0)?.value();
+            let time = cx.argument::<JsNumber>(1)?.value() as u64;
+            let issuer = into_neon_res(&mut cx, PublicKey::from_base58(&issuer_str))?;
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.generate_block_candidate(issuer, time)
+            };
+            match res {
+                Ok(block) => Ok(neon_serde::to_value(&mut cx, &block)?),
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        method proveBlock(mut cx) {
+            let block_js = cx.argument::<JsValue>(0)?;
+            let difficulty = cx.argument::<JsNumber>(1)?.value() as usize;
+            let pow_conf_js = cx.argument::<JsValue>(2)?;
+            let listener = cx.argument::<JsFunction>(3)?;
+
+            let block_stringified: duniter_core::block::DubpBlockV10Stringified = neon_serde::from_value(&mut cx, block_js)?;
+            let pow_conf_stringified: PowConfStringified = neon_serde::from_value(&mut cx, pow_conf_js)?;
+
+            let mut this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let mut server = this.borrow_mut(&guard);
+                server.server.start_block_proof(block_stringified, difficulty, pow_conf_stringified.into())
+            };
+            let pow_handle = match res {
+                Ok(pow_handle) => pow_handle,
+                Err(e) => return throw_server_error(&mut cx, e),
+            };
+            let event_handler = EventHandler::new(&cx, this, listener);
+            std::thread::spawn(move || {
+                let block_opt = pow_handle.wait();
+                event_handler.schedule_with(move |cx, this, listener| {
+                    let block_js = match block_opt {
+                        Some(block) => match neon_serde::to_value(cx, &block) {
+                            Ok(block_js) => block_js,
+                            Err(e) => {
+                                log::error!("fail to convert proved block: {:?}", e);
+                                return;
+                            }
+                        },
+                        None => cx.null().upcast(),
+                    };
+                    if let Err(e) = listener.call(cx, this, vec![block_js]) {
+                        log::error!("block proof listener failed: {:?}", e);
+                    }
+                });
+            });
+            Ok(cx.undefined().upcast())
+        }
+        method getPersonalizedDifficulty(mut cx) {
+            let pubkey_str = cx.argument::<JsString>(0)?.value();
+            let pubkey = into_neon_res(&mut cx, PublicKey::from_base58(&pubkey_str))?;
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_personalized_difficulty(pubkey)
+            };
+            match res {
+                Ok(difficulty_opt) => {
+                    let difficulty_opt = difficulty_opt.map(PersonalizedDifficultyStringified::from);
+                    Ok(neon_serde::to_value(&mut cx, &difficulty_opt)?)
+                }
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        method cancelBlockProof(mut cx) {
+            let mut this = cx.this();
+            {
+                let guard = cx.lock();
+                let mut server = this.borrow_mut(&guard);
+                server.server.cancel_block_proof()
+            };
+            Ok(cx.undefined().upcast())
         }
 
 
@@ -159,9 +296,56 @@ declare_types! {
                 }
                 js_array.upcast()
             });
-            into_neon_res(&mut cx, res)
+            into_neon_server_res(&mut cx, res)
+        }
+
+
+        // WoT mempool
+        method addPendingIdentity(mut cx) {
+            let compact_idty = cx.argument::<JsString>(0)?.value();
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.add_pending_identity(compact_idty)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
+        method addPendingMembership(mut cx) {
+            let compact_ms = cx.argument::<JsString>(0)?.value();
+            let is_in = cx.argument::<JsBoolean>(1)?.value();
 
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.add_pending_membership(compact_ms, is_in)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
+        }
+        method addPendingCert(mut cx) {
+            let compact_cert = cx.argument::<JsString>(0)?.value();
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.add_pending_cert(compact_cert)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
+        }
+        method addPendingRevocation(mut cx) {
+            let compact_revoc = cx.argument::<JsString>(0)?.value();
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.add_pending_revocation(compact_revoc)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
+        }
 
         // Txs mempool
         method acceptNewTx(mut cx) {
@@ -177,8 +361,8 @@ declare_types! {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
                 server.server.accept_new_tx(tx, server_pubkey)
-            }.map(|accepted| cx.boolean(accepted).upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|tx_acceptance| cx.string(tx_acceptance.code()).upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method addPendingTx(mut cx) {
             let tx_js = cx.argument::<JsValue>(0)?;
@@ -192,7 +376,7 @@ declare_types! {
                 let server = this.borrow(&guard);
                 server.server.add_pending_tx_force(tx)
             }.map(|_| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            into_neon_server_res(&mut cx, res)
         }
         method getMempoolTxsFreeRooms(mut cx) {
             let this = cx.this();
@@ -201,7 +385,7 @@ declare_types! {
                 let server = this.borrow(&guard);
                 server.server.get_mempool_txs_free_rooms()
             }.map(|free_rooms| cx.number(free_rooms as f64).upcast());
-            into_neon_res(&mut cx, res)
+            into_neon_server_res(&mut cx, res)
         }
         method getNewPendingTxs(mut cx) {
             let this = cx.this();
@@ -215,25 +399,63 @@ declare_types! {
                     let txs: Vec<_> = txs.into_iter().map(|tx| tx.to_string_object()).collect();
                     Ok(neon_serde::to_value(&mut cx, &txs)?)
                 },
-                Err(e) => cx.throw_error(format!("{}", e)),
+                Err(e) => throw_server_error(&mut cx, e),
             }
         }
+        method onMempoolEvent(mut cx) {
+            let listener = cx.argument::<JsFunction>(0)?;
+            let this = cx.this();
+            let (subscription_id, receiver) = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.subscribe_mempool_events()
+            };
+            let event_handler = EventHandler::new(&cx, this, listener);
+            std::thread::spawn(move || {
+                for event in receiver {
+                    event_handler.schedule_with(move |cx, this, listener| {
+                        if let Ok(event_js) = neon_serde::to_value(cx, &JsMempoolEvent::from(event)) {
+                            if let Err(e) = listener.call(cx, this, vec![event_js]) {
+                                log::error!("mempool event listener failed: {:?}", e);
+                            }
+                        }
+                    });
+                }
+            });
+            Ok(cx.number(subscription_id.0 as f64).upcast())
+        }
+        method offMempoolEvent(mut cx) {
+            let subscription_id = MempoolSubscriptionId(cx.argument::<JsNumber>(0)?.value() as u64);
+
+            let this = cx.this();
+            {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.unsubscribe_mempool_events(subscription_id)
+            };
+            Ok(cx.undefined().upcast())
+        }
         method getTransactionsPending(mut cx) {
             let min_version = cx.argument::<JsNumber>(0)?.value() as usize;
-            let blockchain_time = cx.argument::<JsNumber>(1)?.value() as i64;
+            let blockchain_time_opt = match cx.argument_opt(1) {
+                Some(arg1) if arg1.is_a::<JsNumber>() => {
+                    Some(arg1.downcast_or_throw::<JsNumber, _>(&mut cx)?.value() as i64)
+                }
+                _ => None,
+            };
 
             let this = cx.this();
             let res = {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
-                server.server.get_pending_txs(blockchain_time, min_version)
+                server.server.get_pending_txs(blockchain_time_opt, min_version)
             };
             match res {
                 Ok(txs) => {
                     let txs: Vec<_> = txs.into_iter().map(|tx| tx.doc.to_string_object()).collect();
                     Ok(neon_serde::to_value(&mut cx, &txs)?)
                 },
-                Err(e) => cx.throw_error(format!("{}", e)),
+                Err(e) => throw_server_error(&mut cx, e),
             }
         }
         method removeAllPendingTxs(mut cx) {
@@ -242,8 +464,8 @@ declare_types! {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
                 server.server.remove_all_pending_txs()
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method removePendingTxByHash(mut cx) {
             let hash_str = cx.argument::<JsString>(0)?.value();
@@ -254,27 +476,70 @@ declare_types! {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
                 server.server.remove_pending_tx_by_hash(hash)
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method trimExpiredNonWrittenTxs(mut cx) {
-            let limit_time = cx.argument::<JsNumber>(0)?.value() as i64;
-
             let this = cx.this();
             let res = {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
-                server.server.trim_expired_non_written_txs(limit_time)
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+                server.server.trim_expired_txs()
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
 
         // Transactions history (for BMA only)
         method getTransactionsHistory(mut cx) {
             let pubkey_str = cx.argument::<JsString>(0)?.value();
             let pubkey = into_neon_res(&mut cx, PublicKey::from_base58(&pubkey_str))?;
+            let options_opt: Option<TxsHistoryOptions> = match cx.argument_opt(1) {
+                Some(arg1) if arg1.is_a::<JsObject>() => Some(neon_serde::from_value(&mut cx, arg1)?),
+                _ => None,
+            };
 
             let this = cx.this();
+            if let Some(options) = options_opt {
+                let range_opt = into_neon_res(&mut cx, options.range())?;
+                let cursor_opt = match options.cursor {
+                    Some(ref cursor) => Some(into_neon_res(&mut cx, TxsHistoryCursor::from_str(cursor))?),
+                    None => None,
+                };
+                let res = {
+                    let guard = cx.lock();
+                    let server = this.borrow(&guard);
+                    server.server.get_transactions_history_page(
+                        pubkey,
+                        range_opt,
+                        cursor_opt,
+                        options.page_size.unwrap_or(DEFAULT_TXS_HISTORY_PAGE_SIZE),
+                    )
+                };
+                return match res {
+                    Ok(page) => {
+                        let written = |txs: Vec<(TransactionDocumentV10, Blockstamp, i64)>| -> Vec<DbTx> {
+                            txs.into_iter()
+                                .map(|(tx, wb, wt)| DbTx::v10(tx.to_string_object(), tx.get_hash(), wb.number.0, wt))
+                                .collect()
+                        };
+                        let pending = |txs: Vec<(TransactionDocumentV10, i64)>| -> Vec<PendingTx> {
+                            txs.into_iter()
+                                .map(|(tx, received_time)| PendingTx::v10(tx.to_string_object(), tx.get_hash(), received_time))
+                                .collect()
+                        };
+                        Ok(neon_serde::to_value(&mut cx, &TxsHistoryPageStringified {
+                            sent: written(page.sent),
+                            received: written(page.received),
+                            pending_sent: pending(page.pending_sent),
+                            pending_received: pending(page.pending_received),
+                            balance_delta: page.balance_delta.into(),
+                            pending_balance_delta: page.pending_balance_delta.into(),
+                            next_cursor: page.next_cursor.map(|cursor| cursor.to_string()),
+                        })?)
+                    }
+                    Err(e) => throw_server_error(&mut cx, e),
+                };
+            }
             let res = {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
@@ -308,7 +573,181 @@ declare_types! {
                         pending
                     })?)
                 },
-                Err(e) => cx.throw_error(format!("{}", e)),
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        // Web of trust
+        method getIdentityByPubkey(mut cx) {
+            let pubkey_str = cx.argument::<JsString>(0)?.value();
+            let pubkey = into_neon_res(&mut cx, PublicKey::from_base58(&pubkey_str))?;
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_identity_by_pubkey(pubkey)
+            };
+            match res {
+                Ok(idty_opt) => Ok(neon_serde::to_value(&mut cx, &idty_opt.map(IdentityStringified::from))?),
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        method getIdentityByUid(mut cx) {
+            let uid = cx.argument::<JsString>(0)?.value();
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_identity_by_uid(&uid)
+            };
+            match res {
+                Ok(idty_opt) => Ok(neon_serde::to_value(&mut cx, &idty_opt.map(IdentityStringified::from))?),
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        method getCertsFrom(mut cx) {
+            let pubkey_str = cx.argument::<JsString>(0)?.value();
+            let pubkey = into_neon_res(&mut cx, PublicKey::from_base58(&pubkey_str))?;
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_certs_from(pubkey)
+            };
+            match res {
+                Ok(certs) => {
+                    let certs: Vec<WrittenCertStringified> = certs.into_iter().map(Into::into).collect();
+                    Ok(neon_serde::to_value(&mut cx, &certs)?)
+                }
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        method getCertsTo(mut cx) {
+            let pubkey_str = cx.argument::<JsString>(0)?.value();
+            let pubkey = into_neon_res(&mut cx, PublicKey::from_base58(&pubkey_str))?;
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_certs_to(pubkey)
+            };
+            match res {
+                Ok(certs) => {
+                    let certs: Vec<WrittenCertStringified> = certs.into_iter().map(Into::into).collect();
+                    Ok(neon_serde::to_value(&mut cx, &certs)?)
+                }
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        method getMembershipExpiry(mut cx) {
+            let pubkey_str = cx.argument::<JsString>(0)?.value();
+            let pubkey = into_neon_res(&mut cx, PublicKey::from_base58(&pubkey_str))?;
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_membership_expiry(pubkey)
+            };
+            match res {
+                Ok(Some(expires_on)) => Ok(cx.number(expires_on as f64).upcast()),
+                Ok(None) => Ok(cx.null().upcast()),
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+
+        // Universal dividend
+        method getUdHistory(mut cx) {
+            let from = cx.argument::<JsNumber>(0)?.value() as u32;
+            let to = cx.argument::<JsNumber>(1)?.value() as u32;
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_ud_history(from, to)
+            };
+            match res {
+                Ok(uds) => {
+                    let uds: Vec<_> = uds.into_iter().map(|ud| UdEntryStringified {
+                        block_number: ud.block_number.0,
+                        median_time: ud.median_time,
+                        amount: ud.amount.amount(),
+                        base: ud.amount.base(),
+                    }).collect();
+                    Ok(neon_serde::to_value(&mut cx, &uds)?)
+                }
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        method getNextUdProjection(mut cx) {
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_next_ud_projection()
+            };
+            match res {
+                Ok(projection_opt) => {
+                    let projection_opt = projection_opt.map(|projection| UdProjectionStringified {
+                        median_time: projection.time,
+                        amount: projection.amount.amount(),
+                        base: projection.amount.base(),
+                        reevaluation: projection.reevaluation,
+                    });
+                    Ok(neon_serde::to_value(&mut cx, &projection_opt)?)
+                }
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+
+        // Wallets
+        method getBalance(mut cx) {
+            let script_str = cx.argument::<JsString>(0)?.value();
+            let script = into_neon_res(&mut cx, duniter_core::documents_parser::wallet_script_from_str(&script_str))?;
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_balance(&script)
+            };
+            match res {
+                Ok(balance) => Ok(neon_serde::to_value(&mut cx, &SourceAmountStringified::from(balance))?),
+                Err(e) => throw_server_error(&mut cx, e),
+            }
+        }
+        method getUtxos(mut cx) {
+            let script_str = cx.argument::<JsString>(0)?.value();
+            let script = into_neon_res(&mut cx, duniter_core::documents_parser::wallet_script_from_str(&script_str))?;
+            let page = match cx.argument_opt(1) {
+                Some(arg1) if arg1.is_a::<JsNumber>() => arg1.downcast_or_throw::<JsNumber, _>(&mut cx)?.value() as usize,
+                _ => 0,
+            };
+            let page_size = match cx.argument_opt(2) {
+                Some(arg2) if arg2.is_a::<JsNumber>() => arg2.downcast_or_throw::<JsNumber, _>(&mut cx)?.value() as usize,
+                _ => DEFAULT_UTXOS_PAGE_SIZE,
+            };
+
+            let this = cx.this();
+            let res = {
+                let guard = cx.lock();
+                let server = this.borrow(&guard);
+                server.server.get_utxos(&script, page, page_size)
+            };
+            match res {
+                Ok(utxos_page) => Ok(neon_serde::to_value(&mut cx, &UtxosPageStringified {
+                    sources: utxos_page
+                        .sources
+                        .into_iter()
+                        .map(|source| WalletSourceStringified::new(source, &script_str))
+                        .collect(),
+                    has_next_page: utxos_page.has_next_page,
+                })?),
+                Err(e) => throw_server_error(&mut cx, e),
             }
         }
         method getTxByHash(mut cx) {
@@ -333,7 +772,7 @@ declare_types! {
                 } else {
                     Ok(cx.null().upcast())
                 },
-                Err(e) => cx.throw_error(format!("{}", e)),
+                Err(e) => throw_server_error(&mut cx, e),
             }
         }
 
@@ -353,8 +792,8 @@ declare_types! {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
                 server.server.receive_new_heads(heads)
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method removeAllPeers(mut cx) {
             let this = cx.this();
@@ -362,8 +801,8 @@ declare_types! {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
                 server.server.remove_all_peers()
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method removePeerByPubkey(mut cx) {
             let pubkey_str = cx.argument::<JsString>(0)?.value();
@@ -374,8 +813,8 @@ declare_types! {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
                 server.server.remove_peer_by_pubkey(pubkey)
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method savePeer(mut cx) {
             let peer_js = cx.argument::<JsValue>(0)?;
@@ -388,8 +827,8 @@ declare_types! {
                 let guard = cx.lock();
                 let server = this.borrow(&guard);
                 server.server.save_peer(peer)
-            }.map(|()| cx.undefined().upcast());
-            into_neon_res(&mut cx, res)
+            }.map(|_| cx.undefined().upcast());
+            into_neon_server_res(&mut cx, res)
         }
         method updateSelfPeer(mut cx) {
             let peer_js = cx.argument::<JsValue>(0)?;
@@ -408,6 +847,56 @@ declare_types! {
     }
 }
 
+fn into_neon_server_res<'c, C: Context<'c>, T>(
+    cx: &mut C,
+    res: DuniterServerResult<T>,
+) -> NeonResult<T> {
+    match res {
+        Ok(value) => Ok(value),
+        Err(e) => throw_server_error(cx, e),
+    }
+}
+
+/// Throw a JS error with a `code` property, so that the JS side can match on the error kind
+fn throw_server_error<'c, C: Context<'c>, T>(cx: &mut C, e: DuniterServerError) -> NeonResult<T> {
+    let js_error = cx.error(e.to_string())?;
+    let code = cx.string(e.code());
+    js_error.set(cx, "code", code)?;
+    cx.throw(js_error)
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsMempoolEvent {
+    kind: &'static str,
+    hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx: Option<TransactionDocumentV10Stringified>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block_number: Option<u32>,
+}
+
+impl From<MempoolEvent> for JsMempoolEvent {
+    fn from(event: MempoolEvent) -> Self {
+        let (kind, hash, tx, block_number) = match event {
+            MempoolEvent::TxAdded(tx) => {
+                ("TX_ADDED", tx.get_hash(), Some(tx.to_string_object()), None)
+            }
+            MempoolEvent::TxRemoved(hash) => ("TX_REMOVED", hash, None, None),
+            MempoolEvent::TxIncludedInBlock { hash, block_number } => {
+                ("TX_INCLUDED_IN_BLOCK", hash, None, Some(block_number.0))
+            }
+            MempoolEvent::TxExpired(hash) => ("TX_EXPIRED", hash, None, None),
+        };
+        JsMempoolEvent {
+            kind,
+            hash: hash.to_hex(),
+            tx,
+            block_number,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Hash, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct DbTx {
@@ -538,6 +1027,67 @@ struct RustServerConfStringified {
     currency: String,
     self_keypair: Option<String>,
     txs_mempool_size: u32,
+    txs_mempool_eviction: Option<String>,
+    txs_mempool_max_per_issuer: Option<u32>,
+    txs_mempool_members_priority: Option<bool>,
+    fork_window_size: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PersonalizedDifficultyStringified {
+    block_number: u32,
+    pow_min: u64,
+    issuers_frame: u64,
+    issuers_frame_var: i64,
+    issuers_count: u64,
+    personal_blocks_in_frame: usize,
+    median_blocks_in_frame: f64,
+    last_block_number: Option<u32>,
+    difficulty: u64,
+    pow_zeros: u64,
+    pow_remainder: u64,
+    blocks_to_wait: u64,
+}
+
+impl From<PersonalizedDifficulty> for PersonalizedDifficultyStringified {
+    fn from(difficulty: PersonalizedDifficulty) -> Self {
+        PersonalizedDifficultyStringified {
+            block_number: difficulty.block_number,
+            pow_min: difficulty.pow_min,
+            issuers_frame: difficulty.issuers_frame,
+            issuers_frame_var: difficulty.issuers_frame_var,
+            issuers_count: difficulty.issuers_count,
+            personal_blocks_in_frame: difficulty.personal_blocks_in_frame,
+            median_blocks_in_frame: difficulty.median_blocks_in_frame,
+            last_block_number: difficulty.last_block_number,
+            difficulty: difficulty.difficulty,
+            pow_zeros: difficulty.pow_zeros(),
+            pow_remainder: difficulty.pow_remainder(),
+            blocks_to_wait: difficulty.blocks_to_wait,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PowConfStringified {
+    cpu: Option<f64>,
+    nb_cores: Option<u32>,
+    prefix: Option<u32>,
+}
+
+impl From<PowConfStringified> for PowConf {
+    fn from(pow_conf: PowConfStringified) -> Self {
+        let default = PowConf::default();
+        PowConf {
+            cpu: pow_conf.cpu.unwrap_or(default.cpu),
+            nb_cores: pow_conf
+                .nb_cores
+                .map_or(default.nb_cores, |nb_cores| nb_cores as usize),
+            prefix: pow_conf.prefix.map_or(default.prefix, u64::from),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -547,3 +1097,168 @@ struct TxsHistoryStringified {
     sending: Vec<PendingTx>,
     pending: Vec<PendingTx>,
 }
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TxsHistoryOptions {
+    from_block: Option<u32>,
+    to_block: Option<u32>,
+    from_time: Option<i64>,
+    to_time: Option<i64>,
+    cursor: Option<String>,
+    page_size: Option<usize>,
+}
+
+impl TxsHistoryOptions {
+    fn range(&self) -> Result<Option<TxsHistoryRange>, &'static str> {
+        let blocks = self.from_block.is_some() || self.to_block.is_some();
+        let times = self.from_time.is_some() || self.to_time.is_some();
+        match (blocks, times) {
+            (true, true) => Err("a blocks range and a times range cannot be combined"),
+            (true, false) => Ok(Some(TxsHistoryRange::Blocks {
+                from: self.from_block.unwrap_or(0),
+                to: self.to_block.unwrap_or(u32::MAX),
+            })),
+            (false, true) => Ok(Some(TxsHistoryRange::Times {
+                from: self.from_time.unwrap_or(0),
+                to: self.to_time.unwrap_or(i64::MAX),
+            })),
+            (false, false) => Ok(None),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TxsHistoryPageStringified {
+    sent: Vec<DbTx>,
+    received: Vec<DbTx>,
+    pending_sent: Vec<PendingTx>,
+    pending_received: Vec<PendingTx>,
+    balance_delta: SourceAmountStringified,
+    pending_balance_delta: SourceAmountStringified,
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RuleViolationStringified {
+    rule: &'static str,
+    message: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IdentityStringified {
+    pubkey: String,
+    uid: String,
+    is_member: bool,
+}
+
+impl From<Identity> for IdentityStringified {
+    fn from(idty: Identity) -> Self {
+        IdentityStringified {
+            pubkey: idty.pubkey.to_base58(),
+            uid: idty.uid,
+            is_member: idty.is_member,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WrittenCertStringified {
+    issuer: String,
+    receiver: String,
+    written_block: u32,
+    written_time: u64,
+    expires_on: u64,
+}
+
+impl From<WrittenCert> for WrittenCertStringified {
+    fn from(cert: WrittenCert) -> Self {
+        WrittenCertStringified {
+            issuer: cert.issuer.to_base58(),
+            receiver: cert.receiver.to_base58(),
+            written_block: cert.written_block.0,
+            written_time: cert.written_time,
+            expires_on: cert.expires_on,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UdEntryStringified {
+    block_number: u32,
+    median_time: u64,
+    amount: i64,
+    base: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UdProjectionStringified {
+    median_time: u64,
+    amount: i64,
+    base: i64,
+    reevaluation: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UtxosPageStringified {
+    sources: Vec<WalletSourceStringified>,
+    has_next_page: bool,
+}
+
+/// Same format as BMA `/tx/sources`
+#[derive(Serialize)]
+struct WalletSourceStringified {
+    r#type: &'static str,
+    noffset: u32,
+    identifier: String,
+    amount: i64,
+    base: i64,
+    conditions: String,
+}
+
+impl WalletSourceStringified {
+    fn new(source: WalletSource, conditions: &str) -> Self {
+        let (r#type, noffset, identifier) = match source {
+            WalletSource::Ud {
+                issuer,
+                block_number,
+                ..
+            } => ("D", block_number.0, issuer.to_base58()),
+            WalletSource::Utxo {
+                tx_hash,
+                output_index,
+                ..
+            } => ("T", output_index, tx_hash.to_hex()),
+        };
+        WalletSourceStringified {
+            r#type,
+            noffset,
+            identifier,
+            amount: source.amount().amount(),
+            base: source.amount().base(),
+            conditions: conditions.to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SourceAmountStringified {
+    amount: i64,
+    base: i64,
+}
+
+impl From<SourceAmount> for SourceAmountStringified {
+    fn from(source_amount: SourceAmount) -> Self {
+        SourceAmountStringified {
+            amount: source_amount.amount(),
+            base: source_amount.base(),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::common::currency_params::CurrencyParameters;
+
+#[derive(StructOpt)]
+pub(crate) enum CurrencyCommand {
+    /// Show the parameters of the currency (the node must be stopped).
+    #[structopt(display_order(0))]
+    Params {
+        /// Check a proposed genesis parameter set instead, in the format of the `parameters`
+        /// field of a genesis block (c:dt:ud0:sigPeriod:…:udTime0:udReevalTime0:dtReeval).
+        #[structopt(long)]
+        check: Option<String>,
+        /// Currency of the proposed parameters, it defines the parameters that are not in the
+        /// genesis block.
+        #[structopt(long, default_value = "g1")]
+        currency: String,
+    },
+}
+
+impl CurrencyCommand {
+    pub(crate) fn command(self, profile_path: &Path) -> Result<()> {
+        match self {
+            CurrencyCommand::Params {
+                check: Some(params),
+                currency,
+            } => {
+                let params = duniter_server::parse_genesis_parameters(&currency, &params)
+                    .map_err(|e| anyhow!("Invalid parameters: {}", e))?;
+                print_params(&params);
+                let problems = duniter_server::check_currency_params(&params);
+                if problems.is_empty() {
+                    println!("\nParameters are valid.");
+                    Ok(())
+                } else {
+                    println!();
+                    for problem in &problems {
+                        println!("Error: {}", problem);
+                    }
+                    Err(anyhow!("{} invalid parameter(s)", problems.len()))
+                }
+            }
+            CurrencyCommand::Params { check: None, .. } => {
+                let server = rust_only::open_stopped_node(profile_path)?;
+                print_params(&server.currency_params());
+                Ok(())
+            }
+        }
+    }
+}
+
+fn print_params(params: &CurrencyParameters) {
+    println!("protocol_version: {}", params.protocol_version);
+    println!("c: {}", params.c);
+    println!("dt: {}", format_duration(params.dt));
+    println!("dt_reeval: {}", format_duration(params.dt_reeval));
+    println!("ud0: {}", params.ud0);
+    println!("ud_time0: {}", params.ud_time0);
+    println!("ud_reeval_time0: {}", params.ud_reeval_time0);
+    println!("sig_period: {}", format_duration(params.sig_period));
+    println!(
+        "sig_renew_period: {}",
+        format_duration(params.sig_renew_period)
+    );
+    println!("sig_stock: {}", params.sig_stock);
+    println!("sig_window: {}", format_duration(params.sig_window));
+    println!("sig_validity: {}", format_duration(params.sig_validity));
+    println!("sig_qty: {}", params.sig_qty);
+    println!("idty_window: {}", format_duration(params.idty_window));
+    println!("ms_window: {}", format_duration(params.ms_window));
+    println!("ms_validity: {}", format_duration(params.ms_validity));
+    println!("ms_period: {}", format_duration(params.ms_period));
+    println!("tx_window: {}", format_duration(params.tx_window));
+    println!("x_percent: {}", params.x_percent);
+    println!("step_max: {}", params.step_max);
+    println!("median_time_blocks: {}", params.median_time_blocks);
+    println!("avg_gen_time: {}", format_duration(params.avg_gen_time));
+    println!("dt_diff_eval: {} blocks", params.dt_diff_eval);
+    println!("percent_rot: {}", params.percent_rot);
+    println!("fork_window_size: {} blocks", params.fork_window_size);
+}
+
+/// `5259600` -> `5259600s (60d 21h)`
+fn format_duration(secs: u64) -> String {
+    let units = [(86_400, "d"), (3_600, "h"), (60, "min"), (1, "s")];
+    let mut remaining = secs;
+    let mut parts = Vec::new();
+    for (unit_secs, unit) in units.iter() {
+        if remaining >= *unit_secs {
+            parts.push(format!("{}{}", remaining / unit_secs, unit));
+            remaining %= unit_secs;
+        }
+    }
+    if secs >= 60 {
+        format!("{}s ({})", secs, parts.join(" "))
+    } else {
+        format!("{}s", secs)
+    }
+}
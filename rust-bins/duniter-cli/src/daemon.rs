@@ -15,6 +15,8 @@
 
 use crate::*;
 
+const PID_FILE: &str = "app.pid";
+
 pub fn start(prod: bool, profile_path: &Path, duniter_js_args: &[String]) -> Result<()> {
     let mut duniter_js_command = Command::new(get_node_path()?);
     if prod {
@@ -28,12 +30,7 @@ pub fn start(prod: bool, profile_path: &Path, duniter_js_args: &[String]) -> Res
         .spawn()?;
 
     let pid = child.id();
-
-    // Write pid on file
-    {
-        let mut pid_file = File::create(profile_path.join("app.pid"))?;
-        pid_file.write_all(format!("{}\n{}", pid, duniter_js_args.join(" ")).as_bytes())?;
-    }
+    write_pid_file(profile_path, pid, duniter_js_args)?;
 
     println!("Duniter daemon launched (pid: {}).", pid);
 
@@ -48,8 +45,22 @@ pub fn start(prod: bool, profile_path: &Path, duniter_js_args: &[String]) -> Res
     std::process::exit(status.code().unwrap_or_default())
 }
 
+/// The pid file holds the pid of the node and the arguments to restart it with
+pub fn write_pid_file(profile_path: &Path, pid: u32, duniter_js_args: &[String]) -> Result<()> {
+    let mut pid_file = File::create(profile_path.join(PID_FILE))?;
+    pid_file.write_all(format!("{}\n{}", pid, duniter_js_args.join(" ")).as_bytes())?;
+    Ok(())
+}
+
+pub fn remove_pid_file(profile_path: &Path) -> Result<()> {
+    match std::fs::remove_file(profile_path.join(PID_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
 pub fn status(profile_path: &Path) -> Result<()> {
-    let mut pid_file = File::open(profile_path.join("app.pid"))?;
+    let mut pid_file = File::open(profile_path.join(PID_FILE))?;
     let mut pid_file_content = String::new();
     pid_file.read_to_string(&mut pid_file_content)?;
     let mut lines = pid_file_content.split('\n');
@@ -59,7 +70,8 @@ pub fn status(profile_path: &Path) -> Result<()> {
         .parse::<i32>()
         .expect("invalid pid");
 
-    match nix::sys::signal::kill(Pid::from_raw(pid), Some(Signal::SIGUSR1)) {
+    // Probe without signal, a rust-only node doesn't handle SIGUSR1
+    match nix::sys::signal::kill(Pid::from_raw(pid), None) {
         Ok(()) => {
             println!("Duniter is running using PID {}.", pid);
             Ok(())
@@ -72,8 +84,27 @@ pub fn status(profile_path: &Path) -> Result<()> {
     }
 }
 
+pub fn is_running(profile_path: &Path) -> Result<bool> {
+    let pid_file_content = match std::fs::read_to_string(profile_path.join(PID_FILE)) {
+        Ok(pid_file_content) => pid_file_content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let pid = pid_file_content
+        .split('\n')
+        .next()
+        .and_then(|pid| pid.parse::<i32>().ok())
+        .ok_or_else(|| anyhow!("corrupted pid file"))?;
+
+    match nix::sys::signal::kill(Pid::from_raw(pid), None) {
+        Ok(()) => Ok(true),
+        Err(Error::Sys(Errno::ESRCH)) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
 pub fn stop(profile_path: &Path) -> Result<Vec<String>> {
-    let mut pid_file = File::open(profile_path.join("app.pid"))?;
+    let mut pid_file = File::open(profile_path.join(PID_FILE))?;
     let mut pid_file_content = String::new();
     pid_file.read_to_string(&mut pid_file_content)?;
     let mut lines = pid_file_content.split('\n');
@@ -98,7 +129,7 @@ pub fn stop(profile_path: &Path) -> Result<Vec<String>> {
         Ok(()) => {
             println!("Stopping Duniter daemon …");
             loop {
-                match nix::sys::signal::kill(Pid::from_raw(pid), Some(Signal::SIGUSR1)) {
+                match nix::sys::signal::kill(Pid::from_raw(pid), None) {
                     Ok(()) => {
                         std::thread::sleep(std::time::Duration::from_secs(1));
                         continue;
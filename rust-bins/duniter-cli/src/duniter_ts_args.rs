@@ -63,6 +63,7 @@ pub(crate) fn gen_duniter_ts_args(
         DuniterCommand::DirectStart {
             keyprompt,
             ref start_args,
+            ..
         } => {
             duniter_ts_args.push("direct_start".to_owned());
             if keyprompt {
@@ -82,7 +83,14 @@ pub(crate) fn gen_duniter_ts_args(
             gen_start_args(start_args, &mut duniter_ts_args);
             gen_webstart_args(webstart_args, &mut duniter_ts_args);
         }
-        DuniterCommand::Gva(_) => unreachable!(),
+        DuniterCommand::Gva(_)
+        | DuniterCommand::Mempool(_)
+        | DuniterCommand::Ud(_)
+        | DuniterCommand::Currency(_)
+        | DuniterCommand::Genesis(_)
+        | DuniterCommand::Forge(_) => {
+            unreachable!()
+        }
         DuniterCommand::Start(ref start_args) => {
             duniter_ts_args.push("direct_start".to_owned());
             gen_start_args(start_args, &mut duniter_ts_args);
@@ -0,0 +1,85 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::crypto::keys::{ed25519::PublicKey, PublicKey as _};
+
+#[derive(StructOpt)]
+pub(crate) enum ForgeCommand {
+    /// Show the difficulty of the next block for a member.
+    #[structopt(display_order(0))]
+    Status {
+        /// Public key of the member (defaults to the key of the node, required if the node
+        /// has no key pair).
+        #[structopt(long, parse(try_from_str = parse_pubkey))]
+        pubkey: Option<PublicKey>,
+    },
+}
+
+fn parse_pubkey(pubkey: &str) -> Result<PublicKey> {
+    PublicKey::from_base58(pubkey).map_err(|e| anyhow!("Invalid public key: {}", e))
+}
+
+impl ForgeCommand {
+    pub(crate) fn command(self, profile_path: &Path) -> Result<()> {
+        match self {
+            ForgeCommand::Status { pubkey } => {
+                let pubkey = match pubkey {
+                    Some(pubkey) => pubkey,
+                    None => rust_only::conf_pubkey(profile_path)?.ok_or_else(|| {
+                        anyhow!("No key pair in configuration, please give --pubkey.")
+                    })?,
+                };
+                // The node may be running, its databases are read from a copy
+                let difficulty_opt = rust_only::with_node_snapshot(profile_path, |server| {
+                    Ok(server.get_personalized_difficulty(pubkey)?)
+                })?;
+                if let Some(difficulty) = difficulty_opt {
+                    println!("Member: {}", pubkey.to_base58());
+                    println!("Next block: #{}", difficulty.block_number);
+                    println!("pow_min: {}", difficulty.pow_min);
+                    println!(
+                        "Issuers frame: {} blocks ({} issuers, variation {})",
+                        difficulty.issuers_frame,
+                        difficulty.issuers_count,
+                        difficulty.issuers_frame_var
+                    );
+                    println!(
+                        "Blocks of the member in the frame: {} (median per issuer: {})",
+                        difficulty.personal_blocks_in_frame, difficulty.median_blocks_in_frame
+                    );
+                    if let Some(last_block_number) = difficulty.last_block_number {
+                        println!("Last block of the member: #{}", last_block_number);
+                    }
+                    println!(
+                        "Personalized difficulty: {} ({} leading zeros, then [0-{:X}])",
+                        difficulty.difficulty,
+                        difficulty.pow_zeros(),
+                        15 - difficulty.pow_remainder()
+                    );
+                    if difficulty.blocks_to_wait > 0 {
+                        println!(
+                            "The difficulty decreases after {} more block(s).",
+                            difficulty.blocks_to_wait
+                        );
+                    }
+                } else {
+                    println!("No blockchain, please sync your node first.");
+                }
+            }
+        }
+        Ok(())
+    }
+}
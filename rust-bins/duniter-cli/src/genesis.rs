@@ -0,0 +1,117 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::crypto::keys::KeyPair as _;
+use duniter_server::{GenesisBlockBuilder, GenesisCert, GenesisMember};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(StructOpt)]
+pub(crate) struct GenesisCommand {
+    /// Name of the new currency.
+    #[structopt(long)]
+    currency: String,
+    /// Parameters of the new currency, in the format of the `parameters` field of a genesis
+    /// block (see `duniter currency params --check`).
+    #[structopt(long)]
+    parameters: String,
+    /// Initial member `uid:keyfile`, repeat for each member. The first member issues the block.
+    /// Every initial member certifies all the others.
+    #[structopt(long = "member", parse(try_from_str = parse_member), required = true)]
+    members: Vec<(String, PathBuf)>,
+    /// Time of the genesis block (defaults to now).
+    #[structopt(long)]
+    time: Option<u64>,
+    /// Minimal proof of work of the genesis block.
+    #[structopt(long, default_value = "0")]
+    pow_min: usize,
+    /// Write the block to this JSON file instead of stdout.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Apply the block to the local blockchain (the node must be stopped, its database empty and
+    /// its currency, if configured, the new one).
+    #[structopt(long)]
+    apply: bool,
+}
+
+fn parse_member(member: &str) -> Result<(String, PathBuf)> {
+    let mut parts = member.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(uid), Some(keyfile)) if !uid.is_empty() && !keyfile.is_empty() => {
+            Ok((uid.to_owned(), PathBuf::from(keyfile)))
+        }
+        _ => Err(anyhow!("member must be in the format uid:keyfile")),
+    }
+}
+
+impl GenesisCommand {
+    pub(crate) fn command(self, profile_path: &Path) -> Result<()> {
+        let currency_params =
+            duniter_server::parse_genesis_parameters(&self.currency, &self.parameters)
+                .map_err(|e| anyhow!("Invalid parameters: {}", e))?;
+        let keypairs = self
+            .members
+            .iter()
+            .map(|(_, keyfile)| rust_only::keypair_from_keyfile(keyfile))
+            .collect::<Result<Vec<_>>>()?;
+        let members: Vec<GenesisMember> = self
+            .members
+            .iter()
+            .zip(keypairs.iter())
+            .map(|((uid, _), keypair)| GenesisMember::sign(&self.currency, uid, keypair))
+            .collect();
+        let mut certs = Vec::new();
+        for issuer in &keypairs {
+            for receiver in &members {
+                if issuer.public_key() != receiver.pubkey {
+                    certs.push(GenesisCert::sign(&self.currency, issuer, receiver));
+                }
+            }
+        }
+        let time = if let Some(time) = self.time {
+            time
+        } else {
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()
+        };
+
+        let block = GenesisBlockBuilder {
+            currency: self.currency.clone(),
+            currency_params,
+            time,
+            pow_min: self.pow_min,
+            members,
+            certs,
+        }
+        .build_stringified(&keypairs[0])?;
+
+        let json = serde_json::to_string_pretty(&block)?;
+        if let Some(output) = self.output {
+            File::create(output)?.write_all(json.as_bytes())?;
+        } else {
+            println!("{}", json);
+        }
+
+        if self.apply {
+            let mut server =
+                rust_only::open_stopped_node_for_genesis(profile_path, &self.currency)?;
+            if server.get_current_blockstamp().is_some() {
+                return Err(anyhow!("The local blockchain is not empty."));
+            }
+            server.apply_block(block)?;
+            eprintln!("Genesis block applied.");
+        }
+        Ok(())
+    }
+}
@@ -26,9 +26,15 @@
 )]
 
 mod config;
+mod currency;
 mod daemon;
 mod duniter_ts_args;
+mod forge;
+mod genesis;
+mod mempool;
+mod rust_only;
 mod sync;
+mod ud;
 
 use anyhow::{anyhow, Result};
 use daemonize_me::Daemon;
@@ -98,6 +104,12 @@ enum DuniterCommand {
         /// Force to use the keypair given by user prompt.
         #[structopt(long)]
         keyprompt: bool,
+        /// Start only the rust server (GVA and global background task), without Node.js.
+        #[structopt(long, conflicts_with("keyprompt"))]
+        rust_only: bool,
+        /// Node to pull the blocks from in rust-only mode (host[:port] or BMA url).
+        #[structopt(long)]
+        sync_source: Option<String>,
         #[structopt(flatten)]
         start_args: DuniterStartArgs,
     },
@@ -138,8 +150,23 @@ enum DuniterCommand {
     /// Reset configuration, data, peers, transactions or everything in the database
     #[structopt(display_order(12))]
     Reset(ResetCommand),
-    /// Generate tab-completion script for your shell
+    /// Pending transactions operations (the node must be stopped).
     #[structopt(display_order(13))]
+    Mempool(mempool::MempoolCommand),
+    /// Universal dividend history and projection (the node must be stopped).
+    #[structopt(display_order(14))]
+    Ud(ud::UdCommand),
+    /// Currency parameters.
+    #[structopt(display_order(15))]
+    Currency(currency::CurrencyCommand),
+    /// Build the genesis block of a new currency.
+    #[structopt(display_order(16))]
+    Genesis(genesis::GenesisCommand),
+    /// Block forging status.
+    #[structopt(display_order(17))]
+    Forge(forge::ForgeCommand),
+    /// Generate tab-completion script for your shell
+    #[structopt(display_order(18))]
     Completions {
         #[structopt(case_insensitive(true))]
         shell: Shell,
@@ -220,6 +247,30 @@ fn main() -> Result<()> {
         if let DuniterCommand::Gva(gva_command) = args.command {
             return gva_command.command(profile_path);
         }
+        if let DuniterCommand::Mempool(mempool_command) = args.command {
+            return mempool_command.command(&profile_path);
+        }
+        if let DuniterCommand::Ud(ud_command) = args.command {
+            return ud_command.command(&profile_path);
+        }
+        if let DuniterCommand::Currency(currency_command) = args.command {
+            return currency_command.command(&profile_path);
+        }
+        if let DuniterCommand::Genesis(genesis_command) = args.command {
+            return genesis_command.command(&profile_path);
+        }
+        if let DuniterCommand::Forge(forge_command) = args.command {
+            return forge_command.command(&profile_path);
+        }
+        if let DuniterCommand::DirectStart {
+            rust_only: true,
+            ref sync_source,
+            ref start_args,
+            ..
+        } = args.command
+        {
+            return rust_only::direct_start(&profile_path, start_args, sync_source.as_deref());
+        }
 
         let current_exe = std::env::current_exe()?;
         let prod = current_exe == PathBuf::from(DUNITER_EXE_LINK_PATH)
@@ -230,7 +281,14 @@ fn main() -> Result<()> {
 
         match args.command {
             DuniterCommand::Restart => {
-                daemon::start(prod, &profile_path, &daemon::stop(&profile_path)?)
+                let duniter_args = daemon::stop(&profile_path)?;
+                if let Some((start_args, sync_source)) =
+                    rust_only::parse_pid_file_args(&duniter_args)
+                {
+                    rust_only::direct_start(&profile_path, &start_args, sync_source.as_deref())
+                } else {
+                    daemon::start(prod, &profile_path, &duniter_args)
+                }
             }
             DuniterCommand::Start(_) | DuniterCommand::Webstart { .. } => {
                 daemon::start(prod, &profile_path, &duniter_ts_args)
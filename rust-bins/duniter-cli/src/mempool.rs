@@ -0,0 +1,137 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::crypto::hashs::Hash;
+use duniter_core::documents::{prelude::*, transaction::TransactionDocumentTrait};
+use std::io::{BufReader, BufWriter};
+
+#[derive(StructOpt)]
+pub(crate) enum MempoolCommand {
+    /// List pending transactions.
+    #[structopt(display_order(0))]
+    List,
+    /// Show a pending transaction.
+    #[structopt(display_order(1))]
+    Show {
+        /// Transaction hash.
+        #[structopt(parse(try_from_str = Hash::from_hex))]
+        hash: Hash,
+    },
+    /// Remove a pending transaction and the pending transactions that depend on it.
+    #[structopt(display_order(2))]
+    Remove {
+        /// Transaction hash.
+        #[structopt(parse(try_from_str = Hash::from_hex))]
+        hash: Hash,
+    },
+    /// Remove all pending transactions.
+    #[structopt(display_order(3))]
+    Clear,
+    /// Show mempool occupancy.
+    #[structopt(display_order(4))]
+    Stats,
+    /// Export pending transactions as JSON lines.
+    #[structopt(display_order(5))]
+    Export {
+        /// Output file (defaults to stdout).
+        #[structopt(short, long, parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// Import pending transactions exported by another node.
+    #[structopt(display_order(6))]
+    Import {
+        /// Input file (defaults to stdin).
+        #[structopt(short, long, parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+}
+
+impl MempoolCommand {
+    pub(crate) fn command(self, profile_path: &Path) -> Result<()> {
+        let server = rust_only::open_stopped_node(profile_path)?;
+        match self {
+            MempoolCommand::List => {
+                for pending_tx in server.get_pending_txs(None, 0)? {
+                    let tx = pending_tx.doc;
+                    println!(
+                        "{} issuers={} inputs={} outputs={}",
+                        tx.get_hash(),
+                        tx.issuers()
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(","),
+                        tx.get_inputs().len(),
+                        tx.get_outputs().len(),
+                    );
+                }
+            }
+            MempoolCommand::Show { hash } => {
+                if let Some(pending_tx) = server
+                    .get_pending_txs(None, 0)?
+                    .into_iter()
+                    .find(|pending_tx| pending_tx.doc.get_hash() == hash)
+                {
+                    print!("{}", pending_tx.doc.as_text());
+                } else {
+                    return Err(anyhow!("Transaction {} not found in mempool.", hash));
+                }
+            }
+            MempoolCommand::Remove { hash } => {
+                if !server
+                    .get_pending_txs(None, 0)?
+                    .iter()
+                    .any(|pending_tx| pending_tx.doc.get_hash() == hash)
+                {
+                    return Err(anyhow!("Transaction {} not found in mempool.", hash));
+                }
+                server.remove_pending_tx_by_hash(hash)?;
+                println!("Transaction {} removed.", hash);
+            }
+            MempoolCommand::Clear => {
+                server.remove_all_pending_txs()?;
+                println!("All pending transactions removed.");
+            }
+            MempoolCommand::Stats => {
+                let pending_txs_count = server.get_pending_txs(None, 0)?.len();
+                let free_rooms = server.get_mempool_txs_free_rooms()?;
+                println!("Pending transactions: {}", pending_txs_count);
+                println!("Free rooms: {}", free_rooms);
+                println!("Capacity: {}", pending_txs_count + free_rooms);
+            }
+            MempoolCommand::Export { file } => {
+                let count = if let Some(file) = file {
+                    server.export_mempool(BufWriter::new(File::create(file)?))?
+                } else {
+                    server.export_mempool(std::io::stdout().lock())?
+                };
+                eprintln!("{} pending transactions exported.", count);
+            }
+            MempoolCommand::Import { file } => {
+                let report = if let Some(file) = file {
+                    server.import_mempool(BufReader::new(File::open(file)?))?
+                } else {
+                    server.import_mempool(std::io::stdin().lock())?
+                };
+                eprintln!(
+                    "{} pending transactions imported, {} rejected.",
+                    report.imported, report.rejected
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,533 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::block::{parser::parse_json_block_from_serde_value, DubpBlock};
+use duniter_core::crypto::keys::{
+    ed25519::{Ed25519KeyPair, KeyPairFromSeed32Generator, PublicKey},
+    KeyPair as _,
+};
+use duniter_core::crypto::seeds::Seed32;
+use duniter_core::documents::ToStringObject as _;
+use duniter_server::{
+    DuniterCoreConf, DuniterMode, DuniterServer, ForkChoice, TxsMempoolEviction, TxsMempoolPolicy,
+    DEFAULT_FORK_WINDOW_SIZE,
+};
+use serde_json::Value;
+use std::{sync::mpsc::RecvTimeoutError, time::Duration};
+
+const CONF_FILE: &str = "conf.json";
+/// First argument recorded in the pid file by a rust-only node, so that it restarts without
+/// Node.js
+const RUST_ONLY_PID_ARG: &str = "--rust-only";
+/// Directory of the databases in a profile
+const DATA_DIR: &str = "data";
+/// Databases of the data directory not opened by the server: bc_v1 and the gva index
+const SERVER_UNUSED_DBS: [&str; 2] = ["leveldb", "gva"];
+const DEFAULT_TXS_MEMPOOL_SIZE: usize = 200;
+/// Number of blocks requested to the sync source at once
+const SYNC_CHUNK_SIZE: u32 = 250;
+/// Delay between two pulls of the new blocks of the sync source
+const SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+pub(crate) fn direct_start(
+    profile_path: &Path,
+    start_args: &DuniterStartArgs,
+    sync_source: Option<&str>,
+) -> Result<()> {
+    if daemon::is_running(profile_path)? {
+        return Err(anyhow!("Duniter is already running."));
+    }
+    let (conf, currency) = load_conf(profile_path, start_args)?;
+    let txs_mempool_policy = load_txs_mempool_policy(profile_path)?;
+    let fork_window_size = load_fork_window_size(profile_path)?;
+
+    let (stop_sender, stop_receiver) = std::sync::mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = stop_sender.send(());
+    })?;
+
+    let mut server = DuniterServer::start(
+        conf,
+        currency,
+        DuniterMode::Start,
+        Some(profile_path),
+        std::env!("CARGO_PKG_VERSION"),
+    )?;
+    server.set_txs_mempool_policy(txs_mempool_policy);
+    server.set_fork_window_size(fork_window_size)?;
+    daemon::write_pid_file(
+        profile_path,
+        std::process::id(),
+        &pid_file_args(start_args, sync_source),
+    )?;
+    println!(
+        "Duniter started in rust-only mode (gva endpoints: {:?}).",
+        server.get_self_endpoints()?
+    );
+
+    let sync_source_url = sync_source.map(sync_source_url);
+    loop {
+        if let Some(ref sync_source_url) = sync_source_url {
+            match sync_blocks(&mut server, sync_source_url) {
+                Ok(0) => (),
+                Ok(applied) => log::info!("{} blocks applied from {}", applied, sync_source_url),
+                Err(e) => log::error!("Fail to sync blocks from {}: {}", sync_source_url, e),
+            }
+        }
+        match stop_receiver.recv_timeout(SYNC_INTERVAL) {
+            Err(RecvTimeoutError::Timeout) => continue,
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    println!("Stopping Duniter …");
+    daemon::remove_pid_file(profile_path)?;
+
+    Ok(())
+}
+
+fn pid_file_args(start_args: &DuniterStartArgs, sync_source: Option<&str>) -> Vec<String> {
+    let mut args = vec![RUST_ONLY_PID_ARG.to_owned()];
+    if let Some(ref keyfile) = start_args.keyfile {
+        args.push("--keyfile".to_owned());
+        args.push(keyfile.to_string_lossy().into_owned());
+    }
+    if let Some(sync_source) = sync_source {
+        args.push("--sync-source".to_owned());
+        args.push(sync_source.to_owned());
+    }
+    args
+}
+
+/// Start arguments and sync source of a rust-only node, from the arguments of its pid file.
+/// `None` if the node was started by Node.js.
+pub(crate) fn parse_pid_file_args(args: &[String]) -> Option<(DuniterStartArgs, Option<String>)> {
+    if args.first().map(String::as_str) != Some(RUST_ONLY_PID_ARG) {
+        return None;
+    }
+    let mut start_args = DuniterStartArgs { keyfile: None };
+    let mut sync_source = None;
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keyfile" => start_args.keyfile = args.next().map(PathBuf::from),
+            "--sync-source" => sync_source = args.next().cloned(),
+            _ => (),
+        }
+    }
+    Some((start_args, sync_source))
+}
+
+/// Pull the blocks following the current block from the BMA API of the sync source. Return
+/// the number of blocks applied.
+fn sync_blocks(server: &mut DuniterServer, sync_source_url: &str) -> Result<usize> {
+    let mut applied = 0;
+    loop {
+        let from = server
+            .get_current_blockstamp()
+            .map_or(0, |current| current.number.0 + 1);
+        let json_blocks: Vec<Value> = ureq::get(&format!(
+            "{}/blockchain/blocks/{}/{}",
+            sync_source_url, SYNC_CHUNK_SIZE, from
+        ))
+        .call()?
+        .into_json()?;
+        if json_blocks.is_empty() {
+            return Ok(applied);
+        }
+        for json_block in &json_blocks {
+            let block = match parse_json_block_from_serde_value(json_block)? {
+                DubpBlock::V10(block) => block,
+            };
+            match server.receive_block(block.to_string_object())? {
+                ForkChoice::Applied | ForkChoice::Switched { .. } => applied += 1,
+                ForkChoice::Rejected(violations) => {
+                    return Err(anyhow!(
+                        "invalid block #{} from {}: {:?}",
+                        block.number(),
+                        sync_source_url,
+                        violations
+                    ))
+                }
+                // The source is on another branch, wait for the fork resolution
+                _ => return Ok(applied),
+            }
+        }
+    }
+}
+
+/// The sync source is a BMA url, or a host with an optional port (443 by default)
+fn sync_source_url(sync_source: &str) -> String {
+    let sync_source = sync_source.trim_end_matches('/');
+    if sync_source.starts_with("http://") || sync_source.starts_with("https://") {
+        sync_source.to_owned()
+    } else {
+        let (host, port) = match sync_source.rfind(':') {
+            Some(i) => (
+                &sync_source[..i],
+                sync_source[i + 1..].parse().unwrap_or(DEFAULT_PORT),
+            ),
+            None => (sync_source, DEFAULT_PORT),
+        };
+        if port == DEFAULT_PORT {
+            format!("https://{}", host)
+        } else {
+            format!("http://{}:{}", host, port)
+        }
+    }
+}
+
+/// Open the databases of a stopped node, without serving anything
+pub(crate) fn open_stopped_node(profile_path: &Path) -> Result<DuniterServer> {
+    if daemon::is_running(profile_path)? {
+        return Err(anyhow!("Duniter is running, please stop it first."));
+    }
+    let (conf, currency) = load_conf(profile_path, &DuniterStartArgs { keyfile: None })?;
+    DuniterServer::open_offline(conf, currency, Some(profile_path))
+}
+
+/// Open a stopped node to apply the genesis block of `currency`. A fresh profile has no
+/// currency yet, it is recorded in the configuration, otherwise it must be `currency`.
+pub(crate) fn open_stopped_node_for_genesis(
+    profile_path: &Path,
+    currency: &str,
+) -> Result<DuniterServer> {
+    if daemon::is_running(profile_path)? {
+        return Err(anyhow!("Duniter is running, please stop it first."));
+    }
+    let mut conf_json = read_conf_json(profile_path)?;
+    match conf_json["currency"].as_str() {
+        Some(conf_currency) if conf_currency != currency => {
+            return Err(anyhow!(
+                "The node is configured for currency {}, not {}.",
+                conf_currency,
+                currency
+            ));
+        }
+        Some(_) => (),
+        None => {
+            conf_json["currency"] = Value::String(currency.to_owned());
+            std::fs::create_dir_all(profile_path)?;
+            std::fs::write(
+                profile_path.join(CONF_FILE),
+                serde_json::to_string_pretty(&conf_json)?,
+            )?;
+        }
+    }
+    let (conf, currency) = load_conf(profile_path, &DuniterStartArgs { keyfile: None })?;
+    DuniterServer::open_offline(conf, currency, Some(profile_path))
+}
+
+/// Run `f` on a copy of the databases of a node, running or not. A running node locks its
+/// databases, the copy is read without disturbing it and nothing is written in the profile.
+pub(crate) fn with_node_snapshot<T>(
+    profile_path: &Path,
+    f: impl FnOnce(&DuniterServer) -> Result<T>,
+) -> Result<T> {
+    let (conf, currency) = load_conf(profile_path, &DuniterStartArgs { keyfile: None })?;
+    let snapshot_path =
+        std::env::temp_dir().join(format!("duniter-snapshot-{}", std::process::id()));
+    let res = copy_server_dbs(profile_path, &snapshot_path).and_then(|()| {
+        let server = DuniterServer::open_offline(conf, currency, Some(&snapshot_path))?;
+        f(&server)
+    });
+    if snapshot_path.exists() {
+        std::fs::remove_dir_all(&snapshot_path)?;
+    }
+    res
+}
+
+fn copy_server_dbs(profile_path: &Path, snapshot_path: &Path) -> Result<()> {
+    let data_path = profile_path.join(DATA_DIR);
+    let snapshot_data_path = snapshot_path.join(DATA_DIR);
+    std::fs::create_dir_all(&snapshot_data_path)?;
+    if !data_path.exists() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(data_path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if !SERVER_UNUSED_DBS
+            .iter()
+            .any(|db| name.to_string_lossy().starts_with(db))
+        {
+            copy_recursively(&entry.path(), &snapshot_data_path.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_recursively(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Public key of the key pair of the configuration, if any
+pub(crate) fn conf_pubkey(profile_path: &Path) -> Result<Option<PublicKey>> {
+    read_conf_json(profile_path)?["pair"]["sec"]
+        .as_str()
+        .map(|sec| keypair_from_expanded_base58_secret_key(sec).map(|keypair| keypair.public_key()))
+        .transpose()
+}
+
+fn read_conf_json(profile_path: &Path) -> Result<Value> {
+    Ok(if profile_path.join(CONF_FILE).exists() {
+        serde_json::from_reader(File::open(profile_path.join(CONF_FILE))?)?
+    } else {
+        Value::Null
+    })
+}
+
+pub(crate) fn load_conf(
+    profile_path: &Path,
+    start_args: &DuniterStartArgs,
+) -> Result<(DuniterCoreConf, String)> {
+    let conf_json = read_conf_json(profile_path)?;
+
+    let currency = conf_json["currency"]
+        .as_str()
+        .ok_or_else(|| anyhow!("No currency in configuration, please sync your node first."))?
+        .to_owned();
+
+    let self_key_pair = if let Some(ref keyfile) = start_args.keyfile {
+        keypair_from_keyfile(keyfile)?
+    } else if let Some(sec) = conf_json["pair"]["sec"].as_str() {
+        keypair_from_expanded_base58_secret_key(sec)?
+    } else {
+        Ed25519KeyPair::generate_random().map_err(|_| anyhow!("fail to gen random keypair"))?
+    };
+
+    let txs_mempool_size = conf_json["txsMempoolSize"]
+        .as_u64()
+        .map(|size| size as usize)
+        .unwrap_or(DEFAULT_TXS_MEMPOOL_SIZE);
+
+    Ok((
+        DuniterCoreConf {
+            self_key_pair,
+            txs_mempool_size,
+        },
+        currency,
+    ))
+}
+
+/// Same policy as the one given by the JS layer to the server
+pub(crate) fn load_txs_mempool_policy(profile_path: &Path) -> Result<TxsMempoolPolicy> {
+    let conf_json = read_conf_json(profile_path)?;
+    Ok(TxsMempoolPolicy {
+        eviction: if let Some(eviction) = conf_json["txsMempoolEviction"].as_str() {
+            TxsMempoolEviction::from_str(eviction).map_err(|e| anyhow!(e))?
+        } else {
+            TxsMempoolEviction::None
+        },
+        max_txs_per_issuer: conf_json["txsMempoolMaxPerIssuer"]
+            .as_u64()
+            .map(|max| max as usize),
+        members_priority: conf_json["txsMempoolMembersPriority"]
+            .as_bool()
+            .unwrap_or_default(),
+    })
+}
+
+/// Fork window size of the configuration, `forksize` as in the JS layer
+pub(crate) fn load_fork_window_size(profile_path: &Path) -> Result<u32> {
+    let conf_json = read_conf_json(profile_path)?;
+    Ok(conf_json["forksize"]
+        .as_u64()
+        .map(|forksize| forksize as u32)
+        .unwrap_or(DEFAULT_FORK_WINDOW_SIZE))
+}
+
+pub(crate) fn keypair_from_keyfile(keyfile: &Path) -> Result<Ed25519KeyPair> {
+    let mut keyfile_content = String::new();
+    File::open(keyfile)?.read_to_string(&mut keyfile_content)?;
+
+    let sec = keyfile_content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("sec:"))
+        .map(|sec| sec.trim().trim_matches(|c| c == '"' || c == '\''))
+        .next()
+        .ok_or_else(|| anyhow!("Keyfile must contain a `sec:` field."))?;
+
+    keypair_from_expanded_base58_secret_key(sec)
+}
+
+fn keypair_from_expanded_base58_secret_key(
+    expanded_base58_secret_key: &str,
+) -> Result<Ed25519KeyPair> {
+    let bytes = bs58::decode(expanded_base58_secret_key)
+        .into_vec()
+        .map_err(|_| anyhow!("fail to decode b58"))?;
+    if bytes.len() != 64 {
+        return Err(anyhow!("Invalid expanded secret key length"));
+    }
+
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&bytes[..32]);
+
+    let keypair = KeyPairFromSeed32Generator::generate(Seed32::new(seed));
+    if keypair.public_key().as_ref()[..32] == bytes[32..64] {
+        Ok(keypair)
+    } else {
+        Err(anyhow!("corrupted keypair"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_source_url() {
+        assert_eq!(sync_source_url("g1.duniter.org"), "https://g1.duniter.org");
+        assert_eq!(
+            sync_source_url("g1.duniter.org:443"),
+            "https://g1.duniter.org"
+        );
+        assert_eq!(sync_source_url("localhost:10901"), "http://localhost:10901");
+        assert_eq!(
+            sync_source_url("https://g1.duniter.org/bma/"),
+            "https://g1.duniter.org/bma"
+        );
+    }
+
+    #[test]
+    fn test_pid_file() -> Result<()> {
+        let profile_path =
+            std::env::temp_dir().join(format!("duniter-cli-pid-{}", std::process::id()));
+        std::fs::create_dir_all(&profile_path)?;
+        assert!(!daemon::is_running(&profile_path)?);
+
+        // A rust-only node is seen by the "node must be stopped" guards
+        daemon::write_pid_file(&profile_path, std::process::id(), &[])?;
+        assert!(daemon::is_running(&profile_path)?);
+        assert!(open_stopped_node(&profile_path).is_err());
+
+        daemon::remove_pid_file(&profile_path)?;
+        assert!(!daemon::is_running(&profile_path)?);
+        std::fs::remove_dir_all(profile_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_pid_file_args() {
+        let args = pid_file_args(
+            &DuniterStartArgs {
+                keyfile: Some(PathBuf::from("/tmp/key.yml")),
+            },
+            Some("g1.duniter.org"),
+        );
+        assert_eq!(
+            args,
+            vec![
+                "--rust-only",
+                "--keyfile",
+                "/tmp/key.yml",
+                "--sync-source",
+                "g1.duniter.org"
+            ]
+        );
+        let (start_args, sync_source) =
+            parse_pid_file_args(&args).expect("rust-only args expected");
+        assert_eq!(start_args.keyfile, Some(PathBuf::from("/tmp/key.yml")));
+        assert_eq!(sync_source.as_deref(), Some("g1.duniter.org"));
+
+        let rust_only_args = pid_file_args(&DuniterStartArgs { keyfile: None }, None);
+        assert!(parse_pid_file_args(&rust_only_args).is_some());
+        // Node was started by Node.js
+        assert!(parse_pid_file_args(&["/opt/duniter/bin/duniter_js".to_owned()]).is_none());
+        assert!(parse_pid_file_args(&[]).is_none());
+    }
+
+    #[test]
+    fn test_open_stopped_node_for_genesis() -> Result<()> {
+        let profile_path =
+            std::env::temp_dir().join(format!("duniter-cli-genesis-{}", std::process::id()));
+        std::fs::create_dir_all(&profile_path)?;
+
+        // A fresh profile takes the currency of the genesis block
+        assert!(open_stopped_node(&profile_path).is_err());
+        let server = open_stopped_node_for_genesis(&profile_path, "test")?;
+        assert!(server.get_current_blockstamp().is_none());
+        drop(server);
+        assert_eq!(read_conf_json(&profile_path)?["currency"], "test");
+
+        assert!(open_stopped_node_for_genesis(&profile_path, "g1").is_err());
+
+        std::fs::remove_dir_all(profile_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_node_snapshot() -> Result<()> {
+        let profile_path =
+            std::env::temp_dir().join(format!("duniter-cli-snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&profile_path)?;
+        std::fs::write(profile_path.join(CONF_FILE), r#"{"currency":"test"}"#)?;
+        assert_eq!(conf_pubkey(&profile_path)?, None);
+
+        // The databases of a running node are read from a copy
+        daemon::write_pid_file(&profile_path, std::process::id(), &[])?;
+        let current =
+            with_node_snapshot(&profile_path, |server| Ok(server.get_current_blockstamp()))?;
+        assert_eq!(current, None);
+        assert!(!profile_path.join(DATA_DIR).exists());
+
+        daemon::remove_pid_file(&profile_path)?;
+        std::fs::remove_dir_all(profile_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_txs_mempool_policy() -> Result<()> {
+        let profile_path =
+            std::env::temp_dir().join(format!("duniter-cli-policy-{}", std::process::id()));
+        std::fs::create_dir_all(&profile_path)?;
+        assert_eq!(
+            load_txs_mempool_policy(&profile_path)?,
+            TxsMempoolPolicy::default()
+        );
+
+        std::fs::write(
+            profile_path.join(CONF_FILE),
+            r#"{"txsMempoolEviction":"oldest","txsMempoolMaxPerIssuer":5}"#,
+        )?;
+        assert_eq!(
+            load_txs_mempool_policy(&profile_path)?,
+            TxsMempoolPolicy {
+                eviction: TxsMempoolEviction::OldestFirst,
+                max_txs_per_issuer: Some(5),
+                members_priority: false,
+            }
+        );
+        assert_eq!(
+            load_fork_window_size(&profile_path)?,
+            DEFAULT_FORK_WINDOW_SIZE
+        );
+
+        std::fs::write(profile_path.join(CONF_FILE), r#"{"forksize":10}"#)?;
+        assert_eq!(load_fork_window_size(&profile_path)?, 10);
+        std::fs::remove_dir_all(profile_path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::wallet::prelude::SourceAmount;
+
+#[derive(StructOpt)]
+pub(crate) enum UdCommand {
+    /// List the UDs created between two blocks.
+    #[structopt(display_order(0))]
+    History {
+        /// First block number (defaults to genesis).
+        #[structopt(long)]
+        from: Option<u32>,
+        /// Last block number (defaults to current block).
+        #[structopt(long)]
+        to: Option<u32>,
+    },
+    /// Show the projected date and amount of the next UD.
+    #[structopt(display_order(1))]
+    Next,
+}
+
+impl UdCommand {
+    pub(crate) fn command(self, profile_path: &Path) -> Result<()> {
+        let server = rust_only::open_stopped_node(profile_path)?;
+        match self {
+            UdCommand::History { from, to } => {
+                for ud in server.get_ud_history(from.unwrap_or(0), to.unwrap_or(u32::MAX))? {
+                    println!(
+                        "#{} median_time={} amount={}",
+                        ud.block_number,
+                        ud.median_time,
+                        format_amount(ud.amount)
+                    );
+                }
+            }
+            UdCommand::Next => {
+                if let Some(projection) = server.get_next_ud_projection()? {
+                    println!("Next UD median time: {}", projection.time);
+                    println!(
+                        "Next UD amount: {}{}",
+                        format_amount(projection.amount),
+                        if projection.reevaluation {
+                            " (reevaluated)"
+                        } else {
+                            ""
+                        }
+                    );
+                } else {
+                    println!("No blockchain, please sync your node first.");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Amounts are in cents of unit
+fn format_amount(amount: SourceAmount) -> String {
+    let cents = amount.amount() * 10i64.pow(amount.base() as u32);
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
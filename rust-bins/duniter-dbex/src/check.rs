@@ -0,0 +1,426 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::dbs::{
+    databases::{bc_v1::BcV1DbReadable, bc_v2::BcV2DbReadable},
+    BlockMetaV2,
+};
+use duniter_gva_db::GvaV1DbReadable;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+const MAX_PRINTED_DIVERGENCES: usize = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+struct BcV1Block {
+    hash: String,
+    ud: Option<u64>,
+    txs_count: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct BcV2Block {
+    hash: String,
+    ud: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct BcV2Index {
+    blocks: BTreeMap<u32, BcV2Block>,
+    current: Option<BlockMetaV2>,
+    utxos_count: usize,
+}
+
+/// gva_v1 only indexes the blocks with an UD or with transactions
+#[derive(Clone, Debug, Default)]
+struct GvaBlocks {
+    with_ud: BTreeSet<u32>,
+    txs_count: BTreeMap<u32, usize>,
+    utxos_count: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Divergence {
+    number: u32,
+    db: &'static str,
+    problem: String,
+}
+
+pub(crate) fn check(profile_path: PathBuf, repair: bool) -> anyhow::Result<()> {
+    let start_time = Instant::now();
+
+    let (bc_v1, bc_v2, gva_v1) = read_dbs(&profile_path)?;
+    let divergences = compare(&bc_v1, &bc_v2, &gva_v1);
+
+    let duration = start_time.elapsed();
+    println!(
+        "{} blocks checked in {}.{:06} seconds.",
+        bc_v1.len(),
+        duration.as_secs(),
+        duration.subsec_micros()
+    );
+    if divergences.is_empty() {
+        println!("bc_v1, bc_v2 and gva_v1 agree.");
+        return Ok(());
+    }
+    print_divergences(&divergences);
+
+    if !repair {
+        return Err(anyhow!(
+            "{} divergences from block #{}, run with --repair to reindex the rust dbs",
+            divergences.len(),
+            divergences[0].number
+        ));
+    }
+
+    // The rust dbs can't be truncated: if they only miss the last blocks of bc_v1, these
+    // blocks are applied, otherwise the rust dbs are rebuilt from bc_v1
+    let bc_v2_next = bc_v2
+        .blocks
+        .keys()
+        .next_back()
+        .map_or(0, |number| number + 1);
+    let gva_v1_next = gva_v1
+        .with_ud
+        .iter()
+        .next_back()
+        .max(gva_v1.txs_count.keys().next_back())
+        .map_or(0, |number| number + 1);
+    match bc_v2.current {
+        Some(current)
+            if gva_v1_next <= bc_v2_next
+                && divergences
+                    .iter()
+                    .all(|divergence| divergence.number >= bc_v2_next) =>
+        {
+            println!("Reindex blocks from #{}...", bc_v2_next);
+            migrate::reindex_tail(profile_path.clone(), current)?;
+        }
+        _ => {
+            println!("Rebuild the rust dbs from bc_v1...");
+            migrate::migrate(profile_path.clone())?;
+        }
+    }
+
+    check(profile_path, false)
+}
+
+/// Walk the three dbs in parallel, they are closed on return
+fn read_dbs(
+    profile_path: &Path,
+) -> anyhow::Result<(BTreeMap<u32, BcV1Block>, BcV2Index, GvaBlocks)> {
+    let bc_v1 = BcV1Db::<LevelDb>::open(LevelDbConf {
+        db_path: profile_path.join(DATA_DIR).join("leveldb"),
+        ..Default::default()
+    })?;
+    let bc_v2 = BcV2Db::<Sled>::open(Sled::gen_backend_conf(
+        BcV2Db::<Sled>::NAME,
+        Some(profile_path),
+    ))?;
+    let gva_v1 = GvaV1Db::<Sled>::open(Sled::gen_backend_conf(
+        GvaV1Db::<Sled>::NAME,
+        Some(profile_path),
+    ))?;
+
+    let bc_v1_handle = std::thread::spawn(move || read_bc_v1(&bc_v1));
+    let bc_v2_handle = std::thread::spawn(move || read_bc_v2(&bc_v2));
+    let gva_v1_blocks = read_gva_v1(&gva_v1)?;
+    let bc_v1_blocks = bc_v1_handle
+        .join()
+        .map_err(|_| anyhow!("bc_v1 reader panic"))??;
+    let bc_v2_index = bc_v2_handle
+        .join()
+        .map_err(|_| anyhow!("bc_v2 reader panic"))??;
+
+    Ok((bc_v1_blocks, bc_v2_index, gva_v1_blocks))
+}
+
+fn read_bc_v1(bc_v1: &BcV1Db<LevelDb>) -> KvResult<BTreeMap<u32, BcV1Block>> {
+    bc_v1.main_blocks().iter(.., |it| {
+        it.values()
+            .map(|db_block_res| {
+                db_block_res.map(|db_block| {
+                    (
+                        db_block.number as u32,
+                        BcV1Block {
+                            hash: db_block.hash,
+                            ud: db_block.dividend.map(|ud| ud as u64),
+                            txs_count: db_block.transactions.len(),
+                        },
+                    )
+                })
+            })
+            .collect::<KvResult<_>>()
+    })
+}
+
+fn read_bc_v2(bc_v2: &BcV2Db<Sled>) -> KvResult<BcV2Index> {
+    let blocks = bc_v2.blocks_meta().iter(.., |it| {
+        it.values()
+            .map(|block_meta_res| {
+                block_meta_res.map(|block_meta| {
+                    (
+                        block_meta.number,
+                        BcV2Block {
+                            hash: block_meta.hash.to_hex(),
+                            ud: block_meta.dividend.map(|ud| ud.amount() as u64),
+                        },
+                    )
+                })
+            })
+            .collect::<KvResult<_>>()
+    })?;
+    let current = bc_v2
+        .blocks_meta()
+        .iter_rev(.., |it| it.values().next_res())?;
+    Ok(BcV2Index {
+        blocks,
+        current,
+        utxos_count: bc_v2.utxos().count()?,
+    })
+}
+
+fn read_gva_v1(gva_v1: &GvaV1Db<Sled>) -> KvResult<GvaBlocks> {
+    Ok(GvaBlocks {
+        with_ud: gva_v1.blocks_with_ud().iter(.., |it| {
+            it.keys()
+                .map(|number_res| number_res.map(|U32BE(number)| number))
+                .collect::<KvResult<_>>()
+        })?,
+        txs_count: gva_v1.txs_by_block().iter(.., |it| {
+            it.map(|entry_res| entry_res.map(|(U32BE(number), txs)| (number, txs.len())))
+                .collect::<KvResult<_>>()
+        })?,
+        utxos_count: gva_v1.gva_utxos().count()?,
+    })
+}
+
+/// bc_v1 is the reference: the rust dbs are indexed from its blocks
+fn compare(
+    bc_v1: &BTreeMap<u32, BcV1Block>,
+    bc_v2: &BcV2Index,
+    gva_v1: &GvaBlocks,
+) -> Vec<Divergence> {
+    let mut divergences = Vec::new();
+    let mut diverge = |number, db, problem| {
+        divergences.push(Divergence {
+            number,
+            db,
+            problem,
+        })
+    };
+
+    for (&number, block) in bc_v1 {
+        match bc_v2.blocks.get(&number) {
+            None => diverge(number, "bc_v2", "missing block".to_owned()),
+            Some(block_v2) => {
+                if !block_v2.hash.eq_ignore_ascii_case(&block.hash) {
+                    diverge(
+                        number,
+                        "bc_v2",
+                        format!("hash {} instead of {}", block_v2.hash, block.hash),
+                    );
+                }
+                if block_v2.ud != block.ud {
+                    diverge(
+                        number,
+                        "bc_v2",
+                        format!("UD {:?} instead of {:?}", block_v2.ud, block.ud),
+                    );
+                }
+            }
+        }
+        match (gva_v1.with_ud.contains(&number), block.ud.is_some()) {
+            (true, false) => diverge(number, "gva_v1", "unexpected UD".to_owned()),
+            (false, true) => diverge(number, "gva_v1", "missing UD".to_owned()),
+            _ => (),
+        }
+        let gva_txs_count = gva_v1.txs_count.get(&number).copied().unwrap_or_default();
+        if gva_txs_count != block.txs_count {
+            diverge(
+                number,
+                "gva_v1",
+                format!(
+                    "{} transactions instead of {}",
+                    gva_txs_count, block.txs_count
+                ),
+            );
+        }
+    }
+
+    // Sources are reported at the current block of bc_v2. gva_v1 reads the UDs of bc_v2 and
+    // only indexes the blocks creating them
+    let bc_v2_current = bc_v2.blocks.keys().next_back().copied().unwrap_or_default();
+    if gva_v1.utxos_count != bc_v2.utxos_count {
+        diverge(
+            bc_v2_current,
+            "gva_v1",
+            format!(
+                "{} UTXOs instead of {}",
+                gva_v1.utxos_count, bc_v2.utxos_count
+            ),
+        );
+    }
+    let bc_v2_ud_blocks = bc_v2
+        .blocks
+        .values()
+        .filter(|block_v2| block_v2.ud.is_some())
+        .count();
+    if gva_v1.with_ud.len() != bc_v2_ud_blocks {
+        diverge(
+            bc_v2_current,
+            "gva_v1",
+            format!(
+                "{} blocks with UD instead of {}",
+                gva_v1.with_ud.len(),
+                bc_v2_ud_blocks
+            ),
+        );
+    }
+
+    // Blocks unknown to bc_v1
+    for &number in bc_v2
+        .blocks
+        .keys()
+        .filter(|number| !bc_v1.contains_key(number))
+    {
+        diverge(number, "bc_v2", "block not in bc_v1".to_owned());
+    }
+    for &number in gva_v1
+        .with_ud
+        .iter()
+        .chain(gva_v1.txs_count.keys())
+        .filter(|number| !bc_v1.contains_key(number))
+        .collect::<BTreeSet<_>>()
+    {
+        diverge(number, "gva_v1", "block not in bc_v1".to_owned());
+    }
+
+    divergences.sort_by_key(|divergence| divergence.number);
+    divergences
+}
+
+fn print_divergences(divergences: &[Divergence]) {
+    let mut table = Table::new();
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+    table.set_header(["Block", "Database", "Problem"]);
+    for divergence in divergences.iter().take(MAX_PRINTED_DIVERGENCES) {
+        table.add_row([
+            divergence.number.to_string(),
+            divergence.db.to_owned(),
+            divergence.problem.clone(),
+        ]);
+    }
+    println!("{}", table);
+    if divergences.len() > MAX_PRINTED_DIVERGENCES {
+        println!(
+            "... and {} more divergences.",
+            divergences.len() - MAX_PRINTED_DIVERGENCES
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bc_v1_block(hash: &str, ud: Option<u64>, txs_count: usize) -> BcV1Block {
+        BcV1Block {
+            hash: hash.to_owned(),
+            ud,
+            txs_count,
+        }
+    }
+
+    fn bc_v2_block(hash: &str, ud: Option<u64>) -> BcV2Block {
+        BcV2Block {
+            hash: hash.to_owned(),
+            ud,
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut bc_v1 = BTreeMap::new();
+        bc_v1.insert(0, bc_v1_block("AA", None, 0));
+        bc_v1.insert(1, bc_v1_block("BB", Some(1000), 2));
+        let mut bc_v2 = BcV2Index::default();
+        bc_v2.blocks.insert(0, bc_v2_block("aa", None));
+        bc_v2.blocks.insert(1, bc_v2_block("bb", Some(1000)));
+        bc_v2.utxos_count = 3;
+        let mut gva_v1 = GvaBlocks::default();
+        gva_v1.with_ud.insert(1);
+        gva_v1.txs_count.insert(1, 2);
+        gva_v1.utxos_count = 3;
+        assert_eq!(compare(&bc_v1, &bc_v2, &gva_v1), vec![]);
+
+        // bc_v1 is ahead, bc_v2 has a fork and gva_v1 an extra block
+        bc_v1.insert(2, bc_v1_block("CC", None, 0));
+        bc_v2.blocks.insert(1, bc_v2_block("DD", Some(1000)));
+        gva_v1.txs_count.insert(3, 1);
+        assert_eq!(
+            compare(&bc_v1, &bc_v2, &gva_v1),
+            vec![
+                Divergence {
+                    number: 1,
+                    db: "bc_v2",
+                    problem: "hash DD instead of BB".to_owned(),
+                },
+                Divergence {
+                    number: 2,
+                    db: "bc_v2",
+                    problem: "missing block".to_owned(),
+                },
+                Divergence {
+                    number: 3,
+                    db: "gva_v1",
+                    problem: "block not in bc_v1".to_owned(),
+                },
+            ]
+        );
+
+        // gva_v1 lost an UTXO and the UD of block #1
+        let mut bc_v1 = BTreeMap::new();
+        bc_v1.insert(0, bc_v1_block("AA", None, 0));
+        bc_v1.insert(1, bc_v1_block("BB", Some(1000), 2));
+        bc_v2.blocks.insert(1, bc_v2_block("bb", Some(1000)));
+        gva_v1.with_ud.clear();
+        gva_v1.txs_count.remove(&3);
+        gva_v1.utxos_count = 2;
+        assert_eq!(
+            compare(&bc_v1, &bc_v2, &gva_v1),
+            vec![
+                Divergence {
+                    number: 1,
+                    db: "gva_v1",
+                    problem: "missing UD".to_owned(),
+                },
+                Divergence {
+                    number: 1,
+                    db: "gva_v1",
+                    problem: "2 UTXOs instead of 3".to_owned(),
+                },
+                Divergence {
+                    number: 1,
+                    db: "gva_v1",
+                    problem: "0 blocks with UD instead of 1".to_owned(),
+                },
+            ]
+        );
+    }
+}
@@ -28,7 +28,7 @@ pub struct Opt {
     pub home: Option<PathBuf>,
 
     /// database
-    #[structopt(default_value = "bc_v1", possible_values = &["bc_v1", "bc_v2", "dunp_v1", "gva_v1", "txs_mp_v2"])]
+    #[structopt(default_value = "bc_v1", possible_values = &["bc_v1", "bc_v2", "dunp_v1", "gva_v1", "txs_mp_v2", "wot_v1"])]
     pub database: Database,
 
     #[structopt(subcommand)]
@@ -42,6 +42,7 @@ pub enum Database {
     NetworkV1,
     GvaV1,
     TxsMpV2,
+    WotV1,
 }
 
 impl FromStr for Database {
@@ -54,6 +55,7 @@ impl FromStr for Database {
             "dunp_v1" => Ok(Self::NetworkV1),
             "gva_v1" => Ok(Self::GvaV1),
             "txs_mp_v2" => Ok(Self::TxsMpV2),
+            "wot_v1" => Ok(Self::WotV1),
             _ => unreachable!(),
         }
     }
@@ -121,6 +123,12 @@ pub enum SubCommand {
     Schema,
     /// Fill rust dbs from js db content
     Migrate,
+    /// Check that bc_v1, bc_v2 and gva_v1 agree on blocks hashes, UDs and transactions
+    Check {
+        /// Reindex the rust dbs from the first divergence
+        #[structopt(long)]
+        repair: bool,
+    },
 }
 
 #[derive(Clone, Copy, Debug)]
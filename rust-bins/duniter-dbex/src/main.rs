@@ -22,6 +22,7 @@
     unused_import_braces
 )]
 
+mod check;
 mod cli;
 mod export_bc;
 mod migrate;
@@ -44,6 +45,7 @@ use duniter_core::dbs::regex::Regex;
 use duniter_core::dbs::serde_json::{Map, Value};
 use duniter_core::dbs::smallvec::{smallvec, SmallVec};
 use duniter_gva_db::{GvaV1Db, GvaV1DbWritable};
+use duniter_server::WotV1Db;
 use rayon::prelude::*;
 use std::{
     collections::{HashMap, HashSet},
@@ -88,6 +90,7 @@ fn main() -> anyhow::Result<()> {
 
     match opt.cmd {
         SubCommand::Migrate => migrate::migrate(profile_path),
+        SubCommand::Check { repair } => check::check(profile_path, repair),
         SubCommand::ExportBc {
             chunk_size,
             output_dir,
@@ -144,6 +147,14 @@ fn main() -> anyhow::Result<()> {
                     opt.cmd,
                     open_db_start_time,
                 ),
+                Database::WotV1 => apply_subcommand(
+                    WotV1Db::<Sled>::open(Sled::gen_backend_conf(
+                        WotV1Db::<Sled>::NAME,
+                        Some(profile_path.as_path()),
+                    ))?,
+                    opt.cmd,
+                    open_db_start_time,
+                ),
             }
         }
     }
@@ -14,7 +14,10 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::*;
-use duniter_core::dbs::{databases::bc_v1::BcV1DbReadable, FileBackend};
+use duniter_core::dbs::{
+    databases::{bc_v1::BcV1DbReadable, bc_v2::BcV2DbReadable},
+    BlockMetaV2, BlockNumberKeyV1, FileBackend,
+};
 use duniter_core::{
     block::parser::parse_json_block_from_serde_value, block::parser::ParseJsonBlockError,
     block::prelude::DubpBlockTrait, block::DubpBlock, common::prelude::BlockNumber,
@@ -35,7 +38,7 @@ pub(crate) fn migrate(profile_path: PathBuf) -> anyhow::Result<()> {
     let (bc_db, shared_dbs) = duniter_core::dbs::open_dbs(Some(profile_path.as_path()))?;
     let gva_db = duniter_gva_indexer::get_gva_db_rw(Some(profile_path.as_path()));
 
-    if let Err(e) = migrate_inner(&bc_db, gva_db, profile_path, shared_dbs, start_time) {
+    if let Err(e) = migrate_inner(&bc_db, gva_db, profile_path, shared_dbs, None, start_time) {
         // Clear bc_db and gva_db
         bc_db.clear()?;
         gva_db.clear()?;
@@ -46,11 +49,29 @@ pub(crate) fn migrate(profile_path: PathBuf) -> anyhow::Result<()> {
     }
 }
 
+/// Apply the blocks of the js db following `current` to bc_db and gva_db
+pub(crate) fn reindex_tail(profile_path: PathBuf, current: BlockMetaV2) -> anyhow::Result<()> {
+    let start_time = Instant::now();
+
+    let (bc_db, shared_dbs) = duniter_core::dbs::open_dbs(Some(profile_path.as_path()))?;
+    let gva_db = duniter_gva_indexer::get_gva_db_rw(Some(profile_path.as_path()));
+
+    migrate_inner(
+        &bc_db,
+        gva_db,
+        profile_path,
+        shared_dbs,
+        Some(current),
+        start_time,
+    )
+}
+
 fn migrate_inner(
     bc_db: &BcV2Db<FileBackend>,
     gva_db: &'static GvaV1Db<FileBackend>,
     profile_path: PathBuf,
     shared_dbs: SharedDbs<FileBackend>,
+    mut current: Option<BlockMetaV2>,
     start_time: Instant,
 ) -> anyhow::Result<()> {
     let data_path = profile_path.join(crate::DATA_DIR);
@@ -64,9 +85,11 @@ fn migrate_inner(
     if let Some(target) = get_target_block_number(&duniter_js_db)? {
         println!("target block: #{}", target.0);
 
+        let first_block =
+            BlockNumberKeyV1(BlockNumber(current.map_or(0, |current| current.number + 1)));
         let (s, r) = flume::unbounded();
         let reader_handle = std::thread::spawn(move || {
-            duniter_js_db.main_blocks().iter(.., |it| {
+            duniter_js_db.main_blocks().iter(first_block.., |it| {
                 it.values().try_for_each(|block_res| {
                     s.send(block_res).map_err(|_| anyhow!("fail to send"))
                 })
@@ -102,8 +125,7 @@ fn migrate_inner(
             Ok::<(), anyhow::Error>(())
         });
 
-        let mut current = None;
-        let mut currency_params = Default::default();
+        let mut currency_params = bc_db.currency_params().get(&())?.unwrap_or_default().params;
         while let Ok(chunk) = r2.recv() {
             if !chunk.is_empty() {
                 println!(
@@ -0,0 +1,385 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::crypto::keys::PublicKey as _;
+use duniter_core::documents::transaction::{SourceIdV10, TransactionDocumentTrait};
+use std::collections::HashSet;
+
+/// DUBP: a block can always contain 500 lines. Bigger blocks depend on the average size of
+/// the last blocks, which is not indexed by the server.
+const MAX_BLOCK_LINES: usize = 500;
+/// Transactions bigger than this are never included in a block
+const MAX_TX_LINES: usize = 100;
+
+impl DuniterServer {
+    /// Next block issued by `issuer` at `time`, with the pending WoT documents and transactions
+    /// that can be written in it. The block is neither signed nor proved: its nonce is 0 and
+    /// its signature and hash are empty.
+    pub fn generate_block_candidate(
+        &self,
+        issuer: PublicKey,
+        time: u64,
+    ) -> DuniterServerResult<DubpBlockV10Stringified> {
+        let current = self.current.ok_or_else(|| {
+            DuniterServerError::Validation(
+                "no blockchain, the genesis block must be built from its initial members"
+                    .to_owned(),
+            )
+        })?;
+
+        let median_time = self.next_median_time(&current)?;
+        let pow_min = self.next_pow_min(&current, median_time)?;
+        let issuers_count = self.next_issuers_count(&current)?;
+        let (issuers_frame, issuers_frame_var) = crate::difficulty::next_issuers_frame(
+            current.issuers_frame,
+            current.issuers_frame_var,
+            u64::from(current.issuers_count),
+            issuers_count,
+        );
+
+        let wot = self.select_pending_wot(median_time)?;
+        let members_count =
+            current.members_count + wot.joiners.len() as u64 - wot.excluded.len() as u64;
+        let dividend_opt = self
+            .get_next_ud_projection()?
+            .filter(|projection| median_time >= projection.time)
+            .map(|projection| projection.amount);
+        let (dividend, unit_base, monetary_mass) = if let Some(dividend) = dividend_opt {
+            (
+                Some(dividend.amount() as u64),
+                dividend.base() as u64,
+                current.monetary_mass
+                    + dividend.amount() as u64 * 10u64.pow(dividend.base() as u32) * members_count,
+            )
+        } else {
+            (None, u64::from(current.unit_base), current.monetary_mass)
+        };
+
+        // Transactions fill the space left by the WoT documents. Pending transactions come
+        // after the transactions they spend: a transaction that doesn't fit is skipped with
+        // its descendants, the following ones may still fit.
+        let mut lines_count = wot.lines_count();
+        let mut transactions = Vec::new();
+        let mut skipped_txs = HashSet::new();
+        for pending_tx in self.get_pending_txs(Some(median_time as i64), 10)? {
+            let tx_lines = crate::raw_block::compact_tx_lines(&pending_tx.doc);
+            let spends_skipped_tx =
+                pending_tx
+                    .doc
+                    .get_inputs()
+                    .iter()
+                    .any(|input| match input.id {
+                        SourceIdV10::Utxo(utxo_id) => skipped_txs.contains(&utxo_id.tx_hash),
+                        SourceIdV10::Ud(_) => false,
+                    });
+            if spends_skipped_tx
+                || tx_lines > MAX_TX_LINES
+                || lines_count + tx_lines >= MAX_BLOCK_LINES
+            {
+                skipped_txs.insert(pending_tx.doc.get_hash());
+                continue;
+            }
+            lines_count += tx_lines;
+            transactions.push(pending_tx.doc.to_string_object());
+        }
+
+        let mut block = DubpBlockV10Stringified {
+            version: 10,
+            number: u64::from(current.number) + 1,
+            currency: self.currency.clone(),
+            pow_min,
+            time: time.max(median_time),
+            median_time,
+            dividend,
+            unit_base,
+            issuer: issuer.to_base58(),
+            issuers_frame,
+            issuers_frame_var,
+            issuers_count,
+            previous_hash: Some(current.hash.to_hex()),
+            previous_issuer: Some(current.issuer.to_base58()),
+            members_count,
+            monetary_mass,
+            identities: wot.identities,
+            joiners: wot.joiners,
+            actives: wot.actives,
+            leavers: wot.leavers,
+            certifications: wot.certifications,
+            revoked: wot.revoked,
+            excluded: wot.excluded,
+            transactions,
+            ..Default::default()
+        };
+        block.inner_hash = Some(crate::raw_block::inner_hash(&block)?.to_hex());
+        Ok(block)
+    }
+    /// DUBP: the median time of the block following `current` is the average time of the
+    /// last `median_time_blocks` blocks, it never goes back
+    pub(crate) fn next_median_time(&self, current: &BlockMetaV2) -> KvResult<u64> {
+        let count = self
+            .currency_params
+            .median_time_blocks
+            .min(current.number as usize + 1);
+        let times = self.bc_db.blocks_meta().iter_rev(.., |it| {
+            it.values()
+                .map_ok(|block_meta| block_meta.time)
+                .take(count)
+                .collect::<KvResult<Vec<u64>>>()
+        })?;
+        Ok(median_time(current.median_time, &times))
+    }
+}
+
+fn median_time(previous_median_time: u64, times: &[u64]) -> u64 {
+    if times.is_empty() {
+        previous_median_time
+    } else {
+        (times.iter().sum::<u64>() / times.len() as u64).max(previous_median_time)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::genesis::tests::{builder, keypair};
+    use duniter_core::crypto::keys::{ed25519::Ed25519KeyPair, KeyPair as _};
+
+    /// Sign `block` with `keypair` and prove it at the personalized difficulty of `keypair`
+    /// on top of the current block of `server`
+    pub(crate) fn prove(
+        server: &DuniterServer,
+        block: DubpBlockV10Stringified,
+        keypair: &Ed25519KeyPair,
+    ) -> anyhow::Result<DubpBlockV10Stringified> {
+        let difficulty = server
+            .get_personalized_difficulty(keypair.public_key())?
+            .map_or(block.pow_min, |personalized| personalized.difficulty);
+        let pow_conf = PowConf {
+            cpu: 1.0,
+            nb_cores: 1,
+            prefix: 0,
+        };
+        prove_block(block, keypair, difficulty as usize, pow_conf)?
+            .wait()
+            .ok_or_else(|| anyhow::anyhow!("proof cancelled"))
+    }
+
+    /// Next valid block of `server`, issued by `keypair` at `time`
+    pub(crate) fn forge_block(
+        server: &DuniterServer,
+        keypair: &Ed25519KeyPair,
+        time: u64,
+    ) -> anyhow::Result<DubpBlockV10Stringified> {
+        prove(
+            server,
+            server.generate_block_candidate(keypair.public_key(), time)?,
+            keypair,
+        )
+    }
+
+    #[test]
+    fn test_median_time() {
+        assert_eq!(median_time(100, &[]), 100);
+        assert_eq!(median_time(100, &[110, 121]), 115);
+        // The median time never goes back
+        assert_eq!(median_time(130, &[110, 121]), 130);
+    }
+
+    /// Recorded Ğ1 blocks from #0, as returned by the BMA request `blockchain/blocks/<count>/0`
+    /// of a Ğ1 node
+    const G1_BLOCKS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/g1_blocks.json");
+
+    /// The recorded Ğ1 blocks are applied but the last one, which is generated again from the
+    /// documents it contains
+    #[test]
+    #[ignore = "needs the recorded Ğ1 blocks of tests/data/g1_blocks.json"]
+    fn test_generate_recorded_g1_block() -> anyhow::Result<()> {
+        use duniter_core::block::{parser::parse_json_block_from_serde_value, DubpBlock};
+
+        let json_blocks: Vec<serde_json::Value> =
+            serde_json::from_reader(std::fs::File::open(G1_BLOCKS_PATH)?)?;
+        let mut blocks = Vec::with_capacity(json_blocks.len());
+        for json_block in &json_blocks {
+            match parse_json_block_from_serde_value(json_block)? {
+                DubpBlock::V10(block) => blocks.push(block.to_string_object()),
+            }
+        }
+        let (recorded, previous_blocks) = blocks
+            .split_last()
+            .ok_or_else(|| anyhow::anyhow!("no recorded block"))?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        for block in previous_blocks {
+            server.apply_block(block.clone())?;
+        }
+        for idty in &recorded.identities {
+            server.add_pending_identity(idty.clone())?;
+        }
+        for ms in recorded.joiners.iter().chain(recorded.actives.iter()) {
+            server.add_pending_membership(ms.clone(), true)?;
+        }
+        for ms in &recorded.leavers {
+            server.add_pending_membership(ms.clone(), false)?;
+        }
+        for cert in &recorded.certifications {
+            server.add_pending_cert(cert.clone())?;
+        }
+        for revoc in &recorded.revoked {
+            server.add_pending_revocation(revoc.clone())?;
+        }
+
+        let issuer = PublicKey::from_base58(&recorded.issuer)?;
+        let candidate = server.generate_block_candidate(issuer, recorded.time)?;
+        assert_eq!(candidate.number, recorded.number);
+        assert_eq!(candidate.previous_hash, recorded.previous_hash);
+        assert_eq!(candidate.median_time, recorded.median_time);
+        assert_eq!(candidate.pow_min, recorded.pow_min);
+        assert_eq!(candidate.issuers_count, recorded.issuers_count);
+        assert_eq!(candidate.issuers_frame, recorded.issuers_frame);
+        assert_eq!(candidate.issuers_frame_var, recorded.issuers_frame_var);
+        assert_eq!(candidate.dividend, recorded.dividend);
+        assert_eq!(candidate.unit_base, recorded.unit_base);
+        assert_eq!(candidate.members_count, recorded.members_count);
+        assert_eq!(candidate.monetary_mass, recorded.monetary_mass);
+
+        Ok(())
+    }
+
+    /// Ğ1 parameters and genesis time, on a genesis block built from test keys
+    #[test]
+    fn test_generate_g1_block_candidates() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=6).map(keypair).collect();
+        let mut builder = builder(&keypairs);
+        builder.currency_params = crate::currency::parse_genesis_parameters(
+            "test",
+            "0.0488:86400:1000:432000:100:5259600:63115200:5:5259600:5259600:0.8:31557600:5:24:300:12:0.67:1488970800:1490094000:15778800",
+        ).map_err(anyhow::Error::msg)?;
+        builder.time = 1_488_987_127;
+        let genesis = builder.build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(genesis.clone())?;
+
+        // First UD, the genesis is after udTime0
+        let block1 =
+            server.generate_block_candidate(keypairs[0].public_key(), genesis.time + 300)?;
+        assert_eq!(block1.number, 1);
+        assert_eq!(block1.time, genesis.time + 300);
+        assert_eq!(block1.median_time, genesis.time);
+        assert_eq!(block1.pow_min, 0);
+        assert_eq!(block1.issuers_count, 1);
+        assert_eq!(block1.issuers_frame, 1);
+        assert_eq!(block1.issuers_frame_var, 5);
+        assert_eq!(block1.dividend, Some(1000));
+        assert_eq!(block1.unit_base, 0);
+        assert_eq!(block1.members_count, 6);
+        assert_eq!(block1.monetary_mass, 6000);
+        assert_eq!(block1.previous_hash, genesis.hash);
+        assert_eq!(block1.previous_issuer, Some(genesis.issuer.clone()));
+        let block1 = prove(&server, block1, &keypairs[0])?;
+        assert_eq!(server.validate_block(&block1), Ok(()));
+        server.apply_block(block1)?;
+
+        // The frame grows by one block, the next UD is one day after udTime0
+        let block2 =
+            server.generate_block_candidate(keypairs[1].public_key(), genesis.time + 600)?;
+        assert_eq!(block2.number, 2);
+        assert_eq!(block2.median_time, genesis.time + 150);
+        assert_eq!(block2.issuers_count, 1);
+        assert_eq!(block2.issuers_frame, 2);
+        assert_eq!(block2.issuers_frame_var, 4);
+        assert_eq!(block2.dividend, None);
+        assert_eq!(block2.monetary_mass, 6000);
+        let block2 = prove(&server, block2, &keypairs[1])?;
+        assert_eq!(server.validate_block(&block2), Ok(()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_block_candidate_with_exclusions() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let mut builder = builder(&keypairs);
+        // Memberships valid 600 seconds
+        builder.currency_params = crate::currency::parse_genesis_parameters(
+            "test",
+            "0.0488:86400:1000:432000:100:5259600:63115200:2:5259600:5259600:0.8:600:5:24:300:12:0.67:1600000000:1600000000:15778800",
+        ).map_err(anyhow::Error::msg)?;
+        let genesis = builder.build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(genesis.clone())?;
+        let block1 = forge_block(&server, &keypairs[0], genesis.time + 2000)?;
+        server.apply_block(block1)?;
+
+        // Members 0 and 1 renew their membership at median time genesis + 1000
+        for member in &builder.members[..2] {
+            server.add_pending_membership(crate::wot_mempool::tests::compact_join(member), true)?;
+        }
+        let block2 = forge_block(&server, &keypairs[1], genesis.time + 2300)?;
+        assert_eq!(block2.median_time, genesis.time + 1000);
+        assert_eq!(block2.actives.len(), 2);
+        assert!(block2.excluded.is_empty());
+        server.apply_block(block2)?;
+
+        // The membership of member 2 is expired at the median time of block #2
+        let block3 =
+            server.generate_block_candidate(keypairs[0].public_key(), genesis.time + 2600)?;
+        assert_eq!(block3.excluded, vec![keypairs[2].public_key().to_base58()]);
+        assert_eq!(block3.members_count, 2);
+        let block3 = prove(&server, block3, &keypairs[0])?;
+        assert_eq!(server.validate_block(&block3), Ok(()));
+        let mut without_exclusion = block3.clone();
+        without_exclusion.excluded.clear();
+        without_exclusion.members_count = 3;
+        assert!(server
+            .validate_block(&without_exclusion)
+            .err()
+            .unwrap_or_default()
+            .iter()
+            .any(|violation| violation.rule == "TO_BE_KICKED"));
+        server.apply_block(block3)?;
+
+        let block4 =
+            server.generate_block_candidate(keypairs[1].public_key(), genesis.time + 2900)?;
+        assert!(block4.excluded.is_empty());
+        assert_eq!(block4.members_count, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_block_candidate() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        assert!(server
+            .generate_block_candidate(keypairs[1].public_key(), genesis.time)
+            .is_err());
+        server.apply_block(genesis.clone())?;
+
+        for (i, keypair) in keypairs.iter().enumerate() {
+            let time = genesis.time + 300 * (i as u64 + 1);
+            let block = server.generate_block_candidate(keypair.public_key(), time)?;
+            assert_eq!(block.number, i as u64 + 1);
+            assert_eq!(block.time, time);
+            assert_eq!(block.members_count, 3);
+
+            let block = prove(&server, block, keypair)?;
+            assert_eq!(server.validate_block(&block), Ok(()));
+            server.apply_block(block)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,460 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Write-ahead journal of the blocks being applied or reverted.
+//!
+//! A block is written in bc_v2 then in the wot index and the dbs of the modules, by separate
+//! transactions. The block is journaled before the first one and the journal is cleared after
+//! the last one, so that a node stopped in between can finish the job on restart. A chunk of
+//! applied or reverted blocks is journaled as a whole, it is finished as a whole.
+//!
+//! The journal also records the current block of the modules dbs when the operation begins: a
+//! node stopped after the modules but before the journal is cleared must not apply or revert
+//! the blocks twice in the modules.
+
+use crate::*;
+use duniter_core::dbs::U32BE;
+use duniter_gva_db::GvaV1DbReadable;
+use serde::{Deserialize, Serialize};
+use std::io::Write as _;
+
+const BLOCK_JOURNAL_FILE: &str = "block_journal.json";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) enum BlockJournalOp {
+    Apply,
+    Revert,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct BlockJournalEntry {
+    pub(crate) op: BlockJournalOp,
+    /// Blocks in the order of the operation: reverted blocks from the highest one
+    pub(crate) blocks: Vec<DubpBlockV10Stringified>,
+    /// Number of the current block of the modules dbs before the operation
+    #[serde(default)]
+    pub(crate) modules_current: Option<u32>,
+}
+
+/// Journal file in the profile directory. Servers without profile keep their dbs in memory,
+/// they have nothing to recover and journal nothing.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BlockJournal {
+    path_opt: Option<PathBuf>,
+}
+
+impl BlockJournal {
+    pub(crate) fn new(profile_path_opt: Option<&Path>) -> Self {
+        BlockJournal {
+            path_opt: profile_path_opt.map(|profile_path| profile_path.join(BLOCK_JOURNAL_FILE)),
+        }
+    }
+    /// Record `op` on `block`, durably, before writing anything in the dbs
+    pub(crate) fn begin(
+        &self,
+        op: BlockJournalOp,
+        block: &DubpBlockV10,
+        modules_current: Option<u32>,
+    ) -> std::io::Result<()> {
+        self.begin_chunk(op, std::slice::from_ref(block), modules_current)
+    }
+    pub(crate) fn begin_chunk(
+        &self,
+        op: BlockJournalOp,
+        blocks: &[DubpBlockV10],
+        modules_current: Option<u32>,
+    ) -> std::io::Result<()> {
+        if let Some(ref path) = self.path_opt {
+            let entry = BlockJournalEntry {
+                op,
+                blocks: blocks
+                    .iter()
+                    .map(|block| block.to_string_object())
+                    .collect(),
+                modules_current,
+            };
+            // Write then rename, a journal is never read half-written
+            let tmp_path = path.with_extension("tmp");
+            let mut file = std::fs::File::create(&tmp_path)?;
+            serde_json::to_writer(&mut file, &entry)?;
+            file.flush()?;
+            file.sync_all()?;
+            std::fs::rename(tmp_path, path)?;
+        }
+        Ok(())
+    }
+    /// All the dbs contain the journaled operation
+    pub(crate) fn end(&self) -> std::io::Result<()> {
+        match self.path_opt {
+            Some(ref path) if path.exists() => std::fs::remove_file(path),
+            _ => Ok(()),
+        }
+    }
+    pub(crate) fn read(&self) -> std::io::Result<Option<BlockJournalEntry>> {
+        match self.path_opt {
+            Some(ref path) if path.exists() => {
+                let file = std::fs::File::open(path)?;
+                Ok(Some(serde_json::from_reader(file)?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl DuniterServer {
+    /// Number of the last block applied in the modules dbs, according to the blockchain time
+    /// indexed by gva
+    pub(crate) fn modules_current(&self) -> KvResult<Option<u32>> {
+        duniter_gva_indexer::get_gva_db_ro(self.profile_path_opt.as_deref())
+            .blockchain_time()
+            .iter_rev(.., |it| it.keys().map_ok(|U32BE(number)| number).next_res())
+    }
+    /// Finish the operation journaled by a node stopped in the middle of a block application
+    /// or revert, so that bc_v2 and the dbs of the modules agree again. It must run before the
+    /// modules start.
+    ///
+    /// If bc_v2 contains the operation, the modules replay the blocks they didn't process: an
+    /// applied chunk is in bc_v2 once its last block is the current one. Otherwise nothing was
+    /// written, the operation is dropped.
+    pub(crate) fn recover_block_journal(&mut self) -> DuniterServerResult<()> {
+        let entry = if let Some(entry) = self.block_journal.read()? {
+            entry
+        } else {
+            return Ok(());
+        };
+        let blocks = entry
+            .blocks
+            .iter()
+            .map(DubpBlockV10::from_string_object)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DuniterServerError::deser)?;
+        let (first, last) = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) => (first.blockstamp(), last.blockstamp()),
+            _ => return Ok(self.block_journal.end()?),
+        };
+        let is_current = |current: Option<BlockMetaV2>, blockstamp: Blockstamp| {
+            current.map_or(false, |current| {
+                current.number == blockstamp.number.0 && current.hash == blockstamp.hash.0
+            })
+        };
+        let modules_current = self.modules_current()?;
+
+        match entry.op {
+            BlockJournalOp::Apply if is_current(self.current, last) => {
+                // The wot index writes each block once, it is always replayed
+                for block in &blocks {
+                    crate::wot_index::apply_block(&self.wot_db, block)?;
+                    crate::wot_mempool::remove_written(&self.wot_mp_db, block)?;
+                }
+                let left = blocks_left_in_modules(
+                    BlockJournalOp::Apply,
+                    blocks,
+                    entry.modules_current,
+                    modules_current,
+                );
+                if left.is_empty() {
+                    log::info!("block #{} already in modules dbs", last.number);
+                }
+                for block in left {
+                    log::warn!(
+                        "block #{} missing in modules dbs, replay it",
+                        block.number()
+                    );
+                    apply_block_modules(
+                        Arc::new(block),
+                        Arc::new(self.conf.clone()),
+                        self.currency_params,
+                        &self.dbs_pool,
+                        self.profile_path_opt.clone(),
+                    )
+                    .map_err(DuniterServerError::module)?;
+                }
+            }
+            BlockJournalOp::Revert if !is_current(self.current, first) => {
+                // Finish the revert in bc_v2, then revert in the modules the blocks they still
+                // contain
+                let still_in_bc: Vec<DubpBlockV10> = blocks
+                    .iter()
+                    .skip_while(|block| !is_current(self.current, block.blockstamp()))
+                    .cloned()
+                    .collect();
+                self.revert_blocks_in_bc(Arc::from(still_in_bc))?;
+                if self.current.map_or(0, |current| current.number + 1) == last.number.0 {
+                    // The wot index removes the writes of each block once, it is always reverted
+                    for block in &blocks {
+                        crate::wot_index::revert_block(&self.wot_db, block)?;
+                    }
+                    let left = blocks_left_in_modules(
+                        BlockJournalOp::Revert,
+                        blocks,
+                        entry.modules_current,
+                        modules_current,
+                    );
+                    log::warn!(
+                        "{} blocks of #{}-#{} still in modules dbs, revert them",
+                        left.len(),
+                        last.number,
+                        first.number
+                    );
+                    self.revert_blocks_in_modules(Arc::from(left))?;
+                    if self.current.is_none() {
+                        self.currency_params = CurrencyParameters::default();
+                    }
+                } else {
+                    log::error!(
+                        "revert of blocks #{}-#{} doesn't match bc_v2, drop it",
+                        last.number,
+                        first.number
+                    );
+                }
+            }
+            op => log::info!(
+                "{:?} of blocks #{}-#{} not written, drop it",
+                op,
+                first.number,
+                last.number
+            ),
+        }
+
+        self.block_journal.end()?;
+        Ok(())
+    }
+}
+
+/// Blocks of a journaled operation that the modules didn't process. If the current block of
+/// the modules is still `journaled_current`, the one recorded when the operation began, they
+/// processed none of them. Otherwise they processed the blocks up to `modules_current`: they
+/// apply and revert the blocks one by one, in the order of the operation.
+fn blocks_left_in_modules(
+    op: BlockJournalOp,
+    blocks: Vec<DubpBlockV10>,
+    journaled_current: Option<u32>,
+    modules_current: Option<u32>,
+) -> Vec<DubpBlockV10> {
+    if modules_current == journaled_current {
+        return blocks;
+    }
+    blocks
+        .into_iter()
+        .filter(|block| {
+            let number = block.number().0;
+            match op {
+                BlockJournalOp::Apply => modules_current.map_or(true, |current| number > current),
+                BlockJournalOp::Revert => {
+                    modules_current.map_or(false, |current| number <= current)
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_candidate::tests::forge_block;
+    use crate::genesis::tests::{builder, keypair};
+    use crate::genesis::GenesisMember;
+    use crate::wot_mempool::tests::add_pending_newcomer;
+    use duniter_core::crypto::keys::KeyPair as _;
+
+    fn journal_dir(name: &str) -> std::io::Result<PathBuf> {
+        let dir =
+            std::env::temp_dir().join(format!("duniter-server-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_block_journal() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build(&keypairs[0])?;
+        let dir = journal_dir("block-journal")?;
+        let journal = BlockJournal::new(Some(&dir));
+
+        assert!(journal.read()?.is_none());
+        journal.begin(BlockJournalOp::Revert, &genesis, Some(0))?;
+        let entry = journal
+            .read()?
+            .ok_or_else(|| anyhow::anyhow!("no journal"))?;
+        assert_eq!(entry.op, BlockJournalOp::Revert);
+        assert_eq!(entry.blocks.len(), 1);
+        assert_eq!(entry.blocks[0].number, 0);
+        assert_eq!(entry.modules_current, Some(0));
+        journal.end()?;
+        assert!(journal.read()?.is_none());
+
+        // Without profile nothing is journaled
+        let journal = BlockJournal::new(None);
+        journal.begin(BlockJournalOp::Apply, &genesis, None)?;
+        assert!(journal.read()?.is_none());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocks_left_in_modules() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(builder(&keypairs).build_stringified(&keypairs[0])?)?;
+        let mut blocks = Vec::new();
+        for (i, keypair) in keypairs.iter().take(2).enumerate() {
+            let block = forge_block(&server, keypair, 1_600_000_000 + 300 * (i as u64 + 1))?;
+            blocks
+                .push(DubpBlockV10::from_string_object(&block).map_err(DuniterServerError::deser)?);
+            server.apply_block(block)?;
+        }
+        let numbers = |blocks: Vec<DubpBlockV10>| -> Vec<u32> {
+            blocks.iter().map(|block| block.number().0).collect()
+        };
+        let apply = |modules_current| {
+            numbers(blocks_left_in_modules(
+                BlockJournalOp::Apply,
+                vec![blocks[0].clone()],
+                Some(0),
+                modules_current,
+            ))
+        };
+        let revert = |modules_current| {
+            numbers(blocks_left_in_modules(
+                BlockJournalOp::Revert,
+                vec![blocks[1].clone(), blocks[0].clone()],
+                Some(2),
+                modules_current,
+            ))
+        };
+
+        // The modules didn't move since the journal
+        assert_eq!(apply(Some(0)), vec![1]);
+        assert_eq!(revert(Some(2)), vec![2, 1]);
+        // Stopped after the modules
+        assert_eq!(apply(Some(1)), Vec::<u32>::new());
+        assert_eq!(revert(Some(0)), Vec::<u32>::new());
+        // Stopped in the middle of the chunk
+        assert_eq!(revert(Some(1)), vec![1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_block_journal() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build(&keypairs[0])?;
+        let dir = journal_dir("recover-block-journal")?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.block_journal = BlockJournal::new(Some(&dir));
+
+        // The journal is cleared once the block is in all the dbs
+        server.apply_block_inner(Arc::new(genesis.clone()))?;
+        assert!(server.block_journal.read()?.is_none());
+        let current = server.get_current_blockstamp();
+
+        // Stopped after the modules: the block is already everywhere
+        server
+            .block_journal
+            .begin(BlockJournalOp::Apply, &genesis, None)?;
+        server.recover_block_journal()?;
+        assert!(server.block_journal.read()?.is_none());
+        assert_eq!(server.get_current_blockstamp(), current);
+
+        // Stopped before bc_v2: the revert is dropped
+        server
+            .block_journal
+            .begin(BlockJournalOp::Revert, &genesis, None)?;
+        server.recover_block_journal()?;
+        assert!(server.block_journal.read()?.is_none());
+        assert_eq!(server.get_current_blockstamp(), current);
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_between_bc_and_modules() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build_stringified(&keypairs[0])?;
+        let dir = journal_dir("recover-between-bc-and-modules")?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.block_journal = BlockJournal::new(Some(&dir));
+        server.apply_block(genesis.clone())?;
+        let genesis_blockstamp = server.get_current_blockstamp();
+
+        // Block #1 writes a newcomer certified by two members
+        let newcomer = GenesisMember::sign("test", "newcomer", &keypair(4));
+        add_pending_newcomer(&server, &newcomer, &keypairs[..2])?;
+        let block1 = forge_block(&server, &keypairs[0], genesis.time + 300)?;
+        assert_eq!(block1.certifications.len(), 2);
+        let block1 =
+            Arc::new(DubpBlockV10::from_string_object(&block1).map_err(DuniterServerError::deser)?);
+
+        // Stopped after bc_v2: the modules replay the block
+        server
+            .block_journal
+            .begin(BlockJournalOp::Apply, &block1, server.modules_current()?)?;
+        server.current = Some(duniter_core::dbs_write_ops::apply_block::apply_block(
+            &server.bc_db,
+            Arc::clone(&block1),
+            server.current,
+            &server.dbs_pool,
+            &server.global_sender,
+            false,
+        )?);
+        assert!(server.get_certs_to(newcomer.pubkey)?.is_empty());
+        server.recover_block_journal()?;
+        assert!(server.block_journal.read()?.is_none());
+        assert_eq!(server.get_certs_to(newcomer.pubkey)?.len(), 2);
+        assert_eq!(server.get_current_blockstamp(), Some(block1.blockstamp()));
+
+        // Chunk revert stopped in the middle of bc_v2: bc_v2 and the modules finish the chunk
+        let block2 = forge_block(&server, &keypairs[1], genesis.time + 600)?;
+        server.apply_block(block2.clone())?;
+        let chunk = vec![
+            DubpBlockV10::from_string_object(&block2).map_err(DuniterServerError::deser)?,
+            (*block1).clone(),
+        ];
+        server.block_journal.begin_chunk(
+            BlockJournalOp::Revert,
+            &chunk,
+            server.modules_current()?,
+        )?;
+        server.revert_blocks_in_bc(Arc::from(vec![chunk[0].clone()]))?;
+        server.recover_block_journal()?;
+        assert!(server.block_journal.read()?.is_none());
+        assert_eq!(server.get_current_blockstamp(), genesis_blockstamp);
+        assert!(server.get_certs_to(newcomer.pubkey)?.is_empty());
+
+        // Synced chunk stopped after bc_v2: the modules replay the whole chunk
+        let chunk: Vec<DubpBlockV10> = chunk.into_iter().rev().collect();
+        server.block_journal.begin_chunk(
+            BlockJournalOp::Apply,
+            &chunk,
+            server.modules_current()?,
+        )?;
+        server.current = Some(duniter_core::dbs_write_ops::apply_block::apply_chunk(
+            &server.bc_db,
+            server.current,
+            &server.dbs_pool,
+            Arc::from(chunk.clone()),
+            Some(&server.global_sender),
+        )?);
+        server.recover_block_journal()?;
+        assert!(server.block_journal.read()?.is_none());
+        assert_eq!(server.get_certs_to(newcomer.pubkey)?.len(), 2);
+        assert_eq!(server.get_current_blockstamp(), Some(chunk[1].blockstamp()));
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
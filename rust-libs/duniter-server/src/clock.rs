@@ -0,0 +1,76 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time of the server, in seconds since the unix epoch
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// Wall clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64)
+    }
+}
+
+/// Clock that only moves when it is told to, for tests and simulations.
+/// Clones share the same time.
+#[derive(Clone, Debug)]
+pub struct SimulatedClock(Arc<AtomicI64>);
+
+impl SimulatedClock {
+    pub fn new(now: i64) -> Self {
+        SimulatedClock(Arc::new(AtomicI64::new(now)))
+    }
+    pub fn set(&self, now: i64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+    /// Move the clock `secs` seconds forward and return the new time
+    pub fn advance(&self, secs: i64) -> i64 {
+        self.0.fetch_add(secs, Ordering::SeqCst) + secs
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> i64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulated_clock() {
+        let clock = SimulatedClock::new(1_600_000_000);
+        let shared = clock.clone();
+        assert_eq!(clock.advance(300), 1_600_000_300);
+        assert_eq!(shared.now(), 1_600_000_300);
+        shared.set(1_700_000_000);
+        assert_eq!(clock.now(), 1_700_000_000);
+    }
+}
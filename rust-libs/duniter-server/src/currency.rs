@@ -0,0 +1,171 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::block::parameters::BlockV10Parameters;
+use duniter_core::common::currency_name::CurrencyName;
+use std::str::FromStr;
+
+impl DuniterServer {
+    /// Parameters of the currency, defined by the genesis block
+    pub fn currency_params(&self) -> CurrencyParameters {
+        self.currency_params
+    }
+}
+
+/// Parse the `parameters` field of the genesis block of `currency`:
+/// `c:dt:ud0:sigPeriod:sigStock:sigWindow:sigValidity:sigQty:idtyWindow:msWindow:xpercent:`
+/// `msValidity:stepMax:medianTimeBlocks:avgGenTime:dtDiffEval:percentRot:udTime0:udReevalTime0:dtReeval`
+///
+/// The parameters absent from this field take the default values of the currency, as when a
+/// genesis block is applied.
+pub fn parse_genesis_parameters(
+    currency: &str,
+    parameters: &str,
+) -> Result<CurrencyParameters, String> {
+    let block_params = BlockV10Parameters::from_str(parameters.trim())
+        .map_err(|e| format!("invalid parameters: {:?}", e))?;
+    Ok(CurrencyParameters::from((
+        &CurrencyName(currency.to_owned()),
+        block_params,
+    )))
+}
+
+/// `parameters` field of a genesis block, inverse of [`parse_genesis_parameters`]
+pub fn genesis_parameters_string(params: &CurrencyParameters) -> String {
+    format!(
+        "{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}:{}",
+        params.c,
+        params.dt,
+        params.ud0,
+        params.sig_period,
+        params.sig_stock,
+        params.sig_window,
+        params.sig_validity,
+        params.sig_qty,
+        params.idty_window,
+        params.ms_window,
+        params.x_percent,
+        params.ms_validity,
+        params.step_max,
+        params.median_time_blocks,
+        params.avg_gen_time,
+        params.dt_diff_eval,
+        params.percent_rot,
+        params.ud_time0,
+        params.ud_reeval_time0,
+        params.dt_reeval,
+    )
+}
+
+/// Check the consistency of the parameters of a new currency.
+/// Return the list of the problems found.
+pub fn check_currency_params(params: &CurrencyParameters) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut check = |ok: bool, problem: &str| {
+        if !ok {
+            problems.push(problem.to_owned());
+        }
+    };
+
+    check(params.c > 0.0 && params.c < 1.0, "c must be in ]0;1[");
+    check(params.dt > 0, "dt must be positive");
+    check(params.ud0 > 0, "ud0 must be positive");
+    check(params.sig_stock > 0, "sigStock must be positive");
+    check(params.sig_validity > 0, "sigValidity must be positive");
+    check(
+        params.sig_period < params.sig_validity,
+        "sigPeriod must be lower than sigValidity",
+    );
+    check(
+        params.sig_window <= params.sig_validity,
+        "sigWindow must not exceed sigValidity",
+    );
+    check(params.sig_qty > 0, "sigQty must be positive");
+    check(
+        params.sig_qty <= params.sig_stock,
+        "sigQty must not exceed sigStock",
+    );
+    check(params.idty_window > 0, "idtyWindow must be positive");
+    check(params.ms_window > 0, "msWindow must be positive");
+    check(params.ms_validity > 0, "msValidity must be positive");
+    check(
+        params.x_percent > 0.0 && params.x_percent <= 1.0,
+        "xpercent must be in ]0;1]",
+    );
+    check(params.step_max > 0, "stepMax must be positive");
+    check(
+        params.median_time_blocks > 0,
+        "medianTimeBlocks must be positive",
+    );
+    check(params.avg_gen_time > 0, "avgGenTime must be positive");
+    check(params.dt_diff_eval > 0, "dtDiffEval must be positive");
+    check(
+        params.percent_rot > 0.0 && params.percent_rot <= 1.0,
+        "percentRot must be in ]0;1]",
+    );
+    check(
+        params.ud_reeval_time0 >= params.ud_time0,
+        "udReevalTime0 must not be before udTime0",
+    );
+    check(
+        params.dt_reeval >= params.dt,
+        "dtReeval must not be lower than dt",
+    );
+    check(
+        params.dt == 0 || params.dt_reeval % params.dt == 0,
+        "dtReeval must be a multiple of dt",
+    );
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const G1_PARAMETERS: &str = "0.0488:86400:1000:432000:100:5259600:63115200:5:5259600:5259600:0.8:31557600:5:24:300:12:0.67:1488970800:1490094000:15778800";
+
+    #[test]
+    fn test_g1_parameters() -> Result<(), String> {
+        let params = parse_genesis_parameters("g1", G1_PARAMETERS)?;
+        assert_eq!(params.dt, 86_400);
+        assert_eq!(params.sig_qty, 5);
+        assert_eq!(params.dt_reeval, 15_778_800);
+        // Not in the genesis parameters
+        assert_eq!(params.tx_window, 604_800);
+        assert_eq!(check_currency_params(&params), Vec::<String>::new());
+        assert_eq!(genesis_parameters_string(&params), G1_PARAMETERS);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_parameters() -> Result<(), String> {
+        assert!(parse_genesis_parameters("g1", "0.0488:86400").is_err());
+        assert!(parse_genesis_parameters("g1", &G1_PARAMETERS.replace("0.0488", "abc")).is_err());
+
+        let mut params = parse_genesis_parameters("g1", G1_PARAMETERS)?;
+        params.c = 1.5;
+        params.sig_qty = 200;
+        assert_eq!(
+            check_currency_params(&params),
+            vec![
+                "c must be in ]0;1[".to_owned(),
+                "sigQty must not exceed sigStock".to_owned()
+            ]
+        );
+        Ok(())
+    }
+}
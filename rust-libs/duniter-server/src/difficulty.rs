@@ -0,0 +1,318 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Difficulty of the next block: minimal proof of work, issuers frame and personalized
+//! difficulty of each member.
+
+use crate::*;
+use duniter_core::dbs::U32BE;
+use std::collections::{HashMap, HashSet};
+
+/// DUBP: `pow_min` changes when the blocks are generated this many times faster or slower
+/// than `avg_gen_time`. It is also the base of the handicap of the members who issue too
+/// many blocks.
+const POW_MIN_RANGE_RATIO: f64 = 1.189;
+
+/// Difficulty of the next block for a member
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PersonalizedDifficulty {
+    /// Number of the next block
+    pub block_number: u32,
+    pub pow_min: u64,
+    pub issuers_frame: u64,
+    pub issuers_frame_var: i64,
+    pub issuers_count: u64,
+    /// Blocks issued by the member in the current issuers frame
+    pub personal_blocks_in_frame: usize,
+    /// Median of the number of blocks issued by each issuer of the current frame
+    pub median_blocks_in_frame: f64,
+    /// Last block issued by the member in the current frame
+    pub last_block_number: Option<u32>,
+    pub difficulty: u64,
+    /// Number of blocks to wait before the rotation stops multiplying the difficulty of the
+    /// member. The handicap of the blocks issued in the frame remains.
+    pub blocks_to_wait: u64,
+}
+
+impl PersonalizedDifficulty {
+    /// The hash of the block must start with this number of zeros…
+    pub fn pow_zeros(&self) -> u64 {
+        self.difficulty / 16
+    }
+    /// …followed by a hexadecimal digit lower or equal to `15 - pow_remainder`
+    pub fn pow_remainder(&self) -> u64 {
+        self.difficulty % 16
+    }
+}
+
+impl DuniterServer {
+    /// Difficulty of the next block if it is issued by `pubkey`, `None` if there is no
+    /// blockchain yet
+    pub fn get_personalized_difficulty(
+        &self,
+        pubkey: PublicKey,
+    ) -> DuniterServerResult<Option<PersonalizedDifficulty>> {
+        let current = if let Some(current) = self.current {
+            current
+        } else {
+            return Ok(None);
+        };
+
+        let median_time = self.next_median_time(&current)?;
+        let pow_min = self.next_pow_min(&current, median_time)?;
+        let issuers_count = self.next_issuers_count(&current)?;
+        let (issuers_frame, issuers_frame_var) = next_issuers_frame(
+            current.issuers_frame,
+            current.issuers_frame_var,
+            u64::from(current.issuers_count),
+            issuers_count,
+        );
+
+        // Blocks of the current frame, from the current block
+        let blocks_in_frame = self.bc_db.blocks_meta().iter_rev(.., |it| {
+            it.values()
+                .take(current.issuers_frame as usize)
+                .collect::<KvResult<Vec<BlockMetaV2>>>()
+        })?;
+        let mut blocks_per_issuer: HashMap<PublicKey, usize> = HashMap::new();
+        for block_meta in &blocks_in_frame {
+            *blocks_per_issuer.entry(block_meta.issuer).or_default() += 1;
+        }
+        let personal_blocks_in_frame = blocks_per_issuer.get(&pubkey).copied().unwrap_or_default();
+        let mut blocks_counts: Vec<usize> = blocks_per_issuer.values().copied().collect();
+        blocks_counts.sort_unstable();
+        let median_blocks_in_frame = median(&blocks_counts).max(1.0);
+
+        let last_block_opt = blocks_in_frame
+            .iter()
+            .find(|block_meta| block_meta.issuer == pubkey);
+        let (previous_issuers_count, blocks_since) = last_block_opt.map_or((0, 0), |last_block| {
+            (
+                u64::from(last_block.issuers_count),
+                u64::from(current.number - last_block.number),
+            )
+        });
+
+        let percent_rot = self.currency_params.percent_rot;
+        Ok(Some(PersonalizedDifficulty {
+            block_number: current.number + 1,
+            pow_min,
+            issuers_frame,
+            issuers_frame_var,
+            issuers_count,
+            personal_blocks_in_frame,
+            median_blocks_in_frame,
+            last_block_number: last_block_opt.map(|last_block| last_block.number),
+            difficulty: personalized_difficulty(
+                pow_min,
+                personal_blocks_in_frame,
+                median_blocks_in_frame,
+                rotation_factor(percent_rot, previous_issuers_count, blocks_since),
+            ),
+            blocks_to_wait: (0..=previous_issuers_count)
+                .find(|wait| {
+                    rotation_factor(percent_rot, previous_issuers_count, blocks_since + wait) <= 1
+                })
+                .unwrap_or(previous_issuers_count),
+        }))
+    }
+    /// DUBP: `pow_min` is reevaluated every `dt_diff_eval` blocks, according to the speed of
+    /// the last `dt_diff_eval` blocks
+    pub(crate) fn next_pow_min(&self, current: &BlockMetaV2, median_time: u64) -> KvResult<u64> {
+        let number = current.number as usize + 1;
+        let dt_diff_eval = self.currency_params.dt_diff_eval.max(1);
+        if number % dt_diff_eval != 0 {
+            return Ok(u64::from(current.pow_min));
+        }
+        let first_median_time = self
+            .bc_db
+            .blocks_meta()
+            .get(&U32BE((number - dt_diff_eval) as u32))?
+            .map_or(median_time, |block_meta| block_meta.median_time);
+        let elapsed = median_time.saturating_sub(first_median_time);
+        let speed = if elapsed == 0 {
+            100.0
+        } else {
+            dt_diff_eval as f64 / elapsed as f64
+        };
+        Ok(next_pow_min(
+            u64::from(current.pow_min),
+            self.currency_params.avg_gen_time,
+            speed,
+        ))
+    }
+    /// DUBP: number of different issuers among the last `issuers_frame` blocks
+    pub(crate) fn next_issuers_count(&self, current: &BlockMetaV2) -> KvResult<u64> {
+        let issuers = self.bc_db.blocks_meta().iter_rev(.., |it| {
+            it.values()
+                .map_ok(|block_meta| block_meta.issuer)
+                .take(current.issuers_frame as usize)
+                .collect::<KvResult<HashSet<PublicKey>>>()
+        })?;
+        Ok(issuers.len() as u64)
+    }
+}
+
+/// DUBP: a member who just issued a block must wait for `percent_rot` of the issuers of that
+/// block before forging at `pow_min` again, the difficulty is multiplied in the meantime
+fn rotation_factor(percent_rot: f64, previous_issuers_count: u64, blocks_since: u64) -> u64 {
+    (percent_rot * previous_issuers_count as f64 / (1 + blocks_since) as f64).floor() as u64
+}
+
+/// DUBP: the members who issued more blocks than the median of the frame get a handicap
+fn personalized_difficulty(
+    pow_min: u64,
+    personal_blocks_in_frame: usize,
+    median_blocks_in_frame: f64,
+    rotation_factor: u64,
+) -> u64 {
+    let excess = ((personal_blocks_in_frame + 1) as f64 / median_blocks_in_frame - 1.0).max(0.0);
+    let handicap = ((1.0 + excess).ln() / POW_MIN_RANGE_RATIO.ln()).floor() as u64;
+    let difficulty = pow_min.max(pow_min * rotation_factor) + handicap;
+    // A multiple of 16 means one more leading zero, skip it
+    if (difficulty + 1) % 16 == 0 {
+        difficulty + 1
+    } else {
+        difficulty
+    }
+}
+
+/// The median of an even number of values is the average of the two central values
+fn median(sorted_values: &[usize]) -> f64 {
+    let len = sorted_values.len();
+    if len == 0 {
+        0.0
+    } else if len % 2 == 0 {
+        (sorted_values[len / 2 - 1] + sorted_values[len / 2]) as f64 / 2.0
+    } else {
+        sorted_values[len / 2] as f64
+    }
+}
+
+fn next_pow_min(previous_pow_min: u64, avg_gen_time: u64, speed: f64) -> u64 {
+    let max_gen_time = (avg_gen_time as f64 * POW_MIN_RANGE_RATIO).ceil();
+    let min_gen_time = (avg_gen_time as f64 / POW_MIN_RANGE_RATIO).floor();
+    if speed >= 1.0 / min_gen_time {
+        // A multiple of 16 means one more leading zero, skip it
+        if (previous_pow_min + 2) % 16 == 0 {
+            previous_pow_min + 2
+        } else {
+            previous_pow_min + 1
+        }
+    } else if speed <= 1.0 / max_gen_time {
+        if previous_pow_min % 16 == 0 {
+            previous_pow_min.saturating_sub(2)
+        } else {
+            previous_pow_min.saturating_sub(1)
+        }
+    } else {
+        previous_pow_min
+    }
+}
+
+/// DUBP: the issuers frame grows or shrinks by one block at a time, until it matches five
+/// times the variation of the number of different issuers
+pub(crate) fn next_issuers_frame(
+    previous_frame: u64,
+    previous_frame_var: i64,
+    previous_issuers_count: u64,
+    issuers_count: u64,
+) -> (u64, i64) {
+    let frame = match previous_frame_var {
+        var if var > 0 => previous_frame + 1,
+        var if var < 0 => previous_frame.saturating_sub(1),
+        _ => previous_frame,
+    };
+    let frame_var = previous_frame_var + 5 * (issuers_count as i64 - previous_issuers_count as i64)
+        - previous_frame_var.signum();
+    (frame, frame_var)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::tests::{builder, keypair};
+    use duniter_core::crypto::keys::KeyPair as _;
+
+    #[test]
+    fn test_next_pow_min() {
+        // Ğ1: avg_gen_time = 300s, pow_min changes when a block takes less than 252s or more
+        // than 357s on average
+        assert_eq!(next_pow_min(70, 300, 1.0 / 300.0), 70);
+        assert_eq!(next_pow_min(70, 300, 1.0 / 250.0), 71);
+        assert_eq!(next_pow_min(78, 300, 1.0 / 250.0), 80);
+        assert_eq!(next_pow_min(70, 300, 1.0 / 360.0), 69);
+        assert_eq!(next_pow_min(80, 300, 1.0 / 360.0), 78);
+        assert_eq!(next_pow_min(0, 300, 1.0 / 360.0), 0);
+    }
+
+    #[test]
+    fn test_next_issuers_frame() {
+        // Block #1 after the genesis block
+        assert_eq!(next_issuers_frame(1, 0, 0, 1), (1, 5));
+        assert_eq!(next_issuers_frame(1, 5, 1, 1), (2, 4));
+        assert_eq!(next_issuers_frame(6, 0, 1, 1), (6, 0));
+        assert_eq!(next_issuers_frame(6, -2, 2, 1), (5, -6));
+    }
+
+    #[test]
+    fn test_personalized_difficulty() {
+        assert_eq!(median(&[1, 2, 4]), 2.0);
+        assert_eq!(median(&[1, 2, 3, 4]), 2.5);
+
+        // No block in the frame
+        assert_eq!(personalized_difficulty(70, 0, 1.0, 0), 70);
+        // As many blocks as the median: 2/1 - 1 = 1, ln(2)/ln(1.189) = 4.0
+        assert_eq!(personalized_difficulty(70, 1, 1.0, 0), 74);
+        // 79 + 1 is a multiple of 16
+        assert_eq!(personalized_difficulty(75, 1, 1.0, 0), 80);
+        // Last block issued 2 blocks ago by a frame of 10 issuers: floor(0.67 * 10 / 3) = 2
+        assert_eq!(rotation_factor(0.67, 10, 2), 2);
+        assert_eq!(personalized_difficulty(70, 1, 2.0, 2), 140);
+        assert_eq!(rotation_factor(0.67, 10, 3), 1);
+    }
+
+    #[test]
+    fn test_get_personalized_difficulty() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        assert_eq!(
+            server.get_personalized_difficulty(keypairs[0].public_key())?,
+            None
+        );
+        server.apply_block(genesis)?;
+
+        let issuer_difficulty = server
+            .get_personalized_difficulty(keypairs[0].public_key())?
+            .ok_or_else(|| anyhow::anyhow!("no difficulty"))?;
+        assert_eq!(issuer_difficulty.block_number, 1);
+        assert_eq!(issuer_difficulty.personal_blocks_in_frame, 1);
+        assert_eq!(issuer_difficulty.last_block_number, Some(0));
+        assert_eq!(issuer_difficulty.issuers_count, 1);
+        // One block in a frame of one block: handicap of 4
+        assert_eq!(issuer_difficulty.difficulty, 4);
+
+        let other_difficulty = server
+            .get_personalized_difficulty(keypairs[1].public_key())?
+            .ok_or_else(|| anyhow::anyhow!("no difficulty"))?;
+        assert_eq!(other_difficulty.personal_blocks_in_frame, 0);
+        assert_eq!(other_difficulty.last_block_number, None);
+        assert_eq!(other_difficulty.difficulty, 0);
+        assert_eq!(other_difficulty.blocks_to_wait, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+
+pub type DuniterServerResult<T> = Result<T, DuniterServerError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DuniterServerError {
+    #[error("Fail to deserialize document: {0}")]
+    Deser(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Invalid document: {0}")]
+    Validation(String),
+    #[error("DB error: {0}")]
+    Db(#[from] KvError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Module error: {0}")]
+    Module(anyhow::Error),
+}
+
+impl DuniterServerError {
+    /// Error code, stable across versions so that clients can match on it
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Deser(_) => "DESER_ERROR",
+            Self::Validation(_) => "VALIDATION_ERROR",
+            Self::Db(_) => "DB_ERROR",
+            Self::Io(_) => "IO_ERROR",
+            Self::Module(_) => "MODULE_ERROR",
+        }
+    }
+    pub(crate) fn deser<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> Self {
+        Self::Deser(e.into())
+    }
+    pub(crate) fn module<E: Into<anyhow::Error>>(e: E) -> Self {
+        Self::Module(e.into())
+    }
+}
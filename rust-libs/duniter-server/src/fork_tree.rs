@@ -0,0 +1,593 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fork detection and resolution.
+//!
+//! The fork tree keeps the last blocks of the main chain in the fork window (`forksize` of the
+//! configuration) and every candidate block received in this window. When a branch becomes better than the main chain (more
+//! cumulative proof-of-work, then lower median time), the server reverts the main chain down to
+//! the fork point and applies the branch.
+//!
+//! Only blocks respecting the local rules enter the tree, so the proof-of-work of a branch is
+//! backed by the hashes of its blocks. The global rules are checked when a block is applied.
+
+use crate::*;
+use std::collections::{HashMap, HashSet};
+
+/// Fork window size when the configuration doesn't set one
+pub const DEFAULT_FORK_WINDOW_SIZE: u32 = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ForkChoice {
+    /// The block is already in the fork tree
+    AlreadyKnown,
+    /// The block extends the main chain and has been applied
+    Applied,
+    /// The block has been stored as a fork candidate
+    Stacked,
+    /// The main chain has been switched to a better branch
+    Switched {
+        reverted: Vec<Blockstamp>,
+        applied: Vec<Blockstamp>,
+    },
+    /// The block doesn't respect the DUBP rules, or the branch it completes contains such a block
+    Rejected(Vec<RuleViolation>),
+    /// The block is below the fork window
+    TooOld,
+    /// The block is above the fork window
+    TooFarAhead,
+}
+
+/// Block of the main chain. The content of the blocks applied before the start of the server
+/// is unknown, they can't be reverted by a fork resolution.
+#[derive(Clone, Copy, Debug)]
+struct MainBlock {
+    hash: Hash,
+    pow_min: u64,
+    median_time: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct ForkTree {
+    blocks: HashMap<Hash, Arc<DubpBlockV10>>,
+    main_chain: BTreeMap<BlockNumber, MainBlock>,
+    window_size: u32,
+}
+
+#[derive(Debug)]
+pub(crate) struct Branch {
+    pub(crate) fork_point: BlockNumber,
+    pub(crate) blocks: Vec<Arc<DubpBlockV10>>,
+}
+
+impl ForkTree {
+    /// Fork tree of a server restarted on `bc_db`, with the main chain of the fork window
+    pub(crate) fn load(bc_db: &BcV2Db<FileBackend>, window_size: u32) -> KvResult<Self> {
+        let main_chain = bc_db.blocks_meta().iter_rev(.., |it| {
+            it.values()
+                .map_ok(|block_meta| {
+                    (
+                        BlockNumber(block_meta.number),
+                        MainBlock {
+                            hash: block_meta.hash,
+                            pow_min: u64::from(block_meta.pow_min),
+                            median_time: block_meta.median_time,
+                        },
+                    )
+                })
+                .take(window_size as usize + 1)
+                .collect::<KvResult<BTreeMap<_, _>>>()
+        })?;
+        Ok(ForkTree {
+            blocks: HashMap::new(),
+            main_chain,
+            window_size,
+        })
+    }
+    pub(crate) fn window_size(&self) -> u32 {
+        self.window_size
+    }
+    /// Candidates beyond this count are dropped, from the highest one
+    fn max_candidates(&self) -> usize {
+        10 * self.window_size as usize
+    }
+    pub(crate) fn is_known(&self, blockstamp: Blockstamp) -> bool {
+        self.blocks.contains_key(&blockstamp.hash.0) || self.is_on_main_chain(blockstamp)
+    }
+    pub(crate) fn get(&self, hash: &Hash) -> Option<Arc<DubpBlockV10>> {
+        self.blocks.get(hash).cloned()
+    }
+    /// Store a fork candidate. Beyond `max_candidates`, the highest candidates are
+    /// dropped: their branch is the longest to complete.
+    pub(crate) fn insert_candidate(&mut self, block: Arc<DubpBlockV10>) {
+        self.blocks.insert(block.hash().0, block);
+
+        let mut candidates: Vec<(BlockNumber, Hash)> = self
+            .blocks
+            .values()
+            .filter(|block| !self.is_on_main_chain(block.blockstamp()))
+            .map(|block| (block.number(), block.hash().0))
+            .collect();
+        let max_candidates = self.max_candidates();
+        if candidates.len() > max_candidates {
+            candidates.sort_unstable();
+            for (_, hash) in &candidates[max_candidates..] {
+                self.blocks.remove(hash);
+            }
+        }
+    }
+    pub(crate) fn main_block(&self, number: BlockNumber) -> Option<Arc<DubpBlockV10>> {
+        self.main_chain
+            .get(&number)
+            .and_then(|main_block| self.get(&main_block.hash))
+    }
+    pub(crate) fn is_on_main_chain(&self, blockstamp: Blockstamp) -> bool {
+        self.main_chain
+            .get(&blockstamp.number)
+            .map(|main_block| main_block.hash)
+            == Some(blockstamp.hash.0)
+    }
+    /// Register a block applied on the main chain and prune blocks out of the fork window
+    pub(crate) fn push_main(&mut self, block: Arc<DubpBlockV10>) {
+        let number = block.number();
+        self.main_chain.insert(
+            number,
+            MainBlock {
+                hash: block.hash().0,
+                pow_min: block.pow_min() as u64,
+                median_time: block.common_time(),
+            },
+        );
+        self.blocks.insert(block.hash().0, block);
+
+        if number.0 >= self.window_size {
+            let limit = BlockNumber(number.0 - self.window_size);
+            self.main_chain = self.main_chain.split_off(&limit);
+            self.blocks.retain(|_, block| block.number() >= limit);
+        }
+    }
+    /// Unregister the head of the main chain, the block is kept as a fork candidate
+    pub(crate) fn pop_main(&mut self, number: BlockNumber) {
+        self.main_chain.remove(&number);
+    }
+    pub(crate) fn remove(&mut self, hash: &Hash) {
+        self.blocks.remove(hash);
+    }
+    /// Find the best branch connected to the main chain, if it is better than the main chain
+    pub(crate) fn best_branch(&self) -> Option<Branch> {
+        if self.main_chain.is_empty() {
+            return None;
+        }
+
+        let mut best: Option<(Branch, (u64, std::cmp::Reverse<u64>))> = None;
+        for leaf in self.leaves() {
+            if let Some(branch) = self.branch_to_main_chain(leaf) {
+                let main_blocks = self
+                    .main_chain
+                    .range(BlockNumber(branch.fork_point.0 + 1)..)
+                    .map(|(_, main_block)| main_block);
+                let branch_score = Self::score(
+                    branch
+                        .blocks
+                        .iter()
+                        .map(|block| (block.pow_min() as u64, block.common_time())),
+                );
+                let main_score = Self::score(
+                    main_blocks
+                        .clone()
+                        .map(|main_block| (main_block.pow_min, main_block.median_time)),
+                );
+                if branch_score > main_score
+                    && best
+                        .as_ref()
+                        .map_or(true, |(_, best_score)| branch_score > *best_score)
+                {
+                    if main_blocks
+                        .clone()
+                        .all(|main_block| self.blocks.contains_key(&main_block.hash))
+                    {
+                        best = Some((branch, branch_score));
+                    } else {
+                        log::warn!(
+                            "Fork resolution: can't revert to #{}, the blocks applied before the start are unknown",
+                            branch.fork_point
+                        );
+                    }
+                }
+            }
+        }
+        best.map(|(branch, _)| branch)
+    }
+    fn leaves(&self) -> impl Iterator<Item = &Arc<DubpBlockV10>> {
+        let parents: HashSet<Hash> = self
+            .blocks
+            .values()
+            .map(|block| block.previous_blockstamp().hash.0)
+            .collect();
+        self.blocks.values().filter(move |block| {
+            !parents.contains(&block.hash().0) && !self.is_on_main_chain(block.blockstamp())
+        })
+    }
+    fn branch_to_main_chain(&self, leaf: &Arc<DubpBlockV10>) -> Option<Branch> {
+        let mut blocks = vec![Arc::clone(leaf)];
+        loop {
+            let previous_blockstamp = blocks[blocks.len() - 1].previous_blockstamp();
+            if self.is_on_main_chain(previous_blockstamp) {
+                blocks.reverse();
+                return Some(Branch {
+                    fork_point: previous_blockstamp.number,
+                    blocks,
+                });
+            } else if let Some(previous) = self.blocks.get(&previous_blockstamp.hash.0) {
+                blocks.push(Arc::clone(previous));
+            } else {
+                // Orphan branch
+                return None;
+            }
+        }
+    }
+    /// Cumulative proof-of-work, then lower median time of the head, of blocks given as
+    /// `(pow_min, median_time)`
+    fn score<I: Iterator<Item = (u64, u64)>>(blocks: I) -> (u64, std::cmp::Reverse<u64>) {
+        let (work, head_median_time) = blocks
+            .fold((0, u64::MAX), |(work, _), (pow_min, median_time)| {
+                (work + pow_min + 1, median_time)
+            });
+        (work, std::cmp::Reverse(head_median_time))
+    }
+}
+
+impl DuniterServer {
+    /// Set the fork window size (`forksize` of the configuration), the fork candidates received
+    /// before are dropped
+    pub fn set_fork_window_size(&mut self, fork_window_size: u32) -> DuniterServerResult<()> {
+        self.fork_tree = ForkTree::load(&self.bc_db, fork_window_size)?;
+        Ok(())
+    }
+    /// Receive a block from the network, store it in the fork tree and switch to the best
+    /// branch if needed.
+    pub fn receive_block(
+        &mut self,
+        block_stringified: DubpBlockV10Stringified,
+    ) -> DuniterServerResult<ForkChoice> {
+        let block = Arc::new(
+            DubpBlockV10::from_string_object(&block_stringified)
+                .map_err(DuniterServerError::deser)?,
+        );
+
+        if self.fork_tree.is_known(block.blockstamp()) {
+            return Ok(ForkChoice::AlreadyKnown);
+        }
+        let fork_window_size = self.fork_tree.window_size();
+        match self.current {
+            Some(current) if block.number().0 + fork_window_size <= current.number => {
+                return Ok(ForkChoice::TooOld);
+            }
+            Some(current) if block.number().0 > current.number + fork_window_size => {
+                return Ok(ForkChoice::TooFarAhead);
+            }
+            _ => (),
+        }
+
+        let violations = crate::validation::check_local_rules(&block_stringified, &block);
+        if !violations.is_empty() {
+            return Ok(ForkChoice::Rejected(violations));
+        }
+
+        let extends_current = match self.current {
+            None => block.number() == BlockNumber(0),
+            Some(current) => {
+                current.number + 1 == block.number().0
+                    && current.hash == block.previous_blockstamp().hash.0
+            }
+        };
+        if extends_current {
+            let violations = self.global_rules_violations(&block_stringified, &block);
+            if !violations.is_empty() {
+                return Ok(ForkChoice::Rejected(violations));
+            }
+            self.apply_block_inner(block)?;
+            return Ok(ForkChoice::Applied);
+        }
+        self.fork_tree.insert_candidate(block);
+
+        if let Some(branch) = self.fork_tree.best_branch() {
+            self.switch_to_branch(branch)
+        } else {
+            Ok(ForkChoice::Stacked)
+        }
+    }
+    fn switch_to_branch(&mut self, branch: Branch) -> DuniterServerResult<ForkChoice> {
+        log::info!(
+            "Fork resolution: switch to branch #{}-#{} (fork point #{})",
+            branch.blocks[0].number(),
+            branch.blocks[branch.blocks.len() - 1].number(),
+            branch.fork_point
+        );
+
+        let reverted = self.revert_main_chain_to(branch.fork_point)?;
+
+        let mut applied = Vec::with_capacity(branch.blocks.len());
+        for block in branch.blocks {
+            let blockstamp = block.blockstamp();
+            let violations = self.global_rules_violations(&block.to_string_object(), &block);
+            if !violations.is_empty() {
+                log::warn!(
+                    "Fork resolution: block #{} is invalid: {:?}",
+                    blockstamp,
+                    violations
+                );
+                self.fork_tree.remove(&blockstamp.hash.0);
+                self.restore_main_chain(branch.fork_point, &reverted)?;
+                return Ok(ForkChoice::Rejected(violations));
+            }
+            if let Err(e) = self.apply_block_inner(Arc::clone(&block)) {
+                log::error!(
+                    "Fork resolution: fail to apply block #{}: {}",
+                    blockstamp,
+                    e
+                );
+                self.fork_tree.remove(&blockstamp.hash.0);
+                self.restore_main_chain(branch.fork_point, &reverted)?;
+                return Err(e);
+            }
+            applied.push(blockstamp);
+        }
+
+        Ok(ForkChoice::Switched { reverted, applied })
+    }
+    /// Revert the blocks of the abandoned branch and apply again the `reverted` blocks
+    fn restore_main_chain(
+        &mut self,
+        fork_point: BlockNumber,
+        reverted: &[Blockstamp],
+    ) -> DuniterServerResult<()> {
+        self.revert_main_chain_to(fork_point)?;
+        for blockstamp in reverted.iter().rev() {
+            if let Some(block) = self.fork_tree.get(&blockstamp.hash.0) {
+                self.apply_block_inner(block)?;
+            }
+        }
+        Ok(())
+    }
+    fn revert_main_chain_to(
+        &mut self,
+        fork_point: BlockNumber,
+    ) -> DuniterServerResult<Vec<Blockstamp>> {
+        let mut reverted = Vec::new();
+        while let Some(current) = self.current {
+            if current.number <= fork_point.0 {
+                break;
+            }
+            let block = self
+                .fork_tree
+                .main_block(BlockNumber(current.number))
+                .ok_or_else(|| {
+                    DuniterServerError::Validation(format!(
+                        "block #{} is out of the fork window",
+                        current.number
+                    ))
+                })?;
+            reverted.push(block.blockstamp());
+            self.revert_block_inner(block)?;
+        }
+        Ok(reverted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_candidate::tests::{forge_block, prove};
+    use crate::genesis::tests::{builder, keypair};
+    use duniter_core::crypto::keys::{ed25519::Ed25519KeyPair, KeyPair as _};
+    use duniter_core::dbs::U32BE;
+
+    fn keypairs() -> Vec<Ed25519KeyPair> {
+        (1..=3).map(keypair).collect()
+    }
+
+    /// Node on the genesis block, each node forges its own branch
+    fn start_node(keypairs: &[Ed25519KeyPair]) -> anyhow::Result<DuniterServer> {
+        let genesis = builder(keypairs).build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        assert_eq!(server.receive_block(genesis)?, ForkChoice::Applied);
+        Ok(server)
+    }
+
+    /// Forge the next block of `server` issued by `keypair`, `delay` seconds after the genesis
+    fn forge(
+        server: &mut DuniterServer,
+        keypair: &Ed25519KeyPair,
+        delay: u64,
+    ) -> anyhow::Result<DubpBlockV10Stringified> {
+        let block = forge_block(server, keypair, 1_600_000_000 + delay)?;
+        assert_eq!(server.receive_block(block.clone())?, ForkChoice::Applied);
+        Ok(block)
+    }
+
+    fn blockstamp(block: &DubpBlockV10Stringified) -> anyhow::Result<Blockstamp> {
+        Ok(DubpBlockV10::from_string_object(block)?.blockstamp())
+    }
+
+    fn rules(fork_choice: ForkChoice) -> Vec<&'static str> {
+        match fork_choice {
+            ForkChoice::Rejected(violations) => violations
+                .into_iter()
+                .map(|violation| violation.rule)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_multi_blocks_reorg() -> anyhow::Result<()> {
+        let keypairs = keypairs();
+        let mut a = start_node(&keypairs)?;
+        let mut b = start_node(&keypairs)?;
+        let block1 = forge(&mut a, &keypairs[0], 300)?;
+        assert_eq!(b.receive_block(block1)?, ForkChoice::Applied);
+        let a2 = forge(&mut a, &keypairs[0], 600)?;
+        let a3 = forge(&mut a, &keypairs[0], 900)?;
+        let b2 = forge(&mut b, &keypairs[1], 600)?;
+        let b3 = forge(&mut b, &keypairs[1], 900)?;
+        let b4 = forge(&mut b, &keypairs[1], 1200)?;
+
+        assert_eq!(a.receive_block(b2.clone())?, ForkChoice::Stacked);
+        assert_eq!(a.receive_block(b3.clone())?, ForkChoice::Stacked);
+        assert_eq!(
+            a.receive_block(b4.clone())?,
+            ForkChoice::Switched {
+                reverted: vec![blockstamp(&a3)?, blockstamp(&a2)?],
+                applied: vec![blockstamp(&b2)?, blockstamp(&b3)?, blockstamp(&b4)?],
+            }
+        );
+
+        assert_eq!(a.get_current_blockstamp(), Some(blockstamp(&b4)?));
+        assert_eq!(
+            a.bc_db
+                .blocks_meta()
+                .get(&U32BE(2))?
+                .map(|block_meta| block_meta.hash),
+            Some(blockstamp(&b2)?.hash.0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_main_chain_if_branch_is_not_better() -> anyhow::Result<()> {
+        let keypairs = keypairs();
+        let mut a = start_node(&keypairs)?;
+        let mut b = start_node(&keypairs)?;
+        let block1 = forge(&mut a, &keypairs[0], 300)?;
+        assert_eq!(b.receive_block(block1)?, ForkChoice::Applied);
+        forge(&mut a, &keypairs[0], 600)?;
+        let a3 = forge(&mut a, &keypairs[0], 900)?;
+
+        // Same length but a later median time
+        let b2 = forge(&mut b, &keypairs[1], 700)?;
+        let b3 = forge(&mut b, &keypairs[1], 1000)?;
+        assert_eq!(a.receive_block(b2)?, ForkChoice::Stacked);
+        assert_eq!(a.receive_block(b3.clone())?, ForkChoice::Stacked);
+        assert_eq!(a.receive_block(b3)?, ForkChoice::AlreadyKnown);
+
+        assert_eq!(a.get_current_blockstamp(), Some(blockstamp(&a3)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_orphan_branch() -> anyhow::Result<()> {
+        let keypairs = keypairs();
+        let mut a = start_node(&keypairs)?;
+        let mut b = start_node(&keypairs)?;
+        let block1 = forge(&mut a, &keypairs[0], 300)?;
+        assert_eq!(b.receive_block(block1)?, ForkChoice::Applied);
+        forge(&mut a, &keypairs[0], 600)?;
+        forge(&mut a, &keypairs[0], 900)?;
+        let branch = (2..=5)
+            .map(|n| forge(&mut b, &keypairs[1], n * 300))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // The parent of this branch is unknown
+        for block in &branch[1..] {
+            assert_eq!(a.receive_block(block.clone())?, ForkChoice::Stacked);
+        }
+
+        // The missing block connects the branch to the main chain
+        assert!(matches!(
+            a.receive_block(branch[0].clone())?,
+            ForkChoice::Switched { .. }
+        ));
+        assert_eq!(a.get_current_blockstamp(), Some(blockstamp(&branch[3])?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reject_invalid_blocks() -> anyhow::Result<()> {
+        let keypairs = keypairs();
+        let mut a = start_node(&keypairs)?;
+        let mut b = start_node(&keypairs)?;
+        let block1 = forge(&mut a, &keypairs[0], 300)?;
+        assert_eq!(b.receive_block(block1)?, ForkChoice::Applied);
+        let a2 = forge(&mut a, &keypairs[0], 600)?;
+
+        // Proof-of-work declared but not done
+        let mut block = b.generate_block_candidate(keypairs[1].public_key(), 1_600_000_600)?;
+        block.pow_min = 60;
+        let block = prove_block(block, &keypairs[1], 0, PowConf::default())?
+            .wait()
+            .ok_or_else(|| anyhow::anyhow!("proof cancelled"))?;
+        assert!(rules(a.receive_block(block)?).contains(&"POW"));
+
+        // Valid on its own but not on top of the current block
+        let mut block = a.generate_block_candidate(keypairs[1].public_key(), 1_600_000_900)?;
+        block.members_count += 1;
+        let block = prove(&a, block, &keypairs[1])?;
+        assert!(rules(a.receive_block(block)?).contains(&"MEMBERS_COUNT"));
+
+        // A better branch containing an invalid block is abandoned
+        let b2 = forge(&mut b, &keypairs[1], 600)?;
+        let mut b3 = b.generate_block_candidate(keypairs[1].public_key(), 1_600_000_900)?;
+        b3.members_count += 1;
+        let b3 = prove(&b, b3, &keypairs[1])?;
+        assert_eq!(a.receive_block(b2)?, ForkChoice::Stacked);
+        assert!(rules(a.receive_block(b3)?).contains(&"MEMBERS_COUNT"));
+        assert_eq!(a.get_current_blockstamp(), Some(blockstamp(&a2)?));
+
+        // Blocks above the fork window are not stored
+        let mut block = a.generate_block_candidate(keypairs[1].public_key(), 1_600_000_900)?;
+        block.number += u64::from(DEFAULT_FORK_WINDOW_SIZE);
+        let block = prove(&a, block, &keypairs[1])?;
+        assert_eq!(a.receive_block(block)?, ForkChoice::TooFarAhead);
+
+        // The fork window comes from the configuration
+        a.set_fork_window_size(1)?;
+        let mut block = a.generate_block_candidate(keypairs[1].public_key(), 1_600_000_900)?;
+        block.number += 1;
+        let block = prove(&a, block, &keypairs[1])?;
+        assert_eq!(a.receive_block(block)?, ForkChoice::TooFarAhead);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_main_chain() -> anyhow::Result<()> {
+        let keypairs = keypairs();
+        let mut a = start_node(&keypairs)?;
+        let mut b = start_node(&keypairs)?;
+        let block1 = forge(&mut a, &keypairs[0], 300)?;
+        assert_eq!(b.receive_block(block1.clone())?, ForkChoice::Applied);
+        forge(&mut a, &keypairs[0], 600)?;
+        let b2 = forge(&mut b, &keypairs[1], 600)?;
+        let b3 = forge(&mut b, &keypairs[1], 900)?;
+
+        // Restart
+        a.fork_tree = ForkTree::load(&a.bc_db, DEFAULT_FORK_WINDOW_SIZE)?;
+        assert_eq!(a.receive_block(block1)?, ForkChoice::AlreadyKnown);
+
+        // The content of the main blocks is lost, they can't be reverted
+        assert_eq!(a.receive_block(b2)?, ForkChoice::Stacked);
+        assert_eq!(a.receive_block(b3)?, ForkChoice::Stacked);
+        assert_eq!(
+            a.get_current_blockstamp().map(|current| current.number),
+            Some(BlockNumber(2))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::crypto::keys::{
+    ed25519::{Ed25519KeyPair, Signature},
+    KeyPair as _, PublicKey as _, Signator as _, Signature as _,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Identity and membership of an initial member, signed by the member
+#[derive(Clone, Debug)]
+pub struct GenesisMember {
+    pub pubkey: PublicKey,
+    pub uid: String,
+    pub idty_sig: Signature,
+    pub membership_sig: Signature,
+}
+
+impl GenesisMember {
+    pub fn sign(currency: &str, uid: &str, keypair: &Ed25519KeyPair) -> Self {
+        let pubkey = keypair.public_key();
+        let signator = keypair.generate_signator();
+        GenesisMember {
+            pubkey,
+            uid: uid.to_owned(),
+            idty_sig: signator.sign(identity_text(currency, pubkey, uid).as_bytes()),
+            membership_sig: signator.sign(membership_text(currency, pubkey, uid).as_bytes()),
+        }
+    }
+}
+
+/// Certification between two initial members, signed by its issuer
+#[derive(Clone, Debug)]
+pub struct GenesisCert {
+    pub issuer: PublicKey,
+    pub receiver: PublicKey,
+    pub sig: Signature,
+}
+
+impl GenesisCert {
+    pub fn sign(currency: &str, issuer: &Ed25519KeyPair, receiver: &GenesisMember) -> Self {
+        let text = format!(
+            "Version: 10\nType: Certification\nCurrency: {}\nIssuer: {}\nIdtyIssuer: {}\nIdtyUniqueID: {}\nIdtyTimestamp: {}\nIdtySignature: {}\nCertTimestamp: {}\n",
+            currency,
+            issuer.public_key().to_base58(),
+            receiver.pubkey.to_base58(),
+            receiver.uid,
+            genesis_blockstamp(),
+            receiver.idty_sig.to_base64(),
+            genesis_blockstamp(),
+        );
+        GenesisCert {
+            issuer: issuer.public_key(),
+            receiver: receiver.pubkey,
+            sig: issuer.generate_signator().sign(text.as_bytes()),
+        }
+    }
+}
+
+/// Build the block #0 of a new currency
+#[derive(Clone, Debug)]
+pub struct GenesisBlockBuilder {
+    pub currency: String,
+    pub currency_params: CurrencyParameters,
+    /// Time and median time of the genesis block
+    pub time: u64,
+    pub pow_min: usize,
+    pub members: Vec<GenesisMember>,
+    pub certs: Vec<GenesisCert>,
+}
+
+impl GenesisBlockBuilder {
+    /// Build the genesis block, signed by `issuer` who must be one of the initial members
+    pub fn build(&self, issuer: &Ed25519KeyPair) -> DuniterServerResult<DubpBlockV10> {
+        DubpBlockV10::from_string_object(&self.build_stringified(issuer)?)
+            .map_err(DuniterServerError::deser)
+    }
+    pub fn build_stringified(
+        &self,
+        issuer: &Ed25519KeyPair,
+    ) -> DuniterServerResult<DubpBlockV10Stringified> {
+        self.check(issuer.public_key())
+            .map_err(DuniterServerError::Validation)?;
+
+        let parameters = crate::currency::genesis_parameters_string(&self.currency_params);
+        let identities: Vec<String> = self
+            .members
+            .iter()
+            .map(|member| {
+                format!(
+                    "{}:{}:{}:{}",
+                    member.pubkey.to_base58(),
+                    member.idty_sig.to_base64(),
+                    genesis_blockstamp(),
+                    member.uid
+                )
+            })
+            .collect();
+        let joiners: Vec<String> = self
+            .members
+            .iter()
+            .map(|member| {
+                format!(
+                    "{}:{}:{}:{}:{}",
+                    member.pubkey.to_base58(),
+                    member.membership_sig.to_base64(),
+                    genesis_blockstamp(),
+                    genesis_blockstamp(),
+                    member.uid
+                )
+            })
+            .collect();
+        let certifications: Vec<String> = self
+            .certs
+            .iter()
+            .map(|cert| {
+                format!(
+                    "{}:{}:0:{}",
+                    cert.issuer.to_base58(),
+                    cert.receiver.to_base58(),
+                    cert.sig.to_base64()
+                )
+            })
+            .collect();
+
+        let block = DubpBlockV10Stringified {
+            version: 10,
+            number: 0,
+            currency: self.currency.clone(),
+            pow_min: self.pow_min as u64,
+            time: self.time,
+            median_time: self.time,
+            unit_base: 0,
+            issuer: issuer.public_key().to_base58(),
+            issuers_frame: 1,
+            issuers_frame_var: 0,
+            issuers_count: 0,
+            parameters: Some(parameters),
+            members_count: self.members.len() as u64,
+            monetary_mass: 0,
+            identities,
+            joiners,
+            certifications,
+            ..Default::default()
+        };
+        let pow_conf = PowConf {
+            cpu: 1.0,
+            nb_cores: 1,
+            prefix: 0,
+        };
+        crate::pow::prove_block(block, issuer, self.pow_min, pow_conf)?
+            .wait()
+            .ok_or_else(|| {
+                DuniterServerError::Validation("proof of the genesis block cancelled".to_owned())
+            })
+    }
+    fn check(&self, issuer: PublicKey) -> Result<(), String> {
+        let problems = crate::currency::check_currency_params(&self.currency_params);
+        if !problems.is_empty() {
+            return Err(problems.join(", "));
+        }
+        if !self.members.iter().any(|member| member.pubkey == issuer) {
+            return Err("the issuer must be an initial member".to_owned());
+        }
+
+        let mut pubkeys = HashSet::new();
+        let mut uids = HashSet::new();
+        for member in &self.members {
+            if !pubkeys.insert(member.pubkey) || !uids.insert(member.uid.as_str()) {
+                return Err(format!("duplicate member {}", member.uid));
+            }
+        }
+
+        let mut certs = HashSet::new();
+        let mut received: HashMap<PublicKey, usize> = HashMap::new();
+        let mut issued: HashMap<PublicKey, usize> = HashMap::new();
+        for cert in &self.certs {
+            if !pubkeys.contains(&cert.issuer) || !pubkeys.contains(&cert.receiver) {
+                return Err("certifications must link initial members".to_owned());
+            }
+            if cert.issuer == cert.receiver || !certs.insert((cert.issuer, cert.receiver)) {
+                return Err(format!(
+                    "invalid certification from {} to {}",
+                    cert.issuer.to_base58(),
+                    cert.receiver.to_base58()
+                ));
+            }
+            *received.entry(cert.receiver).or_default() += 1;
+            *issued.entry(cert.issuer).or_default() += 1;
+        }
+        for member in &self.members {
+            if received.get(&member.pubkey).copied().unwrap_or_default()
+                < self.currency_params.sig_qty
+            {
+                return Err(format!(
+                    "{} must receive at least {} certifications",
+                    member.uid, self.currency_params.sig_qty
+                ));
+            }
+            if issued.get(&member.pubkey).copied().unwrap_or_default()
+                > self.currency_params.sig_stock
+            {
+                return Err(format!(
+                    "{} issues more than {} certifications",
+                    member.uid, self.currency_params.sig_stock
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Documents written in the genesis block refer to the blockstamp `0-<sha256 of "">`
+pub(crate) fn genesis_blockstamp() -> String {
+    format!("0-{}", Hash::compute(b"").to_hex().to_uppercase())
+}
+
+fn identity_text(currency: &str, pubkey: PublicKey, uid: &str) -> String {
+    format!(
+        "Version: 10\nType: Identity\nCurrency: {}\nIssuer: {}\nUniqueID: {}\nTimestamp: {}\n",
+        currency,
+        pubkey.to_base58(),
+        uid,
+        genesis_blockstamp()
+    )
+}
+
+fn membership_text(currency: &str, pubkey: PublicKey, uid: &str) -> String {
+    format!(
+        "Version: 10\nType: Membership\nCurrency: {}\nIssuer: {}\nBlock: {}\nMembership: IN\nUserID: {}\nCertTS: {}\n",
+        currency,
+        pubkey.to_base58(),
+        genesis_blockstamp(),
+        uid,
+        genesis_blockstamp()
+    )
+}
+
+/// DUBP: the hash of a block must start with `pow_min / 16` zeros, followed by a hexadecimal
+/// digit lower or equal to `15 - pow_min % 16`
+pub(crate) fn hash_matches_pow(hash: &Hash, pow_min: usize) -> bool {
+    let hex = hash.to_hex().to_uppercase();
+    let zeros = pow_min / 16;
+    let remainder = pow_min % 16;
+    if !hex.bytes().take(zeros).all(|c| c == b'0') {
+        return false;
+    }
+    remainder == 0
+        || hex
+            .chars()
+            .nth(zeros)
+            .and_then(|c| c.to_digit(16))
+            .map_or(false, |digit| digit as usize <= 15 - remainder)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use duniter_core::crypto::keys::ed25519::KeyPairFromSeed32Generator;
+    use duniter_core::crypto::seeds::Seed32;
+
+    pub(crate) fn keypair(seed: u8) -> Ed25519KeyPair {
+        KeyPairFromSeed32Generator::generate(Seed32::new([seed; 32]))
+    }
+
+    pub(crate) fn builder(keypairs: &[Ed25519KeyPair]) -> GenesisBlockBuilder {
+        let members: Vec<GenesisMember> = keypairs
+            .iter()
+            .enumerate()
+            .map(|(i, kp)| GenesisMember::sign("test", &format!("member{}", i), kp))
+            .collect();
+        let mut certs = Vec::new();
+        for issuer in keypairs {
+            for receiver in &members {
+                if issuer.public_key() != receiver.pubkey {
+                    certs.push(GenesisCert::sign("test", issuer, receiver));
+                }
+            }
+        }
+        GenesisBlockBuilder {
+            currency: "test".to_owned(),
+            currency_params: crate::currency::parse_genesis_parameters(
+                "test",
+                "0.0488:86400:1000:432000:100:5259600:63115200:2:5259600:5259600:0.8:31557600:5:24:300:12:0.67:1600000000:1600000000:15778800",
+            )
+            .expect("invalid params"),
+            time: 1_600_000_000,
+            pow_min: 0,
+            members,
+            certs,
+        }
+    }
+
+    #[test]
+    fn test_hash_matches_pow() {
+        let hash =
+            Hash::from_hex("00A0000000000000000000000000000000000000000000000000000000000000")
+                .expect("invalid hash");
+        assert!(hash_matches_pow(&hash, 0));
+        assert!(hash_matches_pow(&hash, 32));
+        assert!(hash_matches_pow(&hash, 37));
+        assert!(!hash_matches_pow(&hash, 38));
+        assert!(!hash_matches_pow(&hash, 48));
+    }
+
+    #[test]
+    fn test_build_genesis() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build_stringified(&keypairs[0])?;
+        assert_eq!(genesis.number, 0);
+        assert_eq!(genesis.members_count, 3);
+        assert_eq!(genesis.identities.len(), 3);
+        assert_eq!(genesis.certifications.len(), 6);
+
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        assert_eq!(server.receive_block(genesis)?, ForkChoice::Applied);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_genesis() {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+
+        // The issuer is not an initial member
+        assert!(builder(&keypairs).build(&keypair(4)).is_err());
+
+        // Not enough certifications
+        let mut genesis_builder = builder(&keypairs);
+        genesis_builder.certs.truncate(4);
+        assert!(genesis_builder.build(&keypairs[0]).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::wot_index::{CertWriteDbV1, MembershipWriteDbV1, WotV1DbReadable};
+use crate::*;
+use std::collections::HashMap;
+
+/// Written identity
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub pubkey: PublicKey,
+    pub uid: String,
+    pub is_member: bool,
+}
+
+/// Certification written in the blockchain and not expired
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrittenCert {
+    pub issuer: PublicKey,
+    pub receiver: PublicKey,
+    pub written_block: BlockNumber,
+    pub written_time: u64,
+    /// Median time from which the certification is expired
+    pub expires_on: u64,
+}
+
+impl DuniterServer {
+    pub fn get_identity_by_pubkey(
+        &self,
+        pubkey: PublicKey,
+    ) -> DuniterServerResult<Option<Identity>> {
+        Ok(self
+            .bc_db
+            .identities()
+            .get(&PubKeyKeyV2(pubkey))?
+            .map(|idty| Identity {
+                pubkey,
+                uid: idty.username,
+                is_member: idty.is_member,
+            }))
+    }
+    pub fn get_identity_by_uid(&self, uid: &str) -> DuniterServerResult<Option<Identity>> {
+        if let Some(pubkey) = self.bc_db.uids_index().get(&uid.to_owned())? {
+            self.get_identity_by_pubkey(pubkey.0)
+        } else {
+            Ok(None)
+        }
+    }
+    /// Certifications issued by `issuer`, from the oldest one
+    pub fn get_certs_from(&self, issuer: PublicKey) -> DuniterServerResult<Vec<WrittenCert>> {
+        let writes = crate::wot_index::read_writes(&self.wot_db.certs_by_issuer(), issuer)?;
+        Ok(self.valid_certs(writes, |receiver| (issuer, receiver)))
+    }
+    /// Certifications received by `receiver`, from the oldest one
+    pub fn get_certs_to(&self, receiver: PublicKey) -> DuniterServerResult<Vec<WrittenCert>> {
+        let writes = crate::wot_index::read_writes(&self.wot_db.certs_by_receiver(), receiver)?;
+        Ok(self.valid_certs(writes, |issuer| (issuer, receiver)))
+    }
+    /// Median time from which the membership of `pubkey` is expired, if it is a member
+    pub fn get_membership_expiry(&self, pubkey: PublicKey) -> DuniterServerResult<Option<u64>> {
+        if !self
+            .get_identity_by_pubkey(pubkey)?
+            .map_or(false, |idty| idty.is_member)
+        {
+            return Ok(None);
+        }
+        let writes: Vec<MembershipWriteDbV1> =
+            crate::wot_index::read_writes(&self.wot_db.memberships(), pubkey)?;
+        Ok(writes
+            .iter()
+            .map(|write| write.written_time + self.currency_params.ms_validity)
+            .max())
+    }
+    /// Last write of each certification, if not expired at the median time of the current block
+    fn valid_certs(
+        &self,
+        writes: Vec<CertWriteDbV1>,
+        issuer_and_receiver: impl Fn(PublicKey) -> (PublicKey, PublicKey),
+    ) -> Vec<WrittenCert> {
+        let median_time = self.current.map_or(0, |current| current.median_time);
+        let mut last_writes = HashMap::new();
+        for write in writes {
+            last_writes.insert(write.pubkey, write);
+        }
+        let mut certs: Vec<WrittenCert> = last_writes
+            .into_iter()
+            .map(|(pubkey, write)| {
+                let (issuer, receiver) = issuer_and_receiver(pubkey);
+                WrittenCert {
+                    issuer,
+                    receiver,
+                    written_block: BlockNumber(write.written_block),
+                    written_time: write.written_time,
+                    expires_on: write.written_time + self.currency_params.sig_validity,
+                }
+            })
+            .filter(|cert| cert.expires_on > median_time)
+            .collect();
+        certs.sort_unstable_by_key(|cert| (cert.written_block, cert.issuer, cert.receiver));
+        certs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_identity() -> anyhow::Result<()> {
+        let server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+
+        assert_eq!(server.get_identity_by_pubkey(PublicKey::default())?, None);
+        assert_eq!(server.get_identity_by_uid("toto")?, None);
+        assert_eq!(server.get_certs_from(PublicKey::default())?, vec![]);
+        assert_eq!(server.get_certs_to(PublicKey::default())?, vec![]);
+        assert_eq!(server.get_membership_expiry(PublicKey::default())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_written_identities() -> anyhow::Result<()> {
+        use crate::genesis::tests::{builder, keypair};
+
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let builder = builder(&keypairs);
+        let genesis = builder.build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(genesis.clone())?;
+        let pubkey = |i: usize| keypairs[i].public_key();
+
+        let idty = Identity {
+            pubkey: pubkey(1),
+            uid: "member1".to_owned(),
+            is_member: true,
+        };
+        assert_eq!(
+            server.get_identity_by_pubkey(pubkey(1))?,
+            Some(idty.clone())
+        );
+        assert_eq!(server.get_identity_by_uid("member1")?, Some(idty));
+
+        let cert = |issuer: usize, receiver: usize| WrittenCert {
+            issuer: pubkey(issuer),
+            receiver: pubkey(receiver),
+            written_block: BlockNumber(0),
+            written_time: genesis.median_time,
+            expires_on: genesis.median_time + builder.currency_params.sig_validity,
+        };
+        let mut certs_from = vec![cert(1, 0), cert(1, 2)];
+        certs_from.sort_unstable_by_key(|cert| cert.receiver);
+        assert_eq!(server.get_certs_from(pubkey(1))?, certs_from);
+        let mut certs_to = vec![cert(0, 1), cert(2, 1)];
+        certs_to.sort_unstable_by_key(|cert| cert.issuer);
+        assert_eq!(server.get_certs_to(pubkey(1))?, certs_to);
+        assert_eq!(
+            server.get_membership_expiry(pubkey(1))?,
+            Some(genesis.median_time + builder.currency_params.ms_validity)
+        );
+
+        // Reverted, the genesis block leaves no certification nor membership
+        server.revert_block(genesis)?;
+        assert_eq!(server.get_identity_by_pubkey(pubkey(1))?, None);
+        assert_eq!(server.get_certs_from(pubkey(1))?, vec![]);
+        assert_eq!(server.get_certs_to(pubkey(1))?, vec![]);
+        assert_eq!(server.get_membership_expiry(pubkey(1))?, None);
+
+        Ok(())
+    }
+}
@@ -16,55 +16,66 @@
 use crate::*;
 
 impl DuniterServer {
-    pub fn apply_block(&mut self, block: DubpBlockV10Stringified) -> KvResult<()> {
-        let block = Arc::new(
-            DubpBlockV10::from_string_object(&block).map_err(|e| KvError::DeserError(e.into()))?,
-        );
-
-        // Get currency parameters from genesis block
-        if let Some(currency_params) = block.currency_parameters() {
-            self.currency_params = currency_params;
-        }
-
-        self.current = Some(duniter_core::dbs_write_ops::apply_block::apply_block(
-            &self.bc_db,
-            block.clone(),
-            self.current,
-            &self.dbs_pool,
-            &self.global_sender,
-            false,
-        )?);
-        apply_block_modules(
-            block,
-            Arc::new(self.conf.clone()),
-            self.currency_params,
-            &self.dbs_pool,
-            self.profile_path_opt.clone(),
-        )
+    pub fn apply_block(&mut self, block: DubpBlockV10Stringified) -> DuniterServerResult<()> {
+        let block =
+            Arc::new(DubpBlockV10::from_string_object(&block).map_err(DuniterServerError::deser)?);
+        self.apply_block_inner(block)
     }
-    pub fn apply_chunk_of_blocks(&mut self, blocks: Vec<DubpBlockV10Stringified>) -> KvResult<()> {
+    pub fn apply_chunk_of_blocks(
+        &mut self,
+        blocks: Vec<DubpBlockV10Stringified>,
+    ) -> DuniterServerResult<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
         log::debug!("apply_chunk(#{})", blocks[0].number);
+        self.cancel_block_proof();
 
         let blocks = Arc::from(
             blocks
                 .into_iter()
                 .map(|block| DubpBlockV10::from_string_object(&block))
                 .collect::<Result<Vec<_>, _>>()
-                .map_err(|e| KvError::DeserError(e.into()))?,
+                .map_err(DuniterServerError::deser)?,
         );
 
         // Get currency parameters from genesis block
         if let Some(currency_params) = blocks[0].currency_parameters() {
             self.currency_params = currency_params;
         }
+        let mut included_txs = Vec::new();
+        for block in blocks.iter() {
+            included_txs.extend(self.mark_txs_included_in_block(block)?);
+        }
 
-        self.current = Some(duniter_core::dbs_write_ops::apply_block::apply_chunk(
+        self.block_journal.begin_chunk(
+            crate::block_journal::BlockJournalOp::Apply,
+            &blocks,
+            self.modules_current()?,
+        )?;
+        match duniter_core::dbs_write_ops::apply_block::apply_chunk(
             &self.bc_db,
             self.current,
             &self.dbs_pool,
             blocks.clone(),
             Some(&self.global_sender),
-        )?);
+        ) {
+            Ok(current) => self.current = Some(current),
+            Err(e) => {
+                self.mempool_event_bus.forget_removal_reasons(&included_txs);
+                return Err(e.into());
+            }
+        }
+        let fork_window_start = blocks
+            .len()
+            .saturating_sub(self.fork_tree.window_size() as usize);
+        for block in &blocks[fork_window_start..] {
+            self.fork_tree.push_main(Arc::new(block.clone()));
+        }
+        for block in blocks.iter() {
+            crate::wot_index::apply_block(&self.wot_db, block)?;
+            crate::wot_mempool::remove_written(&self.wot_mp_db, block)?;
+        }
         apply_chunk_of_blocks_modules(
             blocks,
             Arc::new(self.conf.clone()),
@@ -72,11 +83,177 @@ impl DuniterServer {
             &self.dbs_pool,
             self.profile_path_opt.clone(),
         )
+        .map_err(DuniterServerError::module)?;
+        self.block_journal.end()?;
+        Ok(())
+    }
+    pub fn revert_block(&mut self, block: DubpBlockV10Stringified) -> DuniterServerResult<()> {
+        let block =
+            Arc::new(DubpBlockV10::from_string_object(&block).map_err(DuniterServerError::deser)?);
+        self.revert_block_inner(block)
     }
-    pub fn revert_block(&mut self, block: DubpBlockV10Stringified) -> KvResult<()> {
-        let block = Arc::new(
-            DubpBlockV10::from_string_object(&block).map_err(|e| KvError::DeserError(e.into()))?,
+    /// Revert `blocks`, a contiguous range ending at the current block, as a single journaled
+    /// operation
+    pub fn revert_chunk_of_blocks(
+        &mut self,
+        blocks: Vec<DubpBlockV10Stringified>,
+    ) -> DuniterServerResult<()> {
+        let mut blocks = blocks
+            .into_iter()
+            .map(|block| DubpBlockV10::from_string_object(&block))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DuniterServerError::deser)?;
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        // Revert from the highest block to the lowest one
+        blocks.sort_unstable_by(|b1, b2| b2.number().cmp(&b1.number()));
+        log::debug!(
+            "revert_chunk(#{}-#{})",
+            blocks[0].number(),
+            blocks[blocks.len() - 1].number()
         );
+
+        let top = blocks[0].blockstamp();
+        if self.current.map(|current| (current.number, current.hash))
+            != Some((top.number.0, top.hash.0))
+        {
+            return Err(DuniterServerError::Validation(format!(
+                "block #{} is not the current block",
+                top
+            )));
+        }
+        if let Some(pair) = blocks
+            .windows(2)
+            .find(|pair| pair[0].previous_blockstamp() != pair[1].blockstamp())
+        {
+            return Err(DuniterServerError::Validation(format!(
+                "block #{} doesn't follow block #{}",
+                pair[0].blockstamp(),
+                pair[1].blockstamp()
+            )));
+        }
+
+        self.cancel_block_proof();
+        self.block_journal.begin_chunk(
+            crate::block_journal::BlockJournalOp::Revert,
+            &blocks,
+            self.modules_current()?,
+        )?;
+        let blocks: Arc<[DubpBlockV10]> = Arc::from(blocks);
+        self.revert_blocks_in_bc(Arc::clone(&blocks))?;
+        self.revert_blocks_in_modules(blocks)?;
+        self.block_journal.end()?;
+
+        // Genesis block reverted: currency parameters are no longer known
+        if self.current.is_none() {
+            self.currency_params = CurrencyParameters::default();
+        }
+
+        Ok(())
+    }
+    /// Revert `blocks`, from the highest one, in bc_v2 and the txs mempool
+    pub(crate) fn revert_blocks_in_bc(
+        &mut self,
+        blocks: Arc<[DubpBlockV10]>,
+    ) -> DuniterServerResult<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let blocks_arc_clone = Arc::clone(&blocks);
+        let txs_mp_job_handle = self
+            .dbs_pool
+            .launch(move |dbs| {
+                for block in blocks_arc_clone.iter() {
+                    duniter_core::dbs_write_ops::txs_mp::revert_block(
+                        block.transactions(),
+                        &dbs.txs_mp_db,
+                    )?;
+                }
+                Ok::<_, KvError>(())
+            })
+            .expect("dbs pool disconnected");
+        for block in blocks.iter() {
+            self.current = duniter_core::dbs_write_ops::bc::revert_block(&self.bc_db, block)?;
+            self.fork_tree.pop_main(block.number());
+        }
+        txs_mp_job_handle.join().expect("dbs pool disconnected")?;
+        Ok(())
+    }
+    /// Revert `blocks`, from the highest one, in the dbs of the modules
+    pub(crate) fn revert_blocks_in_modules(
+        &self,
+        blocks: Arc<[DubpBlockV10]>,
+    ) -> DuniterServerResult<()> {
+        let conf = Arc::new(self.conf.clone());
+        for block in blocks.iter() {
+            crate::wot_index::revert_block(&self.wot_db, block)?;
+            revert_block_modules(
+                Arc::new(block.clone()),
+                Arc::clone(&conf),
+                self.currency_params,
+                &self.dbs_pool,
+                None,
+            )
+            .map_err(DuniterServerError::module)?;
+        }
+        Ok(())
+    }
+    pub(crate) fn apply_block_inner(
+        &mut self,
+        block: Arc<DubpBlockV10>,
+    ) -> DuniterServerResult<()> {
+        // The block in proof no longer follows the current block
+        self.cancel_block_proof();
+
+        // Get currency parameters from genesis block
+        if let Some(currency_params) = block.currency_parameters() {
+            self.currency_params = currency_params;
+        }
+        self.block_journal.begin(
+            crate::block_journal::BlockJournalOp::Apply,
+            &block,
+            self.modules_current()?,
+        )?;
+        let included_txs = self.mark_txs_included_in_block(&block)?;
+        match duniter_core::dbs_write_ops::apply_block::apply_block(
+            &self.bc_db,
+            block.clone(),
+            self.current,
+            &self.dbs_pool,
+            &self.global_sender,
+            false,
+        ) {
+            Ok(current) => self.current = Some(current),
+            Err(e) => {
+                self.mempool_event_bus.forget_removal_reasons(&included_txs);
+                return Err(e.into());
+            }
+        }
+        self.fork_tree.push_main(block.clone());
+        crate::wot_index::apply_block(&self.wot_db, &block)?;
+        crate::wot_mempool::remove_written(&self.wot_mp_db, &block)?;
+        apply_block_modules(
+            block,
+            Arc::new(self.conf.clone()),
+            self.currency_params,
+            &self.dbs_pool,
+            self.profile_path_opt.clone(),
+        )
+        .map_err(DuniterServerError::module)?;
+        self.block_journal.end()?;
+        Ok(())
+    }
+    pub(crate) fn revert_block_inner(
+        &mut self,
+        block: Arc<DubpBlockV10>,
+    ) -> DuniterServerResult<()> {
+        self.cancel_block_proof();
+        self.block_journal.begin(
+            crate::block_journal::BlockJournalOp::Revert,
+            &block,
+            self.modules_current()?,
+        )?;
         let block_arc_clone = Arc::clone(&block);
         let txs_mp_job_handle = self
             .dbs_pool
@@ -88,7 +265,9 @@ impl DuniterServer {
             })
             .expect("dbs pool disconnected");
         self.current = duniter_core::dbs_write_ops::bc::revert_block(&self.bc_db, &block)?;
+        self.fork_tree.pop_main(block.number());
         txs_mp_job_handle.join().expect("dbs pool disconnected")?;
+        crate::wot_index::revert_block(&self.wot_db, &block)?;
         revert_block_modules(
             block,
             Arc::new(self.conf.clone()),
@@ -96,5 +275,115 @@ impl DuniterServer {
             &self.dbs_pool,
             None,
         )
+        .map_err(DuniterServerError::module)?;
+        self.block_journal.end()?;
+
+        // Genesis block reverted: currency parameters are no longer known
+        if self.current.is_none() {
+            self.currency_params = CurrencyParameters::default();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_candidate::tests::forge_block;
+    use crate::genesis::tests::{builder, keypair};
+    use duniter_core::dbs::U32BE;
+
+    /// Server with 3 blocks after the genesis, and these blocks from the genesis
+    fn server_with_chain() -> anyhow::Result<(DuniterServer, Vec<DubpBlockV10Stringified>)> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(genesis.clone())?;
+        let mut chain = vec![genesis];
+        for (i, keypair) in keypairs.iter().enumerate() {
+            let block = forge_block(&server, keypair, chain[0].time + 300 * (i as u64 + 1))?;
+            server.apply_block(block.clone())?;
+            chain.push(block);
+        }
+        Ok((server, chain))
+    }
+
+    #[test]
+    fn test_revert_chunk_of_blocks() -> anyhow::Result<()> {
+        let (mut server, chain) = server_with_chain()?;
+
+        // The order of the blocks doesn't matter
+        server.revert_chunk_of_blocks(vec![
+            chain[2].clone(),
+            chain[3].clone(),
+            chain[1].clone(),
+        ])?;
+        assert_eq!(
+            server
+                .get_current_blockstamp()
+                .map(|current| current.number),
+            Some(BlockNumber(0))
+        );
+        assert!(server.bc_db.blocks_meta().get(&U32BE(1))?.is_none());
+
+        // Reverted blocks can be applied again, the genesis can be reverted
+        server.apply_block(chain[1].clone())?;
+        server.revert_chunk_of_blocks(chain[..2].to_vec())?;
+        assert_eq!(server.get_current_blockstamp(), None);
+        assert!(server.bc_db.blocks_meta().get(&U32BE(0))?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_revert_genesis_block() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let mut genesis_builder = builder(&keypairs);
+        genesis_builder.currency_params.sig_stock = 2;
+        let genesis = genesis_builder.build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(genesis.clone())?;
+        assert_eq!(server.currency_params.sig_stock, 2);
+
+        server.revert_block(genesis)?;
+        assert_eq!(server.get_current_blockstamp(), None);
+        assert_eq!(
+            server.currency_params.sig_stock,
+            CurrencyParameters::default().sig_stock
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_revert_chunk_of_blocks_of_another_branch() -> anyhow::Result<()> {
+        let (mut server, chain) = server_with_chain()?;
+        let current = server.get_current_blockstamp();
+
+        // Blocks #2 and #3 of another branch
+        let (mut other_server, _) = server_with_chain()?;
+        other_server.revert_chunk_of_blocks(chain[2..].to_vec())?;
+        let keypair = keypair(2);
+        let other_2 = forge_block(&other_server, &keypair, chain[0].time + 700)?;
+        other_server.apply_block(other_2.clone())?;
+        let other_3 = forge_block(&other_server, &keypair, chain[0].time + 1000)?;
+
+        // The highest block is not the current block
+        assert!(matches!(
+            server.revert_chunk_of_blocks(vec![chain[2].clone(), other_3]),
+            Err(DuniterServerError::Validation(_))
+        ));
+        // The blocks are not chained
+        assert!(matches!(
+            server.revert_chunk_of_blocks(vec![other_2, chain[3].clone()]),
+            Err(DuniterServerError::Validation(_))
+        ));
+
+        // Nothing has been reverted
+        assert_eq!(server.get_current_blockstamp(), current);
+        assert!(server.bc_db.blocks_meta().get(&U32BE(3))?.is_some());
+
+        Ok(())
     }
 }
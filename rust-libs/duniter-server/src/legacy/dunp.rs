@@ -16,14 +16,15 @@
 use crate::*;
 
 impl DuniterServer {
-    pub fn get_self_endpoints(&self) -> anyhow::Result<Vec<Endpoint>> {
+    pub fn get_self_endpoints(&self) -> DuniterServerResult<Vec<Endpoint>> {
         // Do not get rust endpoints on js tests or when gva is disabled
         if std::env::var_os("DUNITER_JS_TESTS") != Some("yes".into()) {
             let (sender, recv) = flume::bounded(1);
             loop {
                 self.global_sender
-                    .send(GlobalBackGroundTaskMsg::GetSelfEndpoints(sender.clone()))?;
-                if let Some(self_endpoints) = recv.recv()? {
+                    .send(GlobalBackGroundTaskMsg::GetSelfEndpoints(sender.clone()))
+                    .map_err(DuniterServerError::module)?;
+                if let Some(self_endpoints) = recv.recv().map_err(DuniterServerError::module)? {
                     break Ok(self_endpoints);
                 } else {
                     std::thread::sleep(std::time::Duration::from_millis(100));
@@ -39,7 +40,7 @@ impl DuniterServer {
             duniter_core::dbs::DunpNodeIdV1Db,
             duniter_core::dbs::DunpHeadDbV1,
         )>,
-    ) -> KvResult<()> {
+    ) -> DuniterServerResult<()> {
         self.dbs_pool
             .execute(move |dbs| {
                 for (dunp_node_id, dunp_head) in heads {
@@ -50,20 +51,23 @@ impl DuniterServer {
                 Ok::<(), KvError>(())
             })
             .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
     }
-    pub fn remove_all_peers(&self) -> KvResult<()> {
+    pub fn remove_all_peers(&self) -> DuniterServerResult<()> {
         use duniter_core::dbs::databases::network_v1::NetworkV1DbWritable as _;
         self.dbs_pool
             .execute(move |dbs| dbs.dunp_db.peers_old_write().clear())
             .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
     }
-    pub fn remove_peer_by_pubkey(&self, pubkey: PublicKey) -> KvResult<()> {
+    pub fn remove_peer_by_pubkey(&self, pubkey: PublicKey) -> DuniterServerResult<()> {
         use duniter_core::dbs::databases::network_v1::NetworkV1DbWritable as _;
         self.dbs_pool
             .execute(move |dbs| dbs.dunp_db.peers_old_write().remove(PubKeyKeyV2(pubkey)))
             .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
     }
-    pub fn save_peer(&self, new_peer_card: PeerCardDbV1) -> anyhow::Result<()> {
+    pub fn save_peer(&self, new_peer_card: PeerCardDbV1) -> DuniterServerResult<()> {
         let pubkey = new_peer_card.peer.pubkey;
         use duniter_core::dbs::databases::network_v1::NetworkV1DbWritable as _;
         self.dbs_pool
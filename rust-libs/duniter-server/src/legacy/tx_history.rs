@@ -14,16 +14,47 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::*;
+use resiter::flatten::Flatten as _;
+use std::collections::HashMap;
 
 impl DuniterServer {
-    pub fn get_transactions_history(&self, pubkey: PublicKey) -> KvResult<TxsHistoryForBma> {
-        get_transactions_history_for_bma(&self.dbs_pool, self.profile_path_opt.as_deref(), pubkey)
+    /// The received time of pending transactions is the one given by the clock of the server
+    pub fn get_transactions_history(
+        &self,
+        pubkey: PublicKey,
+    ) -> DuniterServerResult<TxsHistoryForBma> {
+        let mut history = get_transactions_history_for_bma(
+            &self.dbs_pool,
+            self.profile_path_opt.as_deref(),
+            pubkey,
+        )?;
+        if !history.sending.is_empty() || !history.pending.is_empty() {
+            let received_times = self
+                .dbs_pool
+                .execute(|dbs| {
+                    dbs.txs_mp_db.txs_by_received_time().iter(.., |it| {
+                        it.map_ok(|(received_time, hashs)| {
+                            hashs.0.into_iter().map(move |hash| (hash, received_time))
+                        })
+                        .flatten_ok()
+                        .collect::<KvResult<HashMap<Hash, i64>>>()
+                    })
+                })
+                .expect("dbs pool disconnected")?;
+            for (tx, received_time) in history.sending.iter_mut().chain(history.pending.iter_mut())
+            {
+                if let Some(time) = received_times.get(&tx.get_hash()) {
+                    *received_time = *time;
+                }
+            }
+        }
+        Ok(history)
     }
 
     pub fn get_tx_by_hash(
         &self,
         hash: Hash,
-    ) -> KvResult<Option<(TransactionDocumentV10, Option<BlockNumber>)>> {
-        get_tx_by_hash(&self.dbs_pool, hash, self.profile_path_opt.as_deref())
+    ) -> DuniterServerResult<Option<(TransactionDocumentV10, Option<BlockNumber>)>> {
+        get_tx_by_hash(&self.dbs_pool, hash, self.profile_path_opt.as_deref()).map_err(|e| e.into())
     }
 }
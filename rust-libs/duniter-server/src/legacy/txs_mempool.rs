@@ -16,91 +16,52 @@
 use crate::*;
 
 impl DuniterServer {
-    pub fn accept_new_tx(
-        &self,
-        tx: TransactionDocumentV10,
-        server_pubkey: PublicKey,
-    ) -> KvResult<bool> {
+    pub fn add_pending_tx_force(&self, tx: TransactionDocumentV10) -> DuniterServerResult<()> {
         let txs_mempool = self.txs_mempool;
-        match self
-            .dbs_pool
+        let received_time = self.clock.now();
+        self.dbs_pool
             .execute(move |dbs| {
-                txs_mempool.accept_new_tx(&dbs.bc_db_ro, server_pubkey, tx, &dbs.txs_mp_db)
+                let inserted_since = SystemClock.now();
+                txs_mempool.add_pending_tx_force(&dbs.txs_mp_db, &tx)?;
+                crate::mempool::set_received_time(
+                    &dbs.txs_mp_db,
+                    tx.get_hash(),
+                    inserted_since,
+                    received_time,
+                )
             })
-            .expect("dbs pool discorrected")
-        {
-            Ok(()) => Ok(true),
-            Err(TxMpError::Db(e)) => Err(e),
-            Err(_) => Ok(false),
-        }
-    }
-    pub fn add_pending_tx_force(&self, tx: TransactionDocumentV10) -> KvResult<()> {
-        let txs_mempool = self.txs_mempool;
-        self.dbs_pool
-            .execute(move |dbs| txs_mempool.add_pending_tx_force(&dbs.txs_mp_db, &tx))
             .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
     }
-    pub fn get_mempool_txs_free_rooms(&self) -> KvResult<usize> {
+    pub fn get_mempool_txs_free_rooms(&self) -> DuniterServerResult<usize> {
         let txs_mempool = self.txs_mempool;
         self.dbs_pool
             .execute(move |dbs| txs_mempool.get_free_rooms(&dbs.txs_mp_db))
             .expect("dbs pool discorrected")
+            .map_err(|e| e.into())
     }
-    pub fn get_new_pending_txs(&self) -> KvResult<Vec<TransactionDocumentV10>> {
+    pub fn get_new_pending_txs(&self) -> DuniterServerResult<Vec<TransactionDocumentV10>> {
         let mut new_pending_txs = BTreeMap::new();
-        for events in self.pending_txs_subscriber.drain() {
-            use std::ops::Deref as _;
-            for event in events.deref() {
-                match event {
-                    duniter_core::dbs::databases::txs_mp_v2::TxsEvent::Upsert { key, value } => {
-                        new_pending_txs.insert(key.0, value.doc.clone());
-                    }
-                    duniter_core::dbs::databases::txs_mp_v2::TxsEvent::Remove { key } => {
-                        new_pending_txs.remove(&key.0);
-                    }
-                    _ => (),
+        for event in self.new_pending_txs_recv.drain() {
+            match event {
+                MempoolEvent::TxAdded(tx) => {
+                    new_pending_txs.insert(tx.get_hash(), (*tx).clone());
+                }
+                MempoolEvent::TxRemoved(hash)
+                | MempoolEvent::TxIncludedInBlock { hash, .. }
+                | MempoolEvent::TxExpired(hash) => {
+                    new_pending_txs.remove(&hash);
                 }
             }
         }
         Ok(new_pending_txs.into_values().collect())
     }
-    pub fn get_pending_txs(
-        &self,
-        _blockchain_time: i64,
-        min_version: usize,
-    ) -> KvResult<Vec<PendingTxDbV2>> {
-        self.dbs_pool
-            .execute(move |dbs| {
-                dbs.txs_mp_db.txs().iter(.., |it| {
-                    it.values()
-                        .filter_ok(|tx| tx.doc.version() >= min_version)
-                        .collect()
-                })
-            })
-            .expect("dbs pool disconnected")
-    }
-    pub fn remove_all_pending_txs(&self) -> KvResult<()> {
+    pub fn remove_all_pending_txs(&self) -> DuniterServerResult<()> {
         self.dbs_pool
             .execute(move |dbs| {
                 duniter_core::dbs_write_ops::txs_mp::remove_all_pending_txs(&dbs.txs_mp_db)
             })
             .expect("dbs pool disconnected")
-    }
-    pub fn remove_pending_tx_by_hash(&self, hash: Hash) -> KvResult<()> {
-        self.dbs_pool
-            .execute(move |dbs| {
-                duniter_core::dbs_write_ops::txs_mp::remove_pending_tx_by_hash(&dbs.txs_mp_db, hash)
-            })
-            .expect("dbs pool disconnected")
-    }
-    pub fn trim_expired_non_written_txs(&self, limit_time: i64) -> KvResult<()> {
-        self.dbs_pool
-            .execute(move |dbs| {
-                duniter_core::dbs_write_ops::txs_mp::trim_expired_non_written_txs(
-                    &dbs.txs_mp_db,
-                    limit_time,
-                )
-            })
-            .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
     }
 }
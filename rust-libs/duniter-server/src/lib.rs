@@ -22,9 +22,46 @@
     unused_import_braces
 )]
 
+mod block_candidate;
+mod block_journal;
+mod clock;
+mod currency;
+mod difficulty;
+mod error;
 mod fill_cm;
+mod fork_tree;
+mod genesis;
+mod identities;
 mod legacy;
+mod mempool;
+mod pow;
+mod raw_block;
+mod txs_history;
+mod ud;
+mod validation;
+mod wallet;
+mod wot_index;
+mod wot_mempool;
 
+pub use crate::clock::{Clock, SimulatedClock, SystemClock};
+pub use crate::currency::{
+    check_currency_params, genesis_parameters_string, parse_genesis_parameters,
+};
+pub use crate::difficulty::PersonalizedDifficulty;
+pub use crate::error::{DuniterServerError, DuniterServerResult};
+pub use crate::fork_tree::{ForkChoice, DEFAULT_FORK_WINDOW_SIZE};
+pub use crate::genesis::{GenesisBlockBuilder, GenesisCert, GenesisMember};
+pub use crate::identities::{Identity, WrittenCert};
+pub use crate::mempool::{
+    MempoolEvent, MempoolImportReport, MempoolSubscriptionId, TxAcceptance, TxsMempoolEviction,
+    TxsMempoolPolicy,
+};
+pub use crate::pow::{prove_block, PowConf, PowHandle, MAX_POW_PREFIX};
+pub use crate::txs_history::{TxsHistoryCursor, TxsHistoryPage, TxsHistoryRange};
+pub use crate::ud::{UdEntry, UdProjection};
+pub use crate::validation::RuleViolation;
+pub use crate::wallet::{UtxosPage, WalletSource};
+pub use crate::wot_index::{CertWriteDbV1, MembershipWriteDbV1, WotV1Db, WritesDbV1};
 pub use duniter_core::conf::{DuniterCoreConf, DuniterMode};
 use duniter_core::dbs::databases::{bc_v2::BcV2DbReadable, network_v1::NetworkV1DbWritable};
 pub use duniter_core::dbs::{
@@ -34,7 +71,10 @@ pub use duniter_gva::GvaModule;
 
 use anyhow::Context;
 use duniter_core::common::prelude::*;
-use duniter_core::common::{crypto::keys::ed25519::PublicKey, currency_params::CurrencyParameters};
+use duniter_core::common::{
+    crypto::keys::{ed25519::PublicKey, KeyPair as _},
+    currency_params::CurrencyParameters,
+};
 use duniter_core::dbs::{
     databases::{bc_v2::BcV2Db, txs_mp_v2::TxsMpV2DbReadable},
     kv_typed::prelude::*,
@@ -64,36 +104,111 @@ plug_duniter_modules!([GvaModule], TxsHistoryForBma);
 
 pub struct DuniterServer {
     bc_db: BcV2Db<FileBackend>,
+    block_journal: block_journal::BlockJournal,
+    clock: Arc<dyn Clock>,
     conf: DuniterCoreConf,
+    currency: String,
     currency_params: CurrencyParameters,
     current: Option<BlockMetaV2>,
     dbs_pool: fast_threadpool::ThreadPoolSyncHandler<SharedDbs<FileBackend>>,
+    fork_tree: fork_tree::ForkTree,
     global_sender: flume::Sender<GlobalBackGroundTaskMsg>,
-    pending_txs_subscriber:
-        flume::Receiver<Arc<Events<duniter_core::dbs::databases::txs_mp_v2::TxsEvent>>>,
+    /// Kept by an offline server, which doesn't run the global background task, so that the
+    /// dbs writers can still notify it
+    _global_recv_opt: Option<flume::Receiver<GlobalBackGroundTaskMsg>>,
+    mempool_event_bus: mempool::MempoolEventBus,
+    /// Mempool events since the last call to `get_new_pending_txs`
+    new_pending_txs_recv: flume::Receiver<MempoolEvent>,
+    pow_stop: Option<Arc<std::sync::atomic::AtomicBool>>,
     profile_path_opt: Option<PathBuf>,
     shared_dbs: SharedDbs<FileBackend>,
     txs_mempool: TxsMempool,
+    txs_mempool_policy: TxsMempoolPolicy,
+    wot_db: wot_index::WotV1Db<FileBackend>,
+    wot_mp_db: wot_mempool::WotMpV1Db<FileBackend>,
 }
 
 impl DuniterServer {
     pub fn get_shared_dbs(&self) -> SharedDbs<FileBackend> {
         self.shared_dbs.clone()
     }
+    pub fn get_self_pubkey(&self) -> PublicKey {
+        self.conf.self_key_pair.public_key()
+    }
+    /// Current time according to the clock of the server
+    pub fn now(&self) -> i64 {
+        self.clock.now()
+    }
+    pub fn get_current_blockstamp(&self) -> Option<Blockstamp> {
+        self.current.map(|current| Blockstamp {
+            number: BlockNumber(current.number),
+            hash: BlockHash(current.hash),
+        })
+    }
     pub fn start(
         conf: DuniterCoreConf,
         currency: String,
         duniter_mode: DuniterMode,
         profile_path_opt: Option<&Path>,
         software_version: &'static str,
+    ) -> anyhow::Result<DuniterServer> {
+        Self::start_with_clock(
+            conf,
+            currency,
+            duniter_mode,
+            profile_path_opt,
+            software_version,
+            Arc::new(SystemClock),
+        )
+    }
+    /// Start the server with a custom clock, used for the received time of pending
+    /// transactions and their expiration
+    pub fn start_with_clock(
+        conf: DuniterCoreConf,
+        currency: String,
+        duniter_mode: DuniterMode,
+        profile_path_opt: Option<&Path>,
+        software_version: &'static str,
+        clock: Arc<dyn Clock>,
     ) -> anyhow::Result<DuniterServer> {
         log::info!("mode={:?}", duniter_mode);
-
+        Self::open(
+            conf,
+            currency,
+            Some((duniter_mode, software_version)),
+            profile_path_opt,
+            clock,
+        )
+    }
+    /// Open the databases of a stopped node without starting the modules nor the global
+    /// background task, for offline commands
+    pub fn open_offline(
+        conf: DuniterCoreConf,
+        currency: String,
+        profile_path_opt: Option<&Path>,
+    ) -> anyhow::Result<DuniterServer> {
+        Self::open(
+            conf,
+            currency,
+            None,
+            profile_path_opt,
+            Arc::new(SystemClock),
+        )
+    }
+    fn open(
+        conf: DuniterCoreConf,
+        currency: String,
+        modules_opt: Option<(DuniterMode, &'static str)>,
+        profile_path_opt: Option<&Path>,
+        clock: Arc<dyn Clock>,
+    ) -> anyhow::Result<DuniterServer> {
         let txs_mempool = TxsMempool::new(conf.txs_mempool_size);
 
         log::info!("open duniter databases...");
         let (bc_db, shared_dbs) = duniter_core::dbs::open_dbs(profile_path_opt)?;
         shared_dbs.dunp_db.heads_old_write().clear()?; // Clear WS2Pv1 HEADs
+        let wot_db = wot_index::open(profile_path_opt)?;
+        let wot_mp_db = wot_mempool::open(profile_path_opt)?;
 
         // Create channel with global async task
         let (global_sender, global_recv) = flume::unbounded();
@@ -105,63 +220,91 @@ impl DuniterServer {
         // Get currency parameters
         let currency_params = bc_db.currency_params().get(&())?.unwrap_or_default().params;
 
+        // Main chain of the fork window, applied before the restart
+        let fork_tree = fork_tree::ForkTree::load(&bc_db, fork_tree::DEFAULT_FORK_WINDOW_SIZE)?;
+
         if let Some(current) = current {
             log::info!("Current block: #{}-{}", current.number, current.hash);
         } else {
             log::info!("Current block: no blockchain");
         }
 
-        let (s, pending_txs_subscriber) = flume::unbounded();
+        let (s, txs_events_recv) = flume::unbounded();
         shared_dbs
             .txs_mp_db
             .txs()
             .subscribe(s)
             .context("Fail to subscribe to txs col")?;
+        let pending_txs = shared_dbs.txs_mp_db.txs().iter(.., |it| {
+            it.keys()
+                .map_ok(|duniter_core::dbs::HashKeyV2(hash)| hash)
+                .collect::<KvResult<_>>()
+        })?;
+        let mempool_event_bus = mempool::MempoolEventBus::start(pending_txs, txs_events_recv);
+        let (_, new_pending_txs_recv) = mempool_event_bus.subscribe();
 
         log::info!("start dbs threadpool...");
 
         let threadpool =
             fast_threadpool::ThreadPool::start(ThreadPoolConfig::default(), shared_dbs.clone());
-
-        // Start async runtime
-        let conf_clone = conf.clone();
-        let profile_path_opt_clone = profile_path_opt.map(ToOwned::to_owned);
         let threadpool_async_handler = threadpool.async_handler();
-        std::thread::spawn(move || {
-            duniter_core::global::get_async_runtime().block_on(async {
-                // Start global background task
-                duniter_core::global::start_global_background_task(global_recv).await;
-
-                // Start duniter modules
-                log::info!("start duniter modules...");
-                start_duniter_modules(
-                    &conf_clone,
-                    currency,
-                    threadpool_async_handler,
-                    Mempools { txs: txs_mempool },
-                    duniter_mode,
-                    profile_path_opt_clone,
-                    software_version,
-                )
-                .await
-                .expect("Fail to start duniter modules");
-            });
-        });
 
-        log::info!("Duniter sever started.");
-
-        Ok(DuniterServer {
+        let mut server = DuniterServer {
             bc_db,
-            conf,
+            block_journal: block_journal::BlockJournal::new(profile_path_opt),
+            clock,
+            conf: conf.clone(),
+            currency: currency.clone(),
             current,
             currency_params,
             dbs_pool: threadpool.into_sync_handler(),
+            fork_tree,
             global_sender,
-            pending_txs_subscriber,
+            _global_recv_opt: None,
+            mempool_event_bus,
+            new_pending_txs_recv,
+            pow_stop: None,
             profile_path_opt: profile_path_opt.map(ToOwned::to_owned),
             shared_dbs,
             txs_mempool,
-        })
+            txs_mempool_policy: TxsMempoolPolicy::default(),
+            wot_db,
+            wot_mp_db,
+        };
+        // A block interrupted by a crash may be only in some of the dbs, the modules must not
+        // start before they agree again
+        server.recover_block_journal()?;
+
+        // Start async runtime
+        if let Some((duniter_mode, software_version)) = modules_opt {
+            let profile_path_opt_clone = profile_path_opt.map(ToOwned::to_owned);
+            std::thread::spawn(move || {
+                duniter_core::global::get_async_runtime().block_on(async {
+                    // Start global background task
+                    duniter_core::global::start_global_background_task(global_recv).await;
+
+                    // Start duniter modules
+                    log::info!("start duniter modules...");
+                    start_duniter_modules(
+                        &conf,
+                        currency,
+                        threadpool_async_handler,
+                        Mempools { txs: txs_mempool },
+                        duniter_mode,
+                        profile_path_opt_clone,
+                        software_version,
+                    )
+                    .await
+                    .expect("Fail to start duniter modules");
+                });
+            });
+        } else {
+            server._global_recv_opt = Some(global_recv);
+        }
+
+        log::info!("Duniter sever started.");
+
+        Ok(server)
     }
     #[cfg(test)]
     pub(crate) fn test(
@@ -0,0 +1,722 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+mod chaining;
+mod events;
+mod eviction;
+mod persistence;
+mod time_filter;
+
+pub(crate) use events::MempoolEventBus;
+pub use events::{MempoolEvent, MempoolSubscriptionId};
+pub use eviction::{TxsMempoolEviction, TxsMempoolPolicy};
+pub(crate) use persistence::set_received_time;
+pub use persistence::MempoolImportReport;
+pub(crate) use time_filter::{blockstamp_time_ok, inputs_unlockable};
+
+use crate::*;
+use duniter_core::dbs::{databases::bc_v2::BcV2DbRo, HashKeyV2, UdIdV2, UtxoIdDbV2};
+use duniter_core::documents::transaction::{SourceIdV10, TransactionDocumentTrait};
+use resiter::flatten::Flatten as _;
+use std::collections::{BTreeSet, HashMap};
+
+/// Result of the submission of a new transaction to the mempool
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxAcceptance {
+    /// The transaction has been added to the mempool
+    Accepted,
+    /// The mempool is full
+    MempoolFull,
+    /// The transaction is already in the mempool or in the blockchain
+    AlreadyKnown,
+    /// At least one input refers to a source that doesn't exist
+    UnknownSource,
+    /// The transaction is not signed by all its issuers
+    InvalidSignature,
+    /// The transaction is for another currency
+    WrongCurrency,
+    /// The transaction is malformed, for example its inputs and outputs don't balance
+    InvalidDocument,
+    /// The mempool is full of transactions with a higher priority
+    TooLowPriority,
+    /// An issuer has too many pending transactions
+    IssuerQuotaExceeded,
+}
+
+impl TxAcceptance {
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Accepted => "ACCEPTED",
+            Self::MempoolFull => "MEMPOOL_FULL",
+            Self::AlreadyKnown => "ALREADY_KNOWN",
+            Self::UnknownSource => "UNKNOWN_SOURCE",
+            Self::InvalidSignature => "INVALID_SIGNATURE",
+            Self::WrongCurrency => "WRONG_CURRENCY",
+            Self::InvalidDocument => "INVALID_DOCUMENT",
+            Self::TooLowPriority => "TOO_LOW_PRIORITY",
+            Self::IssuerQuotaExceeded => "ISSUER_QUOTA_EXCEEDED",
+        }
+    }
+    pub fn is_accepted(self) -> bool {
+        self == Self::Accepted
+    }
+}
+
+impl DuniterServer {
+    pub fn set_txs_mempool_policy(&mut self, txs_mempool_policy: TxsMempoolPolicy) {
+        self.txs_mempool_policy = txs_mempool_policy;
+    }
+    pub fn accept_new_tx(
+        &self,
+        tx: TransactionDocumentV10,
+        server_pubkey: PublicKey,
+    ) -> DuniterServerResult<TxAcceptance> {
+        self.accept_new_tx_received_at(tx, server_pubkey, self.clock.now())
+    }
+    pub(crate) fn accept_new_tx_received_at(
+        &self,
+        tx: TransactionDocumentV10,
+        server_pubkey: PublicKey,
+        received_time: i64,
+    ) -> DuniterServerResult<TxAcceptance> {
+        // A node that isn't synced yet doesn't know its currency
+        if !self.currency.is_empty() && tx.currency() != self.currency {
+            return Ok(TxAcceptance::WrongCurrency);
+        }
+        if let Err(e) = tx.verify(None) {
+            log::debug!("invalid tx {}: {:?}", tx.get_hash(), e);
+            return Ok(if tx.verify_signatures().is_err() {
+                TxAcceptance::InvalidSignature
+            } else {
+                TxAcceptance::InvalidDocument
+            });
+        }
+
+        let txs_mempool = self.txs_mempool;
+        let policy = self.txs_mempool_policy;
+        self.dbs_pool
+            .execute(move |dbs| {
+                let hash = tx.get_hash();
+                if dbs.txs_mp_db.txs().get(&HashKeyV2(hash))?.is_some()
+                    || dbs.bc_db_ro.txs_hashs().contains_key(&HashKeyV2(hash))?
+                {
+                    return Ok(TxAcceptance::AlreadyKnown);
+                }
+                if !inputs_sources_exist(&dbs.bc_db_ro, &dbs.txs_mp_db, &tx)? {
+                    return Ok(TxAcceptance::UnknownSource);
+                }
+                // The transactions of the node itself are never limited
+                let is_own_tx = tx.issuers().contains(&server_pubkey);
+                if !is_own_tx {
+                    if let Some(max_txs_per_issuer) = policy.max_txs_per_issuer {
+                        for issuer in tx.issuers() {
+                            let issuer_txs_count = dbs
+                                .txs_mp_db
+                                .txs_by_issuer()
+                                .get(&PubKeyKeyV2(*issuer))?
+                                .map_or(0, |hashs| hashs.0.len());
+                            if issuer_txs_count >= max_txs_per_issuer {
+                                return Ok(TxAcceptance::IssuerQuotaExceeded);
+                            }
+                        }
+                    }
+                }
+                let victim_opt = if !is_own_tx
+                    && policy.eviction != TxsMempoolEviction::None
+                    && txs_mempool.get_free_rooms(&dbs.txs_mp_db)? == 0
+                {
+                    // Evicting an ancestor of the new transaction would evict it too
+                    let ancestors = pending_ancestors(&dbs.txs_mp_db, hash, &tx)?;
+                    let candidates: Vec<_> =
+                        eviction::eviction_candidates(&dbs.bc_db_ro, &dbs.txs_mp_db)?
+                            .into_iter()
+                            .filter(|candidate| !ancestors.contains(&candidate.hash))
+                            .collect();
+                    match policy.select_victim(
+                        eviction::tx_size(&tx),
+                        eviction::is_member(&dbs.bc_db_ro, tx.issuers()[0])?,
+                        &candidates,
+                    ) {
+                        Some(victim) => Some(victim),
+                        None => return Ok(TxAcceptance::TooLowPriority),
+                    }
+                } else {
+                    None
+                };
+
+                let inserted_since = SystemClock.now();
+                if let Some(victim) = victim_opt {
+                    // The new transaction is acceptable, it takes the room of the victim
+                    txs_mempool.add_pending_tx_force(&dbs.txs_mp_db, &tx)?;
+                    log::debug!("txs mempool full: evict tx {}", victim);
+                    for hash in std::iter::once(victim)
+                        .chain(pending_descendants(&dbs.txs_mp_db, &[victim])?)
+                    {
+                        duniter_core::dbs_write_ops::txs_mp::remove_pending_tx_by_hash(
+                            &dbs.txs_mp_db,
+                            hash,
+                        )?;
+                    }
+                } else {
+                    match txs_mempool.accept_new_tx(
+                        &dbs.bc_db_ro,
+                        server_pubkey,
+                        tx,
+                        &dbs.txs_mp_db,
+                    ) {
+                        Ok(()) => (),
+                        Err(TxMpError::Full) => return Ok(TxAcceptance::MempoolFull),
+                        Err(TxMpError::TxAlreadyWritten) => return Ok(TxAcceptance::AlreadyKnown),
+                        Err(TxMpError::Db(e)) => return Err(e),
+                    }
+                }
+                set_received_time(&dbs.txs_mp_db, hash, inserted_since, received_time)?;
+                Ok(TxAcceptance::Accepted)
+            })
+            .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
+    }
+    /// Get pending transactions, parents always come before the transactions that spend
+    /// their outputs.
+    ///
+    /// If `blockchain_time` is given, only the transactions that can be included in a block
+    /// whose median time is `blockchain_time` are returned.
+    pub fn get_pending_txs(
+        &self,
+        blockchain_time_opt: Option<i64>,
+        min_version: usize,
+    ) -> DuniterServerResult<Vec<PendingTxDbV2>> {
+        let gva_db_ro = duniter_gva_indexer::get_gva_db_ro(self.profile_path_opt.as_deref());
+        self.dbs_pool
+            .execute(move |dbs| {
+                let mut pending_txs = dbs.txs_mp_db.txs().iter(.., |it| {
+                    it.filter_ok(|(_, tx)| tx.doc.version() >= min_version)
+                        .map_ok(|(HashKeyV2(hash), tx)| (hash, tx))
+                        .collect::<KvResult<HashMap<_, _>>>()
+                })?;
+                let txs_graph =
+                    chaining::TxsGraph::new(pending_txs.iter().map(|(hash, tx)| (*hash, &tx.doc)));
+
+                let mut not_includable = Vec::new();
+                if let Some(blockchain_time) = blockchain_time_opt {
+                    let blockchain_time = blockchain_time.max(0) as u64;
+                    for (hash, tx) in &pending_txs {
+                        if !time_filter::is_includable(
+                            &dbs.bc_db_ro,
+                            gva_db_ro,
+                            &pending_txs,
+                            &tx.doc,
+                            blockchain_time,
+                        )? {
+                            not_includable.push(*hash);
+                        }
+                    }
+                }
+                // A transaction can't be included without the transactions it spends
+                let not_includable: BTreeSet<Hash> = txs_graph
+                    .descendants(not_includable.iter().copied())
+                    .into_iter()
+                    .chain(not_includable)
+                    .collect();
+
+                Ok::<_, KvError>(
+                    txs_graph
+                        .topological_order()
+                        .into_iter()
+                        .filter(|hash| !not_includable.contains(hash))
+                        .filter_map(|hash| pending_txs.remove(&hash))
+                        .collect(),
+                )
+            })
+            .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
+    }
+    /// Remove a pending transaction and all the pending transactions that depend on it
+    pub fn remove_pending_tx_by_hash(&self, hash: Hash) -> DuniterServerResult<()> {
+        self.dbs_pool
+            .execute(move |dbs| {
+                for hash in
+                    std::iter::once(hash).chain(pending_descendants(&dbs.txs_mp_db, &[hash])?)
+                {
+                    duniter_core::dbs_write_ops::txs_mp::remove_pending_tx_by_hash(
+                        &dbs.txs_mp_db,
+                        hash,
+                    )?;
+                }
+                Ok::<_, KvError>(())
+            })
+            .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
+    }
+    /// Remove the pending transactions received more than one transaction window ago
+    /// according to the clock of the server
+    pub fn trim_expired_txs(&self) -> DuniterServerResult<()> {
+        self.trim_expired_non_written_txs(self.clock.now() - time_filter::TX_WINDOW as i64)
+    }
+    /// Remove expired pending transactions and all the pending transactions that depend on them
+    pub fn trim_expired_non_written_txs(&self, limit_time: i64) -> DuniterServerResult<()> {
+        let mempool_event_bus = self.mempool_event_bus.clone();
+        self.dbs_pool
+            .execute(move |dbs| {
+                let expired_txs =
+                    dbs.txs_mp_db
+                        .txs_by_received_time()
+                        .iter(..limit_time, |it| {
+                            it.values()
+                                .map_ok(|hashs| hashs.0)
+                                .flatten_ok()
+                                .collect::<KvResult<Vec<Hash>>>()
+                        })?;
+                let descendants = pending_descendants(&dbs.txs_mp_db, &expired_txs)?;
+                mempool_event_bus.will_expire(&expired_txs);
+                duniter_core::dbs_write_ops::txs_mp::trim_expired_non_written_txs(
+                    &dbs.txs_mp_db,
+                    limit_time,
+                )?;
+                for hash in descendants {
+                    duniter_core::dbs_write_ops::txs_mp::remove_pending_tx_by_hash(
+                        &dbs.txs_mp_db,
+                        hash,
+                    )?;
+                }
+                Ok::<_, KvError>(())
+            })
+            .expect("dbs pool disconnected")
+            .map_err(|e| e.into())
+    }
+}
+
+fn pending_descendants<TxsMpDb: TxsMpV2DbReadable>(
+    txs_mp_db: &TxsMpDb,
+    roots: &[Hash],
+) -> KvResult<BTreeSet<Hash>> {
+    let pending_txs = txs_mp_db.txs().iter(.., |it| {
+        it.map_ok(|(HashKeyV2(hash), tx)| (hash, tx.doc))
+            .collect::<KvResult<Vec<_>>>()
+    })?;
+    Ok(
+        chaining::TxsGraph::new(pending_txs.iter().map(|(hash, tx)| (*hash, tx)))
+            .descendants(roots.iter().copied()),
+    )
+}
+
+/// Pending transactions whose outputs `tx` spends, directly or indirectly
+fn pending_ancestors<TxsMpDb: TxsMpV2DbReadable>(
+    txs_mp_db: &TxsMpDb,
+    hash: Hash,
+    tx: &TransactionDocumentV10,
+) -> KvResult<BTreeSet<Hash>> {
+    let pending_txs = txs_mp_db.txs().iter(.., |it| {
+        it.map_ok(|(HashKeyV2(hash), tx)| (hash, tx.doc))
+            .collect::<KvResult<Vec<_>>>()
+    })?;
+    Ok(chaining::TxsGraph::new(
+        pending_txs
+            .iter()
+            .map(|(hash, tx)| (*hash, tx))
+            .chain(std::iter::once((hash, tx))),
+    )
+    .ancestors(hash))
+}
+
+/// A source exists if it is unspent in the blockchain or if it is an output of a pending
+/// transaction.
+fn inputs_sources_exist<TxsMpDb: TxsMpV2DbReadable>(
+    bc_db_ro: &BcV2DbRo<FileBackend>,
+    txs_mp_db: &TxsMpDb,
+    tx: &TransactionDocumentV10,
+) -> KvResult<bool> {
+    for input in tx.get_inputs() {
+        let exist = match input.id {
+            SourceIdV10::Ud(ud_source_id) => bc_db_ro
+                .uds()
+                .contains_key(&UdIdV2(ud_source_id.issuer, ud_source_id.block_number))?,
+            SourceIdV10::Utxo(utxo_id) => {
+                bc_db_ro
+                    .utxos()
+                    .contains_key(&UtxoIdDbV2(utxo_id.tx_hash, utxo_id.output_index as u32))?
+                    || txs_mp_db
+                        .txs()
+                        .get(&HashKeyV2(utxo_id.tx_hash))?
+                        .map_or(false, |pending_tx| {
+                            utxo_id.output_index < pending_tx.doc.get_outputs().len()
+                        })
+            }
+        };
+        if !exist {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::block_candidate::tests::forge_block;
+    use crate::genesis::tests::{builder, keypair};
+    use duniter_core::crypto::keys::{ed25519::Ed25519KeyPair, KeyPair as _};
+    use duniter_core::documents::smallvec::smallvec;
+    use duniter_core::documents::transaction::{
+        TransactionDocumentV10Builder, TransactionInputUnlocksV10, TransactionInputV10,
+        TransactionOutputV10, UTXOConditions, UdSourceIdV10, UtxoIdV10,
+    };
+    use duniter_core::wallet::prelude::*;
+
+    /// Server of `keypairs[0]` on a blockchain where each member received an UD of 1000 in
+    /// block #1
+    pub(crate) fn server_with_uds(
+        keypairs: &[Ed25519KeyPair],
+        txs_mempool_size: usize,
+    ) -> anyhow::Result<DuniterServer> {
+        let genesis = builder(keypairs).build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(
+            DuniterCoreConf {
+                self_key_pair: keypairs[0].clone(),
+                txs_mempool_size,
+            },
+            DuniterMode::Start,
+        )?;
+        server.apply_block(genesis.clone())?;
+        let block = forge_block(&server, &keypairs[0], genesis.time + 300)?;
+        assert_eq!(block.dividend, Some(1_000));
+        server.apply_block(block)?;
+        Ok(server)
+    }
+
+    pub(crate) fn ud_input(issuer: PublicKey) -> TransactionInputV10 {
+        TransactionInputV10 {
+            amount: SourceAmount::new(1_000, 0),
+            id: SourceIdV10::Ud(UdSourceIdV10 {
+                issuer,
+                block_number: BlockNumber(1),
+            }),
+        }
+    }
+
+    pub(crate) fn utxo_input(
+        tx: &TransactionDocumentV10,
+        output_index: usize,
+    ) -> TransactionInputV10 {
+        TransactionInputV10 {
+            amount: tx.get_outputs()[output_index].amount,
+            id: SourceIdV10::Utxo(UtxoIdV10 {
+                tx_hash: tx.get_hash(),
+                output_index,
+            }),
+        }
+    }
+
+    /// Transaction of `issuer` on the current block of `server`, spending `inputs` to
+    /// `outputs` (recipient and amount in base 0)
+    pub(crate) fn signed_tx(
+        server: &DuniterServer,
+        issuer: &Ed25519KeyPair,
+        inputs: &[TransactionInputV10],
+        outputs: &[(PublicKey, i64)],
+    ) -> TransactionDocumentV10 {
+        let unlocks: Vec<TransactionInputUnlocksV10> = (0..inputs.len())
+            .map(|index| TransactionInputUnlocksV10 {
+                index,
+                unlocks: smallvec![WalletUnlockProofV10::Sig(0)],
+            })
+            .collect();
+        TransactionDocumentV10Builder {
+            currency: "test",
+            blockstamp: server.get_current_blockstamp().unwrap_or_default(),
+            locktime: 0,
+            issuers: smallvec![issuer.public_key()],
+            inputs,
+            unlocks: &unlocks,
+            outputs: outputs
+                .iter()
+                .map(|(recipient, amount)| TransactionOutputV10 {
+                    amount: SourceAmount::new(*amount, 0),
+                    conditions: UTXOConditions::from(WalletScriptV10::single(
+                        WalletConditionV10::Sig(*recipient),
+                    )),
+                })
+                .collect(),
+            comment: "",
+            hash: None,
+        }
+        .build_and_sign(vec![issuer.generate_signator()])
+    }
+
+    #[test]
+    fn test_tx_acceptance() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let mut server = server_with_uds(&keypairs, 2)?;
+        let server_pubkey = keypairs[0].public_key();
+        let pubkey = |i: usize| keypairs[i].public_key();
+
+        let tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(pubkey(1))],
+            &[(pubkey(2), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(tx.clone(), server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+        assert_eq!(
+            server.accept_new_tx(tx.clone(), server_pubkey)?,
+            TxAcceptance::AlreadyKnown
+        );
+
+        // Outputs of pending transactions can be spent
+        let child = signed_tx(
+            &server,
+            &keypairs[2],
+            &[utxo_input(&tx, 0)],
+            &[(pubkey(1), 1_000)],
+        );
+        let unknown = signed_tx(
+            &server,
+            &keypairs[2],
+            &[utxo_input(&tx, 1)],
+            &[(pubkey(1), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(unknown, server_pubkey)?,
+            TxAcceptance::UnknownSource
+        );
+        let not_member = keypair(9);
+        let unknown = signed_tx(
+            &server,
+            &not_member,
+            &[ud_input(not_member.public_key())],
+            &[(pubkey(1), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(unknown, server_pubkey)?,
+            TxAcceptance::UnknownSource
+        );
+
+        let unbalanced = signed_tx(
+            &server,
+            &keypairs[2],
+            &[ud_input(pubkey(2))],
+            &[(pubkey(1), 900)],
+        );
+        assert_eq!(
+            server.accept_new_tx(unbalanced, server_pubkey)?,
+            TxAcceptance::InvalidDocument
+        );
+        let mut stringified = signed_tx(
+            &server,
+            &keypairs[2],
+            &[ud_input(pubkey(2))],
+            &[(pubkey(1), 1_000)],
+        )
+        .to_string_object();
+        stringified.signatures = child.to_string_object().signatures;
+        stringified.hash = None;
+        assert_eq!(
+            server.accept_new_tx(
+                TransactionDocumentV10::from_string_object(&stringified)?,
+                server_pubkey
+            )?,
+            TxAcceptance::InvalidSignature
+        );
+        stringified.currency = "other".to_owned();
+        assert_eq!(
+            server.accept_new_tx(
+                TransactionDocumentV10::from_string_object(&stringified)?,
+                server_pubkey
+            )?,
+            TxAcceptance::WrongCurrency
+        );
+
+        assert_eq!(
+            server.accept_new_tx(child, server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+        let tx = signed_tx(
+            &server,
+            &keypairs[2],
+            &[ud_input(pubkey(2))],
+            &[(pubkey(1), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(tx, server_pubkey)?,
+            TxAcceptance::MempoolFull
+        );
+
+        // The pending transactions are smaller than the new one
+        server.set_txs_mempool_policy(TxsMempoolPolicy {
+            eviction: TxsMempoolEviction::LargestFirst,
+            ..Default::default()
+        });
+        let tx = signed_tx(
+            &server,
+            &keypairs[2],
+            &[ud_input(pubkey(2))],
+            &[(pubkey(0), 400), (pubkey(1), 300), (pubkey(2), 300)],
+        );
+        assert_eq!(
+            server.accept_new_tx(tx, server_pubkey)?,
+            TxAcceptance::TooLowPriority
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_issuer_quota() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let mut server = server_with_uds(&keypairs, 10)?;
+        server.set_txs_mempool_policy(TxsMempoolPolicy {
+            max_txs_per_issuer: Some(1),
+            ..Default::default()
+        });
+        let server_pubkey = keypairs[0].public_key();
+        let pubkey = |i: usize| keypairs[i].public_key();
+
+        let tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(pubkey(1))],
+            &[(pubkey(1), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(tx.clone(), server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+        let tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[utxo_input(&tx, 0)],
+            &[(pubkey(2), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(tx, server_pubkey)?,
+            TxAcceptance::IssuerQuotaExceeded
+        );
+
+        // The transactions of the node itself are never limited
+        let tx = signed_tx(
+            &server,
+            &keypairs[0],
+            &[ud_input(pubkey(0))],
+            &[(pubkey(0), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(tx.clone(), server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+        let tx = signed_tx(
+            &server,
+            &keypairs[0],
+            &[utxo_input(&tx, 0)],
+            &[(pubkey(1), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(tx, server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+        assert_eq!(server.get_pending_txs(None, 0)?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eviction() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let mut server = server_with_uds(&keypairs, 2)?;
+        let clock = SimulatedClock::new(1_600_000_000);
+        server.clock = Arc::new(clock.clone());
+        server.set_txs_mempool_policy(TxsMempoolPolicy {
+            eviction: TxsMempoolEviction::OldestFirst,
+            ..Default::default()
+        });
+        let server_pubkey = keypairs[0].public_key();
+        let pubkey = |i: usize| keypairs[i].public_key();
+        let pending_hashs = |server: &DuniterServer| -> anyhow::Result<Vec<Hash>> {
+            Ok(server
+                .get_pending_txs(None, 0)?
+                .into_iter()
+                .map(|pending_tx| pending_tx.doc.get_hash())
+                .collect())
+        };
+
+        let oldest = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(pubkey(1))],
+            &[(pubkey(2), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(oldest.clone(), server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+        clock.advance(10);
+        let child = signed_tx(
+            &server,
+            &keypairs[2],
+            &[utxo_input(&oldest, 0)],
+            &[(pubkey(1), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(child, server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+
+        // The oldest transaction is evicted with the transaction that spends it
+        clock.advance(10);
+        let parent = signed_tx(
+            &server,
+            &keypairs[2],
+            &[ud_input(pubkey(2))],
+            &[(pubkey(1), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(parent.clone(), server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+        assert_eq!(pending_hashs(&server)?, vec![parent.get_hash()]);
+
+        // The ancestors of a new transaction are never evicted for it
+        clock.advance(10);
+        let tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[utxo_input(&parent, 0)],
+            &[(pubkey(2), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(tx.clone(), server_pubkey)?,
+            TxAcceptance::Accepted
+        );
+        clock.advance(10);
+        let grandchild = signed_tx(
+            &server,
+            &keypairs[2],
+            &[utxo_input(&tx, 0)],
+            &[(pubkey(1), 1_000)],
+        );
+        assert_eq!(
+            server.accept_new_tx(grandchild, server_pubkey)?,
+            TxAcceptance::TooLowPriority
+        );
+        assert_eq!(
+            pending_hashs(&server)?,
+            vec![parent.get_hash(), tx.get_hash()]
+        );
+
+        Ok(())
+    }
+}
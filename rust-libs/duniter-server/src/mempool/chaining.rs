@@ -0,0 +1,170 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::documents::transaction::{SourceIdV10, TransactionDocumentTrait};
+use std::collections::{BTreeSet, HashMap};
+
+/// Dependency graph of pending transactions: a transaction is a child of the pending
+/// transactions whose outputs it consumes.
+#[derive(Debug, Default)]
+pub(crate) struct TxsGraph {
+    parents: BTreeMap<Hash, BTreeSet<Hash>>,
+    children: HashMap<Hash, BTreeSet<Hash>>,
+}
+
+impl TxsGraph {
+    pub(crate) fn new<'a, I: IntoIterator<Item = (Hash, &'a TransactionDocumentV10)>>(
+        txs: I,
+    ) -> Self {
+        Self::from_parents(
+            txs.into_iter()
+                .map(|(hash, tx)| {
+                    let parents = tx
+                        .get_inputs()
+                        .iter()
+                        .filter_map(|input| match input.id {
+                            SourceIdV10::Utxo(utxo_id) => Some(utxo_id.tx_hash),
+                            SourceIdV10::Ud(_) => None,
+                        })
+                        .collect();
+                    (hash, parents)
+                })
+                .collect(),
+        )
+    }
+    fn from_parents(mut parents: BTreeMap<Hash, BTreeSet<Hash>>) -> Self {
+        // Only keep parents that are pending transactions
+        let hashs: BTreeSet<Hash> = parents.keys().copied().collect();
+        let mut children: HashMap<Hash, BTreeSet<Hash>> = HashMap::new();
+        for (hash, tx_parents) in parents.iter_mut() {
+            tx_parents.retain(|parent| hashs.contains(parent));
+            for parent in tx_parents.iter() {
+                children.entry(*parent).or_default().insert(*hash);
+            }
+        }
+        TxsGraph { parents, children }
+    }
+    /// Parents always come before their children
+    pub(crate) fn topological_order(&self) -> Vec<Hash> {
+        let mut remaining_parents: HashMap<Hash, usize> = self
+            .parents
+            .iter()
+            .map(|(hash, parents)| (*hash, parents.len()))
+            .collect();
+        let mut ready: BTreeSet<Hash> = remaining_parents
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        let mut order = Vec::with_capacity(self.parents.len());
+        while let Some(hash) = ready.iter().next().copied() {
+            ready.remove(&hash);
+            order.push(hash);
+            for child in self.children.get(&hash).into_iter().flatten() {
+                if let Some(count) = remaining_parents.get_mut(child) {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.insert(*child);
+                    }
+                }
+            }
+        }
+        // Transactions in a dependency cycle can never be written, ignore them
+        order
+    }
+    /// All the transactions that depend directly or indirectly on one of the roots
+    pub(crate) fn descendants<I: IntoIterator<Item = Hash>>(&self, roots: I) -> BTreeSet<Hash> {
+        let mut descendants = BTreeSet::new();
+        let mut stack: Vec<Hash> = roots.into_iter().collect();
+        while let Some(hash) = stack.pop() {
+            for child in self.children.get(&hash).into_iter().flatten() {
+                if descendants.insert(*child) {
+                    stack.push(*child);
+                }
+            }
+        }
+        descendants
+    }
+    /// All the transactions `hash` depends on directly or indirectly
+    pub(crate) fn ancestors(&self, hash: Hash) -> BTreeSet<Hash> {
+        let mut ancestors = BTreeSet::new();
+        let mut stack = vec![hash];
+        while let Some(hash) = stack.pop() {
+            for parent in self.parents.get(&hash).into_iter().flatten() {
+                if ancestors.insert(*parent) {
+                    stack.push(*parent);
+                }
+            }
+        }
+        ancestors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn h(n: u8) -> Hash {
+        Hash([n; 32])
+    }
+
+    fn graph(edges: &[(u8, &[u8])]) -> TxsGraph {
+        TxsGraph::from_parents(
+            edges
+                .iter()
+                .map(|(tx, parents)| (h(*tx), parents.iter().map(|p| h(*p)).collect()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_topological_order() {
+        // 1 <- 3 <- 2, 4 spends an unknown tx, 5 and 6 spend each other
+        let g = graph(&[
+            (1, &[]),
+            (2, &[3]),
+            (3, &[1, 9]),
+            (4, &[8]),
+            (5, &[6]),
+            (6, &[5]),
+        ]);
+        assert_eq!(g.topological_order(), vec![h(1), h(3), h(2), h(4)]);
+    }
+
+    #[test]
+    fn test_descendants() {
+        let g = graph(&[(1, &[]), (2, &[1]), (3, &[2]), (4, &[1, 3]), (5, &[])]);
+        assert_eq!(
+            g.descendants(vec![h(2)]),
+            vec![h(3), h(4)].into_iter().collect()
+        );
+        assert_eq!(
+            g.descendants(vec![h(1), h(5)]),
+            vec![h(2), h(3), h(4)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let g = graph(&[(1, &[]), (2, &[1]), (3, &[2]), (4, &[1, 3]), (5, &[])]);
+        assert_eq!(
+            g.ancestors(h(4)),
+            vec![h(1), h(2), h(3)].into_iter().collect()
+        );
+        assert!(g.ancestors(h(5)).is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::dbs::{databases::txs_mp_v2::TxsEvent, HashKeyV2};
+use duniter_core::documents::transaction::TransactionDocumentTrait;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+/// Change of the transactions mempool
+#[derive(Clone, Debug, PartialEq)]
+pub enum MempoolEvent {
+    TxAdded(Arc<TransactionDocumentV10>),
+    /// Removed by an operator, evicted or no longer valid
+    TxRemoved(Hash),
+    TxIncludedInBlock {
+        hash: Hash,
+        block_number: BlockNumber,
+    },
+    TxExpired(Hash),
+}
+
+/// Identifier of a subscription to the mempool events
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MempoolSubscriptionId(pub u64);
+
+#[derive(Clone, Copy, Debug)]
+enum RemovalReason {
+    IncludedInBlock(BlockNumber),
+    Expired,
+}
+
+/// Forward the changes of the txs collection to all subscribers.
+///
+/// The txs collection only knows that a transaction is removed, the server registers the
+/// reason of the removal beforehand when it knows it. A transaction upserted again, for
+/// example when a block is reverted, is only announced once.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MempoolEventBus {
+    next_subscription_id: Arc<AtomicU64>,
+    pending_txs: Arc<Mutex<HashSet<Hash>>>,
+    removal_reasons: Arc<Mutex<HashMap<Hash, RemovalReason>>>,
+    subscribers: Arc<Mutex<HashMap<MempoolSubscriptionId, flume::Sender<MempoolEvent>>>>,
+}
+
+impl MempoolEventBus {
+    pub(crate) fn start(
+        pending_txs: HashSet<Hash>,
+        txs_events_recv: flume::Receiver<Arc<Events<TxsEvent>>>,
+    ) -> Self {
+        let bus = MempoolEventBus {
+            pending_txs: Arc::new(Mutex::new(pending_txs)),
+            ..Default::default()
+        };
+        let bus_clone = bus.clone();
+        std::thread::spawn(move || {
+            for events in txs_events_recv {
+                for event in events.iter() {
+                    match event {
+                        TxsEvent::Upsert {
+                            key: HashKeyV2(hash),
+                            value,
+                        } => {
+                            if bus_clone.lock_pending_txs().insert(*hash) {
+                                bus_clone
+                                    .publish(MempoolEvent::TxAdded(Arc::new(value.doc.clone())))
+                            }
+                        }
+                        TxsEvent::Remove {
+                            key: HashKeyV2(hash),
+                        } => {
+                            if bus_clone.lock_pending_txs().remove(hash) {
+                                bus_clone.publish(bus_clone.removal_event(*hash))
+                            }
+                        }
+                        TxsEvent::RemoveAll => bus_clone.lock_pending_txs().clear(),
+                    }
+                }
+            }
+        });
+        bus
+    }
+    pub(crate) fn subscribe(&self) -> (MempoolSubscriptionId, flume::Receiver<MempoolEvent>) {
+        let id = MempoolSubscriptionId(self.next_subscription_id.fetch_add(1, Ordering::Relaxed));
+        let (s, r) = flume::unbounded();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, s);
+        (id, r)
+    }
+    /// The receiver of the subscription is disconnected
+    pub(crate) fn unsubscribe(&self, id: MempoolSubscriptionId) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id);
+    }
+    pub(crate) fn will_be_included(&self, hashs: &[Hash], block_number: BlockNumber) {
+        self.set_removal_reason(hashs, RemovalReason::IncludedInBlock(block_number));
+    }
+    pub(crate) fn will_expire(&self, hashs: &[Hash]) {
+        self.set_removal_reason(hashs, RemovalReason::Expired);
+    }
+    /// The block that would have included these transactions has not been applied
+    pub(crate) fn forget_removal_reasons(&self, hashs: &[Hash]) {
+        let mut removal_reasons = self
+            .removal_reasons
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for hash in hashs {
+            removal_reasons.remove(hash);
+        }
+    }
+    fn set_removal_reason(&self, hashs: &[Hash], reason: RemovalReason) {
+        let mut removal_reasons = self
+            .removal_reasons
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for hash in hashs {
+            removal_reasons.insert(*hash, reason);
+        }
+    }
+    fn removal_event(&self, hash: Hash) -> MempoolEvent {
+        let reason_opt = self
+            .removal_reasons
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&hash);
+        match reason_opt {
+            Some(RemovalReason::IncludedInBlock(block_number)) => {
+                MempoolEvent::TxIncludedInBlock { hash, block_number }
+            }
+            Some(RemovalReason::Expired) => MempoolEvent::TxExpired(hash),
+            None => MempoolEvent::TxRemoved(hash),
+        }
+    }
+    fn lock_pending_txs(&self) -> std::sync::MutexGuard<HashSet<Hash>> {
+        self.pending_txs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    fn publish(&self, event: MempoolEvent) {
+        // Forget the subscribers that have been dropped
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+impl DuniterServer {
+    /// Receive all the future changes of the transactions mempool, until the receiver is
+    /// dropped or the subscription is ended by `unsubscribe_mempool_events`
+    pub fn subscribe_mempool_events(
+        &self,
+    ) -> (MempoolSubscriptionId, flume::Receiver<MempoolEvent>) {
+        self.mempool_event_bus.subscribe()
+    }
+    pub fn unsubscribe_mempool_events(&self, id: MempoolSubscriptionId) {
+        self.mempool_event_bus.unsubscribe(id)
+    }
+    /// Call `f` on each future change of the transactions mempool, from a dedicated thread
+    /// that ends with the subscription
+    pub fn on_mempool_event<F: FnMut(MempoolEvent) + Send + 'static>(
+        &self,
+        mut f: F,
+    ) -> MempoolSubscriptionId {
+        let (id, receiver) = self.mempool_event_bus.subscribe();
+        std::thread::spawn(move || {
+            for event in receiver {
+                f(event);
+            }
+        });
+        id
+    }
+    /// Register the pending transactions of `block`, before it is applied, and return them
+    pub(crate) fn mark_txs_included_in_block(&self, block: &DubpBlockV10) -> KvResult<Vec<Hash>> {
+        let mut included_txs = Vec::new();
+        for tx in block.transactions() {
+            let hash = tx.get_hash();
+            if self
+                .shared_dbs
+                .txs_mp_db
+                .txs()
+                .contains_key(&HashKeyV2(hash))?
+            {
+                included_txs.push(hash);
+            }
+        }
+        self.mempool_event_bus
+            .will_be_included(&included_txs, block.number());
+        Ok(included_txs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_removal_reasons() {
+        let (s, r) = flume::unbounded();
+        let bus = MempoolEventBus::start(HashSet::new(), r);
+        let (_, subscriber1) = bus.subscribe();
+        let (_, subscriber2) = bus.subscribe();
+        drop(s);
+
+        bus.will_be_included(&[Hash([1; 32])], BlockNumber(42));
+        bus.will_expire(&[Hash([2; 32])]);
+        for n in 1..=3 {
+            bus.publish(bus.removal_event(Hash([n; 32])));
+        }
+
+        let expected = vec![
+            MempoolEvent::TxIncludedInBlock {
+                hash: Hash([1; 32]),
+                block_number: BlockNumber(42),
+            },
+            MempoolEvent::TxExpired(Hash([2; 32])),
+            MempoolEvent::TxRemoved(Hash([3; 32])),
+        ];
+        assert_eq!(subscriber1.try_iter().collect::<Vec<_>>(), expected);
+        assert_eq!(subscriber2.try_iter().collect::<Vec<_>>(), expected);
+        // A reason is only used once
+        assert_eq!(
+            bus.removal_event(Hash([1; 32])),
+            MempoolEvent::TxRemoved(Hash([1; 32]))
+        );
+        // The reasons of a block that has not been applied are forgotten
+        bus.will_be_included(&[Hash([4; 32])], BlockNumber(43));
+        bus.forget_removal_reasons(&[Hash([4; 32])]);
+        assert_eq!(
+            bus.removal_event(Hash([4; 32])),
+            MempoolEvent::TxRemoved(Hash([4; 32]))
+        );
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        let (_s, r) = flume::unbounded();
+        let bus = MempoolEventBus::start(HashSet::new(), r);
+        let (id, subscriber) = bus.subscribe();
+
+        bus.publish(MempoolEvent::TxRemoved(Hash([1; 32])));
+        bus.unsubscribe(id);
+        bus.publish(MempoolEvent::TxRemoved(Hash([2; 32])));
+        assert_eq!(
+            subscriber.iter().collect::<Vec<_>>(),
+            vec![MempoolEvent::TxRemoved(Hash([1; 32]))]
+        );
+    }
+
+    #[test]
+    fn test_mempool_events() -> anyhow::Result<()> {
+        use crate::genesis::tests::keypair;
+        use crate::mempool::tests::{server_with_uds, signed_tx, ud_input};
+        use duniter_core::crypto::keys::KeyPair as _;
+
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let server = server_with_uds(&keypairs, 10)?;
+        let (id, events) = server.subscribe_mempool_events();
+        let tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(keypairs[1].public_key())],
+            &[(keypairs[2].public_key(), 1_000)],
+        );
+
+        assert!(server
+            .accept_new_tx(tx.clone(), keypairs[0].public_key())?
+            .is_accepted());
+        // Upserted again, it is not announced twice
+        server.add_pending_tx_force(tx.clone())?;
+        server.remove_pending_tx_by_hash(tx.get_hash())?;
+
+        let timeout = std::time::Duration::from_secs(5);
+        assert_eq!(
+            events.recv_timeout(timeout)?,
+            MempoolEvent::TxAdded(Arc::new(tx.clone()))
+        );
+        assert_eq!(
+            events.recv_timeout(timeout)?,
+            MempoolEvent::TxRemoved(tx.get_hash())
+        );
+        server.unsubscribe_mempool_events(id);
+        assert!(events.recv().is_err());
+        Ok(())
+    }
+}
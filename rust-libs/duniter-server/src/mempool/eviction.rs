@@ -0,0 +1,188 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::dbs::{databases::bc_v2::BcV2DbRo, HashKeyV2};
+use duniter_core::documents::transaction::TransactionDocumentTrait;
+use std::{collections::HashMap, str::FromStr};
+
+/// Which transaction to evict when the mempool is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxsMempoolEviction {
+    /// Never evict, new transactions are refused when the mempool is full
+    None,
+    /// Evict the oldest received transaction
+    OldestFirst,
+    /// Evict the largest transaction, if it is larger than the new one
+    LargestFirst,
+}
+
+impl Default for TxsMempoolEviction {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl FromStr for TxsMempoolEviction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "oldest" => Ok(Self::OldestFirst),
+            "largest" => Ok(Self::LargestFirst),
+            _ => Err(format!("unknown eviction policy '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TxsMempoolPolicy {
+    pub eviction: TxsMempoolEviction,
+    /// Maximum number of pending transactions per issuer
+    pub max_txs_per_issuer: Option<usize>,
+    /// Transactions issued by members can only be evicted by other members transactions
+    pub members_priority: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct EvictionCandidate {
+    pub(crate) hash: Hash,
+    pub(crate) received_time: i64,
+    pub(crate) size: usize,
+    pub(crate) issued_by_member: bool,
+}
+
+impl TxsMempoolPolicy {
+    /// Choose the pending transaction to evict to make room for the new one.
+    /// Return `None` if the new transaction has a too low priority.
+    pub(crate) fn select_victim(
+        &self,
+        new_tx_size: usize,
+        new_tx_issued_by_member: bool,
+        candidates: &[EvictionCandidate],
+    ) -> Option<Hash> {
+        let mut candidates = candidates
+            .iter()
+            .filter(|c| !self.members_priority || new_tx_issued_by_member || !c.issued_by_member)
+            // Evict non-members transactions first
+            .map(|c| (self.members_priority && c.issued_by_member, c));
+
+        match self.eviction {
+            TxsMempoolEviction::None => None,
+            TxsMempoolEviction::OldestFirst => candidates
+                .min_by_key(|(protected, c)| (*protected, c.received_time))
+                .map(|(_, c)| c.hash),
+            TxsMempoolEviction::LargestFirst => candidates
+                .min_by_key(|(protected, c)| (*protected, std::cmp::Reverse(c.size)))
+                .filter(|(_, c)| c.size > new_tx_size)
+                .map(|(_, c)| c.hash),
+        }
+    }
+}
+
+/// Size of a transaction in compact lines (each input has its unlock line)
+pub(crate) fn tx_size(tx: &TransactionDocumentV10) -> usize {
+    tx.issuers().len() + 2 * tx.get_inputs().len() + tx.get_outputs().len()
+}
+
+pub(crate) fn is_member(bc_db_ro: &BcV2DbRo<FileBackend>, pubkey: PublicKey) -> KvResult<bool> {
+    Ok(bc_db_ro
+        .identities()
+        .get(&PubKeyKeyV2(pubkey))?
+        .map_or(false, |idty| idty.is_member))
+}
+
+pub(crate) fn eviction_candidates<TxsMpDb: TxsMpV2DbReadable>(
+    bc_db_ro: &BcV2DbRo<FileBackend>,
+    txs_mp_db: &TxsMpDb,
+) -> KvResult<Vec<EvictionCandidate>> {
+    let mut received_times = HashMap::new();
+    txs_mp_db.txs_by_received_time().iter(.., |it| {
+        it.try_for_each(|entry_res| {
+            let (received_time, hashs) = entry_res?;
+            for hash in hashs.0 {
+                received_times.insert(hash, received_time);
+            }
+            Ok::<_, KvError>(())
+        })
+    })?;
+
+    txs_mp_db.txs().iter(.., |it| {
+        it.map(|entry_res| {
+            let (HashKeyV2(hash), pending_tx) = entry_res?;
+            Ok(EvictionCandidate {
+                hash,
+                received_time: received_times.get(&hash).copied().unwrap_or_default(),
+                size: tx_size(&pending_tx.doc),
+                issued_by_member: is_member(bc_db_ro, pending_tx.doc.issuers()[0])?,
+            })
+        })
+        .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(n: u8, received_time: i64, size: usize, member: bool) -> EvictionCandidate {
+        EvictionCandidate {
+            hash: Hash([n; 32]),
+            received_time,
+            size,
+            issued_by_member: member,
+        }
+    }
+
+    #[test]
+    fn test_select_victim() {
+        let candidates = [
+            candidate(1, 10, 4, true),
+            candidate(2, 20, 8, false),
+            candidate(3, 30, 6, false),
+        ];
+        let mut policy = TxsMempoolPolicy::default();
+        assert_eq!(policy.select_victim(4, false, &candidates), None);
+
+        policy.eviction = TxsMempoolEviction::OldestFirst;
+        assert_eq!(
+            policy.select_victim(4, false, &candidates),
+            Some(Hash([1; 32]))
+        );
+
+        policy.eviction = TxsMempoolEviction::LargestFirst;
+        assert_eq!(
+            policy.select_victim(4, false, &candidates),
+            Some(Hash([2; 32]))
+        );
+        assert_eq!(policy.select_victim(8, false, &candidates), None);
+
+        policy.eviction = TxsMempoolEviction::OldestFirst;
+        policy.members_priority = true;
+        assert_eq!(
+            policy.select_victim(4, false, &candidates),
+            Some(Hash([2; 32]))
+        );
+        assert_eq!(
+            policy.select_victim(4, false, &[candidate(1, 10, 4, true)]),
+            None
+        );
+        assert_eq!(
+            policy.select_victim(4, true, &[candidate(1, 10, 4, true)]),
+            Some(Hash([1; 32]))
+        );
+    }
+}
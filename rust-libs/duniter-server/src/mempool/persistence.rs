@@ -0,0 +1,220 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::common::crypto::keys::KeyPair as _;
+use duniter_core::dbs::databases::txs_mp_v2::{TxsMpV2Db, TxsMpV2DbWritable};
+use duniter_core::documents_parser::prelude::*;
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+/// One line of a mempool snapshot
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingTxSnapshot {
+    raw_tx: String,
+    received_time: i64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MempoolImportReport {
+    pub imported: usize,
+    pub rejected: usize,
+}
+
+impl DuniterServer {
+    /// Write all pending transactions as JSON lines, parents first.
+    /// Return the number of exported transactions.
+    pub fn export_mempool<W: Write>(&self, mut writer: W) -> DuniterServerResult<usize> {
+        let received_times = self
+            .dbs_pool
+            .execute(|dbs| {
+                dbs.txs_mp_db.txs_by_received_time().iter(.., |it| {
+                    let mut received_times = HashMap::new();
+                    for entry_res in it {
+                        let (received_time, hashs) = entry_res?;
+                        for hash in hashs.0 {
+                            received_times.insert(hash, received_time);
+                        }
+                    }
+                    Ok::<_, KvError>(received_times)
+                })
+            })
+            .expect("dbs pool disconnected")?;
+
+        let pending_txs = self.get_pending_txs(None, 0)?;
+        for pending_tx in &pending_txs {
+            let snapshot = PendingTxSnapshot {
+                raw_tx: pending_tx.doc.as_text().to_owned(),
+                received_time: received_times
+                    .get(&pending_tx.doc.get_hash())
+                    .copied()
+                    .unwrap_or_default(),
+            };
+            serde_json::to_writer(&mut writer, &snapshot).map_err(std::io::Error::from)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+
+        Ok(pending_txs.len())
+    }
+    /// Read pending transactions from JSON lines written by `export_mempool`.
+    ///
+    /// Each transaction goes through the same checks as a transaction received from the
+    /// network, and keeps its original received time so that it expires at the same date.
+    pub fn import_mempool<R: BufRead>(
+        &self,
+        reader: R,
+    ) -> DuniterServerResult<MempoolImportReport> {
+        let server_pubkey = self.conf.self_key_pair.public_key();
+        let mut report = MempoolImportReport::default();
+        for line_res in reader.lines() {
+            let line = line_res?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let snapshot: PendingTxSnapshot =
+                serde_json::from_str(&line).map_err(DuniterServerError::deser)?;
+
+            let tx = match TransactionDocumentV10::parse_from_raw_text(&snapshot.raw_tx) {
+                Ok(tx) => tx,
+                Err(e) => {
+                    log::warn!("mempool import: invalid tx: {}", e);
+                    report.rejected += 1;
+                    continue;
+                }
+            };
+            let hash = tx.get_hash();
+            let tx_acceptance =
+                self.accept_new_tx_received_at(tx, server_pubkey, snapshot.received_time)?;
+            if tx_acceptance.is_accepted() {
+                report.imported += 1;
+            } else {
+                log::warn!(
+                    "mempool import: tx {} rejected: {}",
+                    hash,
+                    tx_acceptance.code()
+                );
+                report.rejected += 1;
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Move a pending transaction just added by duniter-core to its received time.
+///
+/// duniter-core indexes a new pending transaction at the system time of its insertion, it is
+/// looked for from `inserted_since` only.
+pub(crate) fn set_received_time(
+    txs_mp_db: &TxsMpV2Db<FileBackend>,
+    hash: Hash,
+    inserted_since: i64,
+    received_time: i64,
+) -> KvResult<()> {
+    let old_entry_opt = txs_mp_db
+        .txs_by_received_time()
+        .iter(inserted_since.., |it| {
+            it.filter_ok(|(_, hashs)| hashs.0.contains(&hash))
+                .next()
+                .transpose()
+        })?;
+    if let Some((old_received_time, mut hashs)) = old_entry_opt {
+        if old_received_time == received_time {
+            return Ok(());
+        }
+        hashs.0.remove(&hash);
+        if hashs.0.is_empty() {
+            txs_mp_db
+                .txs_by_received_time_write()
+                .remove(old_received_time)?;
+        } else {
+            txs_mp_db
+                .txs_by_received_time_write()
+                .upsert(old_received_time, hashs)?;
+        }
+    }
+
+    let mut hashs = txs_mp_db
+        .txs_by_received_time()
+        .get(&received_time)?
+        .unwrap_or_default();
+    hashs.0.insert(hash);
+    txs_mp_db
+        .txs_by_received_time_write()
+        .upsert(received_time, hashs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::tests::keypair;
+    use crate::mempool::tests::{server_with_uds, signed_tx, ud_input, utxo_input};
+
+    #[test]
+    fn test_export_import_mempool() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let pubkey = |i: usize| keypairs[i].public_key();
+        let mut server = server_with_uds(&keypairs, 10)?;
+        let clock = SimulatedClock::new(1_600_000_000);
+        server.clock = Arc::new(clock.clone());
+
+        let parent = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(pubkey(1))],
+            &[(pubkey(2), 1_000)],
+        );
+        assert!(server
+            .accept_new_tx(parent.clone(), pubkey(0))?
+            .is_accepted());
+        clock.advance(60);
+        let child = signed_tx(
+            &server,
+            &keypairs[2],
+            &[utxo_input(&parent, 0)],
+            &[(pubkey(1), 1_000)],
+        );
+        assert!(server.accept_new_tx(child, pubkey(0))?.is_accepted());
+        let mut snapshot = Vec::new();
+        assert_eq!(server.export_mempool(&mut snapshot)?, 2);
+
+        // The transactions keep their received time
+        let other_server = server_with_uds(&keypairs, 10)?;
+        assert_eq!(
+            other_server.import_mempool(&snapshot[..])?,
+            MempoolImportReport {
+                imported: 2,
+                rejected: 0
+            }
+        );
+        let mut other_snapshot = Vec::new();
+        assert_eq!(other_server.export_mempool(&mut other_snapshot)?, 2);
+        assert_eq!(other_snapshot, snapshot);
+
+        // Known transactions are rejected
+        assert_eq!(
+            other_server.import_mempool(&snapshot[..])?,
+            MempoolImportReport {
+                imported: 0,
+                rejected: 2
+            }
+        );
+
+        Ok(())
+    }
+}
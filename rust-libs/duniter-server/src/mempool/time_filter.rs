@@ -0,0 +1,148 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::dbs::{
+    databases::bc_v2::{BcV2DbReadable, BcV2DbRo},
+    HashKeyV2, UtxoIdDbV2, U32BE,
+};
+use duniter_core::documents::transaction::{SourceIdV10, TransactionDocumentTrait};
+use duniter_core::wallet::prelude::*;
+use duniter_gva_db::{GvaV1DbReadable, GvaV1DbRo};
+use std::collections::HashMap;
+
+/// A transaction can only be written if its blockstamp is less than one week old
+pub(crate) const TX_WINDOW: u64 = 604_800;
+
+/// Check that the blockstamp of a transaction is recent enough and that its locktime is over
+pub(crate) fn blockstamp_time_ok(
+    blockstamp_time: u64,
+    locktime: u64,
+    blockchain_time: u64,
+) -> bool {
+    blockstamp_time + TX_WINDOW >= blockchain_time && blockstamp_time + locktime <= blockchain_time
+}
+
+/// Check if a pending transaction can be included in a block whose median time is
+/// `blockchain_time`.
+///
+/// The outputs of `pending_txs` are considered to be written in the same block.
+pub(crate) fn is_includable(
+    bc_db_ro: &BcV2DbRo<FileBackend>,
+    gva_db_ro: &GvaV1DbRo<FileBackend>,
+    pending_txs: &HashMap<Hash, PendingTxDbV2>,
+    tx: &TransactionDocumentV10,
+    blockchain_time: u64,
+) -> KvResult<bool> {
+    let blockstamp = tx.get_blockstamp();
+    let blockstamp_time = match bc_db_ro.blocks_meta().get(&U32BE(blockstamp.number.0))? {
+        Some(block_meta) if block_meta.hash == blockstamp.hash.0 => block_meta.median_time,
+        // Unknown or forked blockstamp
+        _ => return Ok(false),
+    };
+    if !blockstamp_time_ok(blockstamp_time, tx.get_locktime(), blockchain_time) {
+        return Ok(false);
+    }
+    inputs_unlockable(
+        bc_db_ro,
+        gva_db_ro,
+        |tx_hash| pending_txs.get(tx_hash).map(|pending_tx| &pending_tx.doc),
+        tx,
+        blockchain_time,
+    )
+}
+
+/// Check that the proofs of `tx` unlock its inputs and that their time locks are over in a
+/// block whose median time is `blockchain_time`.
+///
+/// `same_block_tx` gives the transactions written in the same block as `tx`. The written
+/// time of blockchain UTXOs is the median time of the block of their transaction, as indexed
+/// by gva_v1.
+pub(crate) fn inputs_unlockable<'a, BcDb: BcV2DbReadable>(
+    bc_db: &BcDb,
+    gva_db_ro: &GvaV1DbRo<FileBackend>,
+    same_block_tx: impl Fn(&Hash) -> Option<&'a TransactionDocumentV10>,
+    tx: &TransactionDocumentV10,
+    blockchain_time: u64,
+) -> KvResult<bool> {
+    let unlocks = tx.get_inputs_unlocks();
+    for (input, input_unlocks) in tx.get_inputs().iter().zip(unlocks.iter()) {
+        let (script, written_on) = match input.id {
+            // An UD is locked by the signature of its owner
+            SourceIdV10::Ud(ud_id) => (
+                WalletScriptV10::single(WalletConditionV10::Sig(ud_id.issuer)),
+                0,
+            ),
+            SourceIdV10::Utxo(utxo_id) => {
+                if let Some(parent_tx) = same_block_tx(&utxo_id.tx_hash) {
+                    match parent_tx.get_outputs().get(utxo_id.output_index) {
+                        Some(output) => (output.conditions.script.clone(), blockchain_time),
+                        None => return Ok(false),
+                    }
+                } else if let Some(utxo) = bc_db
+                    .utxos()
+                    .get(&UtxoIdDbV2(utxo_id.tx_hash, utxo_id.output_index as u32))?
+                {
+                    match gva_db_ro.txs().get(&HashKeyV2(utxo_id.tx_hash))? {
+                        Some(written_tx) => (utxo.wallet_script, written_tx.written_time as u64),
+                        // Not indexed yet, its time locks can't be checked
+                        None => return Ok(false),
+                    }
+                } else {
+                    return Ok(false);
+                }
+            }
+        };
+        match SourceV10::unlockable_on(tx.issuers(), &input_unlocks.unlocks, written_on, &script) {
+            Ok(unlockable_on) if unlockable_on <= blockchain_time => (),
+            // Invalid proofs or time lock not over yet
+            _ => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blockstamp_time_ok() {
+        let blockstamp_time = 1_600_000_000;
+        assert!(blockstamp_time_ok(blockstamp_time, 0, blockstamp_time));
+        assert!(blockstamp_time_ok(
+            blockstamp_time,
+            0,
+            blockstamp_time + TX_WINDOW
+        ));
+        // Blockstamp too old
+        assert!(!blockstamp_time_ok(
+            blockstamp_time,
+            0,
+            blockstamp_time + TX_WINDOW + 1
+        ));
+        // Locktime not over
+        assert!(!blockstamp_time_ok(
+            blockstamp_time,
+            3_600,
+            blockstamp_time + 3_599
+        ));
+        assert!(blockstamp_time_ok(
+            blockstamp_time,
+            3_600,
+            blockstamp_time + 3_600
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Proof of work of the blocks issued by the node.
+
+use crate::*;
+use duniter_core::crypto::keys::{
+    ed25519::Ed25519KeyPair, KeyPair as _, Signator as _, Signature as _,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Each worker tests the nonces of its own range
+const NONCE_RANGE: u64 = 100_000_000_000;
+/// Highest node prefix, nonces must stay below 2^53 to be read by the JS side
+pub const MAX_POW_PREFIX: u64 = 899;
+/// Number of nonces tested between two checks of the stop signal
+const TESTS_PER_ROUND: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowConf {
+    /// Fraction of the time spent computing by each worker, in ]0;1]
+    pub cpu: f64,
+    /// Number of workers, each one on its own thread
+    pub nb_cores: usize,
+    /// Identifier of the node in its nonces, from 1 to 899, so that the nodes sharing the
+    /// same key don't test the same nonces. 0 means no prefix.
+    pub prefix: u64,
+}
+
+impl Default for PowConf {
+    fn default() -> Self {
+        PowConf {
+            cpu: 0.6,
+            nb_cores: 1,
+            prefix: 1,
+        }
+    }
+}
+
+impl PowConf {
+    /// A nonce is made of the node prefix, the index of the worker and a counter
+    fn first_nonce(&self, worker: usize) -> u64 {
+        let prefix = self.prefix.min(MAX_POW_PREFIX) * 100 * NONCE_RANGE;
+        if self.nb_cores <= 1 {
+            prefix
+        } else {
+            prefix + (worker as u64 + 1) * NONCE_RANGE
+        }
+    }
+}
+
+/// Proof of work in progress
+pub struct PowHandle {
+    stop: Arc<AtomicBool>,
+    receiver: flume::Receiver<DubpBlockV10Stringified>,
+}
+
+impl PowHandle {
+    /// Stop the workers, `wait` then returns `None`
+    pub fn cancel(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+    /// The proof is found or cancelled
+    pub fn is_finished(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+    /// Wait for the end of the proof. Return the signed block whose hash matches the
+    /// difficulty, or `None` if the proof was cancelled.
+    pub fn wait(self) -> Option<DubpBlockV10Stringified> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl DuniterServer {
+    /// Prove `block` with the key pair of the server. The proof in progress, if any, is
+    /// cancelled, and this one is cancelled as soon as the current block changes.
+    pub fn start_block_proof(
+        &mut self,
+        block: DubpBlockV10Stringified,
+        difficulty: usize,
+        pow_conf: PowConf,
+    ) -> DuniterServerResult<PowHandle> {
+        self.cancel_block_proof();
+        let pow_handle = prove_block(block, &self.conf.self_key_pair, difficulty, pow_conf)?;
+        self.pow_stop = Some(Arc::clone(&pow_handle.stop));
+        Ok(pow_handle)
+    }
+    pub fn cancel_block_proof(&mut self) {
+        if let Some(stop) = self.pow_stop.take() {
+            stop.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Search on `pow_conf.nb_cores` threads a nonce such that the hash of `block` signed by
+/// `keypair` matches `difficulty`. Fail if `block` is not a well-formed block.
+pub fn prove_block(
+    block: DubpBlockV10Stringified,
+    keypair: &Ed25519KeyPair,
+    difficulty: usize,
+    pow_conf: PowConf,
+) -> DuniterServerResult<PowHandle> {
+    let inner_hash = crate::raw_block::inner_hash(&block)?;
+    let block = Arc::new(block);
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = flume::bounded(1);
+    let cpu = pow_conf.cpu.clamp(0.01, 1.0);
+
+    for worker in 0..pow_conf.nb_cores.max(1) {
+        let block = Arc::clone(&block);
+        let keypair = keypair.clone();
+        let first_nonce = pow_conf.first_nonce(worker);
+        let sender = sender.clone();
+        let stop = Arc::clone(&stop);
+        std::thread::spawn(move || {
+            if let Some((nonce, signature, hash)) =
+                search_nonce(&inner_hash, &keypair, difficulty, cpu, first_nonce, &stop)
+            {
+                // Only the first worker to find a proof sends it
+                if stop
+                    .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    let mut block = (*block).clone();
+                    block.inner_hash = Some(inner_hash.to_hex());
+                    block.nonce = nonce;
+                    block.signature = signature;
+                    block.hash = Some(hash.to_hex());
+                    let _ = sender.send(block);
+                }
+            }
+        });
+    }
+
+    Ok(PowHandle { stop, receiver })
+}
+
+fn search_nonce(
+    inner_hash: &Hash,
+    keypair: &Ed25519KeyPair,
+    difficulty: usize,
+    cpu: f64,
+    first_nonce: u64,
+    stop: &AtomicBool,
+) -> Option<(u64, String, Hash)> {
+    let signator = keypair.generate_signator();
+    let mut nonce = first_nonce;
+    while !stop.load(Ordering::Relaxed) {
+        let round_start = Instant::now();
+        for _ in 0..TESTS_PER_ROUND {
+            let signed_part = crate::raw_block::signed_part(inner_hash, nonce);
+            let signature = signator.sign(signed_part.as_bytes()).to_base64();
+            let hash = crate::raw_block::block_hash(&signed_part, &signature);
+            if crate::genesis::hash_matches_pow(&hash, difficulty) {
+                return Some((nonce, signature, hash));
+            }
+            nonce += 1;
+        }
+        // Rest long enough to use only `cpu` of the core
+        if cpu < 1.0 {
+            std::thread::sleep(round_start.elapsed().mul_f64((1.0 - cpu) / cpu));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::tests::{builder, keypair};
+
+    #[test]
+    fn test_first_nonce() {
+        let mut pow_conf = PowConf {
+            cpu: 1.0,
+            nb_cores: 1,
+            prefix: 0,
+        };
+        assert_eq!(pow_conf.first_nonce(0), 0);
+        pow_conf.prefix = 12;
+        assert_eq!(pow_conf.first_nonce(0), 120_000_000_000_000);
+        pow_conf.nb_cores = 4;
+        assert_eq!(pow_conf.first_nonce(2), 120_300_000_000_000);
+        pow_conf.prefix = 5_000;
+        assert_eq!(pow_conf.first_nonce(0), 8_990_100_000_000_000);
+    }
+
+    #[test]
+    fn test_prove_block() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let mut server = DuniterServer::start(
+            DuniterCoreConf {
+                self_key_pair: keypairs[0].clone(),
+                txs_mempool_size: 200,
+            },
+            "test".to_owned(),
+            DuniterMode::Start,
+            None,
+            duniter_core::module::SOFTWARE_NAME,
+        )?;
+        let genesis = builder(&keypairs).build_stringified(&keypairs[0])?;
+        server.apply_block(genesis.clone())?;
+
+        let candidate =
+            server.generate_block_candidate(keypairs[0].public_key(), genesis.time + 300)?;
+        let pow_conf = PowConf {
+            cpu: 1.0,
+            nb_cores: 2,
+            prefix: 1,
+        };
+        let block = server
+            .start_block_proof(candidate, 20, pow_conf)?
+            .wait()
+            .ok_or_else(|| anyhow::anyhow!("proof cancelled"))?;
+        let hash = Hash::from_hex(block.hash.as_deref().unwrap_or_default())
+            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        assert!(crate::genesis::hash_matches_pow(&hash, 20));
+        assert_eq!(server.validate_block(&block), Ok(()));
+
+        // A proof is cancelled when the current block changes
+        let candidate =
+            server.generate_block_candidate(keypairs[0].public_key(), genesis.time + 600)?;
+        let pow_handle = server.start_block_proof(candidate, 200, pow_conf)?;
+        server.apply_block(block)?;
+        assert!(pow_handle.is_finished());
+        assert!(pow_handle.wait().is_none());
+
+        Ok(())
+    }
+}
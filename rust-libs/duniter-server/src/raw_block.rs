@@ -0,0 +1,93 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Raw text format of blocks v10, used to compute their inner hash and hash.
+//!
+//! The inner part of a block and the compact format of its transactions come from the dubp
+//! serializers, only the proof fields, which the dubp block requires, are formatted here.
+
+use crate::*;
+use duniter_core::crypto::keys::{ed25519::Signature, Signature as _};
+
+/// Part of the block covered by the inner hash. The proof fields of `block` are not part of it,
+/// they can be missing.
+pub(crate) fn inner_part(block: &DubpBlockV10Stringified) -> DuniterServerResult<String> {
+    let mut block = block.clone();
+    // Any well-formed proof will do, the dubp parser only requires one
+    block.inner_hash = Some(Hash::default().to_hex());
+    block.signature = Signature([0; 64]).to_base64();
+    block.hash = Some(Hash::default().to_hex());
+    Ok(DubpBlockV10::from_string_object(&block)
+        .map_err(DuniterServerError::deser)?
+        .generate_compact_inner_text())
+}
+
+/// Hash of the inner part of `block`
+pub(crate) fn inner_hash(block: &DubpBlockV10Stringified) -> DuniterServerResult<Hash> {
+    Ok(Hash::compute(inner_part(block)?.as_bytes()))
+}
+
+/// Part of the block covered by the signature of its issuer
+pub(crate) fn signed_part(inner_hash: &Hash, nonce: u64) -> String {
+    format!(
+        "InnerHash: {}\nNonce: {}\n",
+        inner_hash.to_hex().to_uppercase(),
+        nonce
+    )
+}
+
+/// The hash of a block covers its signed part and its signature
+pub(crate) fn block_hash(signed_part: &str, signature: &str) -> Hash {
+    Hash::compute(format!("{}{}\n", signed_part, signature).as_bytes())
+}
+
+/// Number of lines of a transaction in the compact format of blocks
+pub(crate) fn compact_tx_lines(tx: &TransactionDocumentV10) -> usize {
+    tx.to_compact_document().as_compact_text().lines().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::tests::{builder, keypair};
+
+    #[test]
+    fn test_inner_part() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let block = builder(&keypairs).build(&keypairs[0])?;
+        assert!(block.verify_inner_hash().is_ok());
+        assert!(block.verify_hash().is_ok());
+        let genesis = block.to_string_object();
+
+        // The inner part doesn't depend on the proof
+        let mut unsigned = genesis.clone();
+        unsigned.inner_hash = None;
+        unsigned.nonce = 0;
+        unsigned.signature = String::new();
+        unsigned.hash = None;
+        assert_eq!(inner_part(&unsigned)?, inner_part(&genesis)?);
+        assert_eq!(
+            Some(inner_hash(&unsigned)?.to_hex()),
+            genesis.inner_hash.clone()
+        );
+        let signed_part = signed_part(&inner_hash(&genesis)?, genesis.nonce);
+        assert_eq!(
+            Some(block_hash(&signed_part, &genesis.signature).to_hex()),
+            genesis.hash
+        );
+
+        Ok(())
+    }
+}
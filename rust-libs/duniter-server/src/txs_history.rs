@@ -0,0 +1,482 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::dbs::{databases::txs_mp_v2::TxsMpV2DbReadable, HashKeyV2};
+use duniter_core::documents::transaction::TransactionDocumentTrait;
+use duniter_core::wallet::prelude::*;
+use duniter_gva_db::{GvaV1DbReadable, GvaV1DbRo, WalletHashWithBnV1Db};
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+pub type WrittenTx = (TransactionDocumentV10, Blockstamp, i64);
+pub type PendingTx = (TransactionDocumentV10, i64);
+
+/// Restrict the history to the transactions written in a range of blocks or in a range
+/// of time (bounds included).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxsHistoryRange {
+    /// Pending transactions are excluded
+    Blocks { from: u32, to: u32 },
+    /// Pending transactions are filtered by received time
+    Times { from: i64, to: i64 },
+}
+
+/// Last transaction of a page of history, the next page starts after it.
+///
+/// The history lists the pending transactions, most recently received first, then the written
+/// transactions, most recently written first. A transaction sent to oneself is listed twice,
+/// as sent and as received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TxsHistoryCursor {
+    Written {
+        block_number: u32,
+        hash: Hash,
+        sent: bool,
+    },
+    Pending {
+        received_time: i64,
+        hash: Hash,
+        sent: bool,
+    },
+}
+
+impl std::fmt::Display for TxsHistoryCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let direction = |sent: bool| if sent { "sent" } else { "received" };
+        match self {
+            Self::Written {
+                block_number,
+                hash,
+                sent,
+            } => write!(f, "written:{}:{}:{}", block_number, hash, direction(*sent)),
+            Self::Pending {
+                received_time,
+                hash,
+                sent,
+            } => write!(f, "pending:{}:{}:{}", received_time, hash, direction(*sent)),
+        }
+    }
+}
+
+impl FromStr for TxsHistoryCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid txs history cursor '{}'", s);
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 4 {
+            return Err(invalid());
+        }
+        let hash = Hash::from_hex(parts[2]).map_err(|_| invalid())?;
+        let sent = match parts[3] {
+            "sent" => true,
+            "received" => false,
+            _ => return Err(invalid()),
+        };
+        match parts[0] {
+            "written" => Ok(Self::Written {
+                block_number: parts[1].parse().map_err(|_| invalid())?,
+                hash,
+                sent,
+            }),
+            "pending" => Ok(Self::Pending {
+                received_time: parts[1].parse().map_err(|_| invalid())?,
+                hash,
+                sent,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// One page of the transactions history of an account
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxsHistoryPage {
+    pub sent: Vec<WrittenTx>,
+    pub received: Vec<WrittenTx>,
+    pub pending_sent: Vec<PendingTx>,
+    pub pending_received: Vec<PendingTx>,
+    /// Balance variation of the account due to the written transactions of this page
+    pub balance_delta: SourceAmount,
+    /// Balance variation of the account if all the pending transactions of this page are written
+    pub pending_balance_delta: SourceAmount,
+    /// Start of the next page, if any
+    pub next_cursor: Option<TxsHistoryCursor>,
+}
+
+/// Transaction of a history, as sent or as received
+#[derive(Clone, Debug, PartialEq)]
+enum HistoryTx {
+    Written(bool, WrittenTx),
+    Pending(bool, PendingTx),
+}
+
+impl HistoryTx {
+    fn cursor(&self) -> TxsHistoryCursor {
+        match self {
+            Self::Written(sent, (tx, blockstamp, _)) => TxsHistoryCursor::Written {
+                block_number: blockstamp.number.0,
+                hash: tx.get_hash(),
+                sent: *sent,
+            },
+            Self::Pending(sent, (tx, received_time)) => TxsHistoryCursor::Pending {
+                received_time: *received_time,
+                hash: tx.get_hash(),
+                sent: *sent,
+            },
+        }
+    }
+}
+
+impl DuniterServer {
+    /// Read the page of history that starts after `cursor_opt`, the first page if it is `None`.
+    ///
+    /// Only the transactions of the page are read, from the indexes by issuer and by recipient
+    /// of gva_v1 and of the mempool.
+    pub fn get_transactions_history_page(
+        &self,
+        pubkey: PublicKey,
+        range_opt: Option<TxsHistoryRange>,
+        cursor_opt: Option<TxsHistoryCursor>,
+        page_size: usize,
+    ) -> DuniterServerResult<TxsHistoryPage> {
+        // One more transaction tells if there is a next page
+        let limit = page_size.saturating_add(1);
+        let gva_db_ro = duniter_gva_indexer::get_gva_db_ro(self.profile_path_opt.as_deref());
+        let txs = self
+            .dbs_pool
+            .execute(move |dbs| {
+                let mut txs = pending_txs(&dbs.txs_mp_db, pubkey, range_opt, cursor_opt, limit)?;
+                if txs.len() < limit {
+                    txs.extend(written_txs(
+                        gva_db_ro,
+                        pubkey,
+                        range_opt,
+                        cursor_opt,
+                        limit - txs.len(),
+                    )?);
+                }
+                Ok::<_, KvError>(txs)
+            })
+            .expect("dbs pool disconnected")?;
+        Ok(history_page(txs, pubkey, page_size))
+    }
+}
+
+/// Pending transactions of `pubkey` after the cursor, most recently received first
+fn pending_txs<TxsMpDb: TxsMpV2DbReadable>(
+    txs_mp_db: &TxsMpDb,
+    pubkey: PublicKey,
+    range_opt: Option<TxsHistoryRange>,
+    cursor_opt: Option<TxsHistoryCursor>,
+    limit: usize,
+) -> KvResult<Vec<HistoryTx>> {
+    let (from, to) = match range_opt {
+        Some(TxsHistoryRange::Blocks { .. }) => return Ok(Vec::new()),
+        Some(TxsHistoryRange::Times { from, to }) => (from, to),
+        None => (i64::MIN, i64::MAX),
+    };
+    // All the pending transactions are in the previous pages
+    if let Some(TxsHistoryCursor::Written { .. }) = cursor_opt {
+        return Ok(Vec::new());
+    }
+
+    // The mempool is small, its received time index is read over the whole range
+    let issued = txs_mp_db
+        .txs_by_issuer()
+        .get(&PubKeyKeyV2(pubkey))?
+        .unwrap_or_default();
+    let received = txs_mp_db
+        .txs_by_recipient()
+        .get(&PubKeyKeyV2(pubkey))?
+        .unwrap_or_default();
+    let mut hashs = HashMap::new();
+    for (sent, tx_hashs) in &[(true, issued), (false, received)] {
+        for hash in &tx_hashs.0 {
+            hashs.entry(*hash).or_insert_with(Vec::new).push(*sent);
+        }
+    }
+    let mut keys = Vec::new();
+    txs_mp_db.txs_by_received_time().iter(from..=to, |it| {
+        for entry_res in it {
+            let (received_time, received_hashs) = entry_res?;
+            for hash in received_hashs.0 {
+                for sent in hashs.get(&hash).into_iter().flatten() {
+                    let key = TxsHistoryCursor::Pending {
+                        received_time,
+                        hash,
+                        sent: *sent,
+                    };
+                    if cursor_opt.map_or(true, |cursor| key < cursor) {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+        Ok::<_, KvError>(())
+    })?;
+    keys.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut txs = Vec::with_capacity(limit.min(keys.len()));
+    for key in keys.into_iter().take(limit) {
+        if let TxsHistoryCursor::Pending {
+            received_time,
+            hash,
+            sent,
+        } = key
+        {
+            if let Some(pending_tx) = txs_mp_db.txs().get(&HashKeyV2(hash))? {
+                txs.push(HistoryTx::Pending(sent, (pending_tx.doc, received_time)));
+            }
+        }
+    }
+    Ok(txs)
+}
+
+/// Written transactions of `pubkey` after the cursor, most recently written first
+fn written_txs(
+    gva_db_ro: &GvaV1DbRo<FileBackend>,
+    pubkey: PublicKey,
+    range_opt: Option<TxsHistoryRange>,
+    cursor_opt: Option<TxsHistoryCursor>,
+    limit: usize,
+) -> KvResult<Vec<HistoryTx>> {
+    let (from, mut to) = match range_opt {
+        Some(TxsHistoryRange::Blocks { from, to }) => (from, to),
+        _ => (0, u32::MAX),
+    };
+    if let Some(TxsHistoryCursor::Written { block_number, .. }) = cursor_opt {
+        to = to.min(block_number);
+    }
+    if from > to {
+        return Ok(Vec::new());
+    }
+    let script_hash = Hash::compute(
+        WalletScriptV10::single(WalletConditionV10::Sig(pubkey))
+            .to_string()
+            .as_bytes(),
+    );
+    let range = WalletHashWithBnV1Db::new(script_hash, BlockNumber(from))
+        ..=WalletHashWithBnV1Db::new(script_hash, BlockNumber(to));
+
+    let mut sent = gva_db_ro.txs_by_issuer().iter_rev(range.clone(), |it| {
+        read_written_txs(gva_db_ro, true, it.values(), range_opt, cursor_opt, limit)
+    })?;
+    let received = gva_db_ro.txs_by_recipient().iter_rev(range, |it| {
+        read_written_txs(gva_db_ro, false, it.values(), range_opt, cursor_opt, limit)
+    })?;
+    sent.extend(received);
+    sent.sort_unstable_by_key(|tx| std::cmp::Reverse(tx.cursor()));
+    sent.truncate(limit);
+    Ok(sent)
+}
+
+/// Read the transactions of an index by wallet, from the most recent block, until `limit`
+fn read_written_txs<I: Iterator<Item = KvResult<BTreeSet<Hash>>>>(
+    gva_db_ro: &GvaV1DbRo<FileBackend>,
+    sent: bool,
+    hashs_by_block: I,
+    range_opt: Option<TxsHistoryRange>,
+    cursor_opt: Option<TxsHistoryCursor>,
+    limit: usize,
+) -> KvResult<Vec<HistoryTx>> {
+    let mut txs = Vec::new();
+    for hashs_res in hashs_by_block {
+        for hash in hashs_res?.into_iter().rev() {
+            if txs.len() == limit {
+                return Ok(txs);
+            }
+            let gva_tx = if let Some(gva_tx) = gva_db_ro.txs().get(&HashKeyV2(hash))? {
+                gva_tx
+            } else {
+                continue;
+            };
+            // Median times never go back, the blocks before `from` are all out of range
+            if let Some(TxsHistoryRange::Times { from, to }) = range_opt {
+                if gva_tx.written_time < from {
+                    return Ok(txs);
+                } else if gva_tx.written_time > to {
+                    continue;
+                }
+            }
+            let tx =
+                HistoryTx::Written(sent, (gva_tx.tx, gva_tx.written_block, gva_tx.written_time));
+            if cursor_opt.map_or(true, |cursor| tx.cursor() < cursor) {
+                txs.push(tx);
+            }
+        }
+    }
+    Ok(txs)
+}
+
+/// Split the first `page_size` transactions of `txs` by kind
+fn history_page(mut txs: Vec<HistoryTx>, pubkey: PublicKey, page_size: usize) -> TxsHistoryPage {
+    let next_cursor = if txs.len() > page_size {
+        txs.truncate(page_size);
+        txs.last().map(HistoryTx::cursor)
+    } else {
+        None
+    };
+    let mut page = TxsHistoryPage {
+        sent: Vec::new(),
+        received: Vec::new(),
+        pending_sent: Vec::new(),
+        pending_received: Vec::new(),
+        balance_delta: SourceAmount::ZERO,
+        pending_balance_delta: SourceAmount::ZERO,
+        next_cursor,
+    };
+
+    // A transaction sent to oneself is both sent and received, count it once
+    let mut counted = BTreeSet::new();
+    for tx in txs {
+        match tx {
+            HistoryTx::Written(sent, tx) => {
+                if counted.insert(tx.0.get_hash()) {
+                    page.balance_delta = page.balance_delta + balance_delta(&tx.0, pubkey);
+                }
+                if sent {
+                    page.sent.push(tx);
+                } else {
+                    page.received.push(tx);
+                }
+            }
+            HistoryTx::Pending(sent, tx) => {
+                if counted.insert(tx.0.get_hash()) {
+                    page.pending_balance_delta =
+                        page.pending_balance_delta + balance_delta(&tx.0, pubkey);
+                }
+                if sent {
+                    page.pending_sent.push(tx);
+                } else {
+                    page.pending_received.push(tx);
+                }
+            }
+        }
+    }
+    page
+}
+
+/// The issuer of a transaction loses what it sends to others (the change comes back to it),
+/// a recipient gains what is sent to it.
+fn balance_delta(tx: &TransactionDocumentV10, pubkey: PublicKey) -> SourceAmount {
+    let own_script = WalletScriptV10::single(WalletConditionV10::Sig(pubkey));
+    let is_issuer = tx.issuers().contains(&pubkey);
+    let mut delta = SourceAmount::ZERO;
+    for output in tx.get_outputs() {
+        let to_self = output.conditions.script == own_script;
+        if is_issuer && !to_self {
+            delta = delta - output.amount;
+        } else if !is_issuer && to_self {
+            delta = delta + output.amount;
+        }
+    }
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use duniter_core::documents::{smallvec::smallvec, transaction::TransactionDocumentV10Builder};
+
+    fn tx(comment: &'static str) -> TransactionDocumentV10 {
+        TransactionDocumentV10Builder {
+            currency: "test",
+            blockstamp: Blockstamp::default(),
+            locktime: 0,
+            issuers: smallvec![PublicKey::default()],
+            inputs: &[],
+            unlocks: &[],
+            outputs: smallvec![],
+            comment,
+            hash: None,
+        }
+        .build_with_signature(smallvec![])
+    }
+
+    fn written(sent: bool, comment: &'static str, block_number: u32) -> HistoryTx {
+        HistoryTx::Written(
+            sent,
+            (
+                tx(comment),
+                Blockstamp {
+                    number: BlockNumber(block_number),
+                    hash: BlockHash(Hash::default()),
+                },
+                1_600_000_000 + block_number as i64 * 300,
+            ),
+        )
+    }
+
+    fn numbers(txs: &[WrittenTx]) -> Vec<u32> {
+        txs.iter()
+            .map(|(_, blockstamp, _)| blockstamp.number.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_history_page() {
+        let pubkey = PublicKey::default();
+        let txs = vec![
+            HistoryTx::Pending(true, (tx("p1"), 1_600_002_000)),
+            written(true, "s5", 5),
+            written(false, "r4", 4),
+            written(true, "s3", 3),
+        ];
+
+        let page = history_page(txs.clone(), pubkey, 3);
+        assert_eq!(page.pending_sent.len(), 1);
+        assert_eq!(numbers(&page.sent), vec![5]);
+        assert_eq!(numbers(&page.received), vec![4]);
+        assert_eq!(page.next_cursor, Some(txs[2].cursor()));
+
+        let page = history_page(txs[3..].to_vec(), pubkey, 3);
+        assert!(page.pending_sent.is_empty());
+        assert_eq!(numbers(&page.sent), vec![3]);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn test_cursor_order() {
+        // Pending transactions come first, then the most recent written transactions
+        let mut cursors = vec![
+            written(true, "s3", 3).cursor(),
+            written(false, "r4", 4).cursor(),
+            HistoryTx::Pending(true, (tx("p1"), 1_600_002_000)).cursor(),
+            HistoryTx::Pending(false, (tx("p2"), 1_600_002_300)).cursor(),
+        ];
+        cursors.sort_unstable_by(|a, b| b.cmp(a));
+        assert!(matches!(
+            cursors[0],
+            TxsHistoryCursor::Pending {
+                received_time: 1_600_002_300,
+                ..
+            }
+        ));
+        assert!(matches!(
+            cursors[3],
+            TxsHistoryCursor::Written {
+                block_number: 3,
+                ..
+            }
+        ));
+
+        for cursor in cursors {
+            assert_eq!(TxsHistoryCursor::from_str(&cursor.to_string()), Ok(cursor));
+        }
+        assert!(TxsHistoryCursor::from_str("written:3").is_err());
+    }
+}
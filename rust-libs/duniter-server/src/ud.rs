@@ -0,0 +1,304 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::dbs::U32BE;
+use duniter_core::wallet::prelude::SourceAmount;
+use resiter::filter_map::FilterMap;
+
+/// Universal dividend created by a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UdEntry {
+    pub block_number: BlockNumber,
+    pub median_time: u64,
+    pub amount: SourceAmount,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UdProjection {
+    /// The next UD is created by the first block whose median time reaches this time
+    pub time: u64,
+    pub amount: SourceAmount,
+    /// The amount of the next UD is reevaluated
+    pub reevaluation: bool,
+}
+
+impl DuniterServer {
+    /// UDs created by the blocks `from..=to`
+    pub fn get_ud_history(&self, from: u32, to: u32) -> DuniterServerResult<Vec<UdEntry>> {
+        Ok(self
+            .bc_db
+            .blocks_meta()
+            .iter(U32BE(from)..=U32BE(to), |it| {
+                it.values()
+                    .filter_map_ok(|block_meta| {
+                        block_meta.dividend.map(|amount| UdEntry {
+                            block_number: BlockNumber(block_meta.number),
+                            median_time: block_meta.median_time,
+                            amount,
+                        })
+                    })
+                    .collect::<KvResult<Vec<_>>>()
+            })?)
+    }
+    pub fn get_next_ud_projection(&self) -> DuniterServerResult<Option<UdProjection>> {
+        let current = if let Some(current) = self.current {
+            current
+        } else {
+            return Ok(None);
+        };
+        let last_ud_opt = self.bc_db.blocks_meta().iter_rev(.., |it| {
+            it.values()
+                .filter_map_ok(|block_meta| {
+                    block_meta
+                        .dividend
+                        .map(|amount| (block_meta.median_time, amount))
+                })
+                .next_res()
+        })?;
+        Ok(Some(project_next_ud(
+            &self.currency_params,
+            last_ud_opt,
+            current.members_count,
+            |reeval_time| self.mass_reeval(reeval_time),
+        )?))
+    }
+    /// `HEAD_1.massReeval` of the block reaching `reeval_time`: the monetary mass before the
+    /// previous reevaluation, none before the first one
+    fn mass_reeval(&self, reeval_time: u64) -> KvResult<u64> {
+        if let Some(previous_reeval_time) = previous_reeval_time(&self.currency_params, reeval_time)
+        {
+            Ok(self
+                .last_block_before(previous_reeval_time)?
+                .map_or(0, |block_meta| block_meta.monetary_mass))
+        } else {
+            Ok(0)
+        }
+    }
+    /// Last block whose median time is before `median_time`, by bisection since the median
+    /// time never goes back
+    fn last_block_before(&self, median_time: u64) -> KvResult<Option<BlockMetaV2>> {
+        let mut found = None;
+        let mut low = 0;
+        let mut high = self.current.map_or(0, |current| current.number + 1);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.bc_db.blocks_meta().get(&U32BE(middle))? {
+                Some(block_meta) if block_meta.median_time < median_time => {
+                    found = Some(block_meta);
+                    low = middle + 1;
+                }
+                _ => high = middle,
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// DUBP: the UD is created every `dt` seconds from `ud_time0` and reevaluated every
+/// `dt_reeval` seconds from `ud_reeval_time0` (BR_G13):
+/// `UD(t+1) = ceil(UD(t) + c² * ceil(massReeval / 10^unitBase) / N / (dt_reeval / dt))`
+fn project_next_ud<F>(
+    currency_params: &CurrencyParameters,
+    last_ud_opt: Option<(u64, SourceAmount)>,
+    members_count: u64,
+    mass_reeval_at: F,
+) -> KvResult<UdProjection>
+where
+    F: FnOnce(u64) -> KvResult<u64>,
+{
+    let (last_ud_time, last_ud) = if let Some(last_ud) = last_ud_opt {
+        last_ud
+    } else {
+        return Ok(UdProjection {
+            time: currency_params.ud_time0,
+            amount: SourceAmount::new(currency_params.ud0 as i64, 0),
+            reevaluation: false,
+        });
+    };
+
+    let time = next_occurrence(currency_params.ud_time0, currency_params.dt, last_ud_time);
+    let reeval_time = next_occurrence(
+        currency_params.ud_reeval_time0,
+        currency_params.dt_reeval,
+        last_ud_time,
+    );
+    let reevaluation = time >= reeval_time && members_count > 0;
+
+    let amount = if reevaluation {
+        let base = last_ud.base();
+        let mass_reeval = (mass_reeval_at(reeval_time)? as f64 / 10f64.powi(base as i32)).ceil();
+        let dts_per_reeval = currency_params.dt_reeval as f64 / currency_params.dt as f64;
+        let dividend = (last_ud.amount() as f64
+            + currency_params.c * currency_params.c * mass_reeval
+                / (members_count as f64)
+                / dts_per_reeval)
+            .ceil();
+        to_source_amount(dividend, base)
+    } else {
+        last_ud
+    };
+
+    Ok(UdProjection {
+        time,
+        amount,
+        reevaluation,
+    })
+}
+
+/// First `time0 + k * period` strictly after `after`
+fn next_occurrence(time0: u64, period: u64, after: u64) -> u64 {
+    if after < time0 || period == 0 {
+        time0
+    } else {
+        time0 + ((after - time0) / period + 1) * period
+    }
+}
+
+/// Reevaluation before the one at `reeval_time`, if any
+fn previous_reeval_time(currency_params: &CurrencyParameters, reeval_time: u64) -> Option<u64> {
+    reeval_time
+        .checked_sub(currency_params.dt_reeval)
+        .filter(|previous| *previous >= currency_params.ud_reeval_time0)
+}
+
+/// BR_G14: an UD amount has at most 4 digits, beyond them the unit base is increased
+fn to_source_amount(dividend: f64, base: i64) -> SourceAmount {
+    if dividend >= 10_000.0 {
+        SourceAmount::new((dividend / 10.0).ceil() as i64, base + 1)
+    } else {
+        SourceAmount::new(dividend as i64, base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency_params() -> CurrencyParameters {
+        CurrencyParameters {
+            c: 0.0488,
+            dt: 86_400,
+            dt_reeval: 15_778_800,
+            ud0: 1_000,
+            ud_time0: 1_488_970_800,
+            ud_reeval_time0: 1_490_094_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_next_occurrence() {
+        assert_eq!(next_occurrence(100, 10, 50), 100);
+        assert_eq!(next_occurrence(100, 10, 100), 110);
+        assert_eq!(next_occurrence(100, 10, 119), 120);
+    }
+
+    #[test]
+    fn test_previous_reeval_time() {
+        let params = currency_params();
+        assert_eq!(previous_reeval_time(&params, params.ud_reeval_time0), None);
+        assert_eq!(
+            previous_reeval_time(&params, params.ud_reeval_time0 + params.dt_reeval),
+            Some(params.ud_reeval_time0)
+        );
+    }
+
+    #[test]
+    fn test_project_first_ud() -> KvResult<()> {
+        let params = currency_params();
+        assert_eq!(
+            project_next_ud(&params, None, 59, |_| Ok(0))?,
+            UdProjection {
+                time: params.ud_time0,
+                amount: SourceAmount::new(1_000, 0),
+                reevaluation: false,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_next_ud() -> KvResult<()> {
+        let params = currency_params();
+        let last_ud = SourceAmount::new(1_000, 0);
+
+        // Between two reevaluations the UD doesn't change
+        let projection = project_next_ud(&params, Some((params.ud_time0, last_ud)), 59, |_| {
+            Ok(59_000)
+        })?;
+        assert_eq!(projection.time, params.ud_time0 + params.dt);
+        assert_eq!(projection.amount, last_ud);
+        assert!(!projection.reevaluation);
+
+        // The first reevaluation of the Ğ1 has no mass to reevaluate: the UD stays 10.00
+        let last_ud_time = params.ud_reeval_time0 - 1;
+        let projection = project_next_ud(&params, Some((last_ud_time, last_ud)), 59, |time| {
+            assert_eq!(time, params.ud_reeval_time0);
+            Ok(0)
+        })?;
+        assert!(projection.reevaluation);
+        assert_eq!(projection.amount, last_ud);
+
+        // BR_G13 reference: ceil(100 + 0.0488² * ceil(18_000 / 10) / 3 / (100 / 100)) = 102
+        let params = CurrencyParameters {
+            dt: 100,
+            dt_reeval: 100,
+            ..currency_params()
+        };
+        let last_ud = SourceAmount::new(100, 1);
+        let projection =
+            project_next_ud(&params, Some((last_ud_time, last_ud)), 3, |_| Ok(18_000))?;
+        assert!(projection.reevaluation);
+        assert_eq!(projection.amount, SourceAmount::new(102, 1));
+
+        // ceil(9_990 + 0.0488² * 1_000_000 / 1 / (15_778_800 / 86_400)) = 10_004, over 4 digits
+        let params = currency_params();
+        let last_ud = SourceAmount::new(9_990, 0);
+        let projection =
+            project_next_ud(&params, Some((last_ud_time, last_ud)), 1, |_| Ok(1_000_000))?;
+        assert_eq!(projection.amount, SourceAmount::new(1_001, 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_ud_projection() -> anyhow::Result<()> {
+        use crate::genesis::tests::keypair;
+        use crate::mempool::tests::server_with_uds;
+
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let server = server_with_uds(&keypairs, 10)?;
+        assert_eq!(server.last_block_before(1_600_000_000)?, None);
+        assert_eq!(server.mass_reeval(1_600_000_000)?, 0);
+
+        let projection = server
+            .get_next_ud_projection()?
+            .ok_or_else(|| anyhow::anyhow!("no projection"))?;
+        assert_eq!(projection.time, 1_600_086_400);
+        assert_eq!(projection.amount, SourceAmount::new(1_000, 0));
+        assert!(!projection.reevaluation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_source_amount() {
+        assert_eq!(to_source_amount(9_999.0, 0), SourceAmount::new(9_999, 0));
+        assert_eq!(to_source_amount(10_000.0, 0), SourceAmount::new(1_000, 1));
+        assert_eq!(to_source_amount(10_001.0, 0), SourceAmount::new(1_001, 1));
+        assert_eq!(to_source_amount(1_001.0, 1), SourceAmount::new(1_001, 1));
+    }
+}
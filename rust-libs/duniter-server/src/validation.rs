@@ -0,0 +1,752 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+mod wot;
+
+use crate::*;
+use duniter_core::crypto::keys::PublicKey as _;
+use duniter_core::dbs::{UdIdV2, UtxoIdDbV2, U32BE};
+use duniter_core::documents::transaction::{SourceIdV10, TransactionDocumentTrait};
+use duniter_core::wallet::prelude::SourceAmount;
+use std::collections::{HashMap, HashSet};
+
+/// A DUBP rule that a block doesn't respect
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleViolation {
+    /// Stable identifier of the rule, like `PREVIOUS_HASH`
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl RuleViolation {
+    fn new<S: Into<String>>(rule: &'static str, message: S) -> Self {
+        RuleViolation {
+            rule,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for RuleViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.rule, self.message)
+    }
+}
+
+impl DuniterServer {
+    /// Check that `block` can be applied on top of the current block, without modifying
+    /// anything. Return all the rules that the block doesn't respect.
+    pub fn validate_block(
+        &self,
+        block_stringified: &DubpBlockV10Stringified,
+    ) -> Result<(), Vec<RuleViolation>> {
+        let block = match DubpBlockV10::from_string_object(block_stringified) {
+            Ok(block) => block,
+            Err(e) => return Err(vec![RuleViolation::new("FORMAT", e.to_string())]),
+        };
+
+        let mut violations = check_local_rules(block_stringified, &block);
+        violations.append(&mut self.global_rules_violations(block_stringified, &block));
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+    /// Rules depending on the current block only, the local rules are not checked
+    pub(crate) fn global_rules_violations(
+        &self,
+        block_stringified: &DubpBlockV10Stringified,
+        block: &DubpBlockV10,
+    ) -> Vec<RuleViolation> {
+        let mut violations = Vec::new();
+        if let Err(e) = self.check_global_rules(block_stringified, block, &mut violations) {
+            violations.push(RuleViolation::new(
+                "DB_ERROR",
+                format!("fail to read the blockchain state: {}", e),
+            ));
+        }
+        violations
+    }
+    fn check_global_rules(
+        &self,
+        block_stringified: &DubpBlockV10Stringified,
+        block: &DubpBlockV10,
+        violations: &mut Vec<RuleViolation>,
+    ) -> DuniterServerResult<()> {
+        let mut check = |ok: bool, rule: &'static str, message: String| {
+            if !ok {
+                violations.push(RuleViolation::new(rule, message));
+            }
+        };
+
+        let joiners = block_stringified.joiners.len() as u64;
+        let excluded = block_stringified.excluded.len() as u64;
+        if let Some(current) = self.current {
+            check(
+                block_stringified.number == current.number as u64 + 1,
+                "NUMBER",
+                format!("expected block #{}", current.number + 1),
+            );
+            check(
+                block_stringified
+                    .previous_hash
+                    .as_deref()
+                    .and_then(|hash| Hash::from_hex(hash).ok())
+                    == Some(current.hash),
+                "PREVIOUS_HASH",
+                format!("expected previous hash {}", current.hash),
+            );
+            check(
+                block_stringified.previous_issuer.as_deref() == Some(&current.issuer.to_base58()),
+                "PREVIOUS_ISSUER",
+                format!("expected previous issuer {}", current.issuer.to_base58()),
+            );
+            let expected_median_time = self.next_median_time(&current)?;
+            check(
+                block_stringified.median_time == expected_median_time,
+                "MEDIAN_TIME",
+                format!("expected median time {}", expected_median_time),
+            );
+            check(
+                block_stringified.members_count + excluded == current.members_count + joiners,
+                "MEMBERS_COUNT",
+                format!(
+                    "expected {} members",
+                    (current.members_count + joiners).saturating_sub(excluded)
+                ),
+            );
+
+            let dividend_opt = block_stringified.dividend.map(|dividend| {
+                SourceAmount::new(dividend as i64, block_stringified.unit_base as i64)
+            });
+            let expected_dividend = self
+                .get_next_ud_projection()?
+                .filter(|projection| block_stringified.median_time >= projection.time)
+                .map(|projection| projection.amount);
+            check(
+                dividend_opt == expected_dividend,
+                "DIVIDEND",
+                format!("expected dividend {:?}", expected_dividend),
+            );
+            let expected_monetary_mass = current.monetary_mass
+                + dividend_opt.map_or(0, |dividend| {
+                    dividend.amount() as u64
+                        * 10u64.pow(dividend.base() as u32)
+                        * block_stringified.members_count
+                });
+            check(
+                block_stringified.monetary_mass == expected_monetary_mass,
+                "MONETARY_MASS",
+                format!("expected monetary mass {}", expected_monetary_mass),
+            );
+            let expected_unit_base = expected_dividend
+                .map_or(u64::from(current.unit_base), |dividend| {
+                    dividend.base() as u64
+                });
+            check(
+                block_stringified.unit_base == expected_unit_base,
+                "UNIT_BASE",
+                format!("expected unit base {}", expected_unit_base),
+            );
+
+            // Difficulty of the block and of its issuer
+            let pow_min = self.next_pow_min(&current, expected_median_time)?;
+            check(
+                block_stringified.pow_min == pow_min,
+                "POW_MIN",
+                format!("expected pow_min {}", pow_min),
+            );
+            let issuers_count = self.next_issuers_count(&current)?;
+            let (issuers_frame, issuers_frame_var) = crate::difficulty::next_issuers_frame(
+                current.issuers_frame,
+                current.issuers_frame_var,
+                u64::from(current.issuers_count),
+                issuers_count,
+            );
+            check(
+                block_stringified.issuers_count == issuers_count,
+                "ISSUERS_COUNT",
+                format!("expected {} issuers", issuers_count),
+            );
+            check(
+                block_stringified.issuers_frame == issuers_frame,
+                "ISSUERS_FRAME",
+                format!("expected issuers frame {}", issuers_frame),
+            );
+            check(
+                block_stringified.issuers_frame_var == issuers_frame_var,
+                "ISSUERS_FRAME_VAR",
+                format!("expected issuers frame variation {}", issuers_frame_var),
+            );
+            if let Ok(issuer) = PublicKey::from_base58(&block_stringified.issuer) {
+                if let Some(personalized) = self.get_personalized_difficulty(issuer)? {
+                    check(
+                        crate::genesis::hash_matches_pow(
+                            &block.hash().0,
+                            personalized.difficulty as usize,
+                        ),
+                        "PERSONALIZED_POW",
+                        format!(
+                            "the hash doesn't match the personalized difficulty {} of the issuer",
+                            personalized.difficulty
+                        ),
+                    );
+                }
+            }
+        } else {
+            check(
+                block_stringified.number == 0,
+                "NUMBER",
+                "expected block #0".to_owned(),
+            );
+            check(
+                block_stringified.members_count + excluded == joiners,
+                "MEMBERS_COUNT",
+                format!("expected {} members", joiners),
+            );
+            check(
+                block_stringified.dividend.is_none() && block_stringified.monetary_mass == 0,
+                "DIVIDEND",
+                "the genesis block can't create money".to_owned(),
+            );
+        }
+
+        for identity in &block_stringified.identities {
+            let mut fields = identity.split(':');
+            let pubkey_opt = fields.next().and_then(|pk| PublicKey::from_base58(pk).ok());
+            let uid = fields.nth(2).unwrap_or_default();
+            let pubkey_written = match pubkey_opt {
+                Some(pubkey) => self.bc_db.identities().contains_key(&PubKeyKeyV2(pubkey))?,
+                None => false,
+            };
+            check(
+                !pubkey_written && !self.bc_db.uids_index().contains_key(&uid.to_owned())?,
+                "IDENTITY_ALREADY_WRITTEN",
+                format!("identity {} is already written", uid),
+            );
+        }
+
+        let gva_db_ro = duniter_gva_indexer::get_gva_db_ro(self.profile_path_opt.as_deref());
+        let mut block_txs: HashMap<Hash, &TransactionDocumentV10> = HashMap::new();
+        let mut spent_uds = HashSet::new();
+        let mut spent_utxos = HashSet::new();
+        for tx in block.transactions() {
+            let hash = tx.get_hash();
+            let blockstamp = tx.get_blockstamp();
+            let blockstamp_ok = match self.bc_db.blocks_meta().get(&U32BE(blockstamp.number.0))? {
+                Some(block_meta) if block_meta.hash == blockstamp.hash.0 => {
+                    crate::mempool::blockstamp_time_ok(
+                        block_meta.median_time,
+                        tx.get_locktime(),
+                        block_stringified.median_time,
+                    )
+                }
+                _ => false,
+            };
+            check(
+                blockstamp_ok,
+                "TX_BLOCKSTAMP",
+                format!("transaction {} has an invalid or expired blockstamp", hash),
+            );
+            let signatures_ok = tx.verify_signatures().is_ok();
+            check(
+                signatures_ok,
+                "TX_SIGNATURES",
+                format!("transaction {} is not signed by all its issuers", hash),
+            );
+            // Once the signatures are checked, the document is only invalid if its inputs and
+            // outputs don't balance
+            check(
+                !signatures_ok || tx.verify(None).is_ok(),
+                "TX_BALANCE",
+                format!(
+                    "the inputs and outputs of transaction {} don't balance",
+                    hash
+                ),
+            );
+
+            let mut sources_ok = true;
+            for input in tx.get_inputs() {
+                // A source can only be spent once in a block
+                let available = match input.id {
+                    SourceIdV10::Ud(ud_id) => {
+                        spent_uds.insert((ud_id.issuer, ud_id.block_number.0))
+                            && self
+                                .bc_db
+                                .uds()
+                                .contains_key(&UdIdV2(ud_id.issuer, ud_id.block_number))?
+                    }
+                    SourceIdV10::Utxo(utxo_id) => {
+                        spent_utxos.insert((utxo_id.tx_hash, utxo_id.output_index))
+                            && match block_txs.get(&utxo_id.tx_hash) {
+                                Some(parent_tx) => {
+                                    utxo_id.output_index < parent_tx.get_outputs().len()
+                                }
+                                None => self.bc_db.utxos().contains_key(&UtxoIdDbV2(
+                                    utxo_id.tx_hash,
+                                    utxo_id.output_index as u32,
+                                ))?,
+                            }
+                    }
+                };
+                check(
+                    available,
+                    "TX_SOURCES",
+                    format!("transaction {} spends an unavailable source", hash),
+                );
+                sources_ok &= available;
+            }
+            // The conditions of the sources, with the CSV and CLTV time locks from their
+            // written time
+            if sources_ok {
+                check(
+                    crate::mempool::inputs_unlockable(
+                        &self.bc_db,
+                        gva_db_ro,
+                        |tx_hash| block_txs.get(tx_hash).copied(),
+                        tx,
+                        block_stringified.median_time,
+                    )?,
+                    "TX_UNLOCKS",
+                    format!(
+                        "the proofs of transaction {} don't unlock its inputs or a time lock is not over",
+                        hash
+                    ),
+                );
+            }
+            block_txs.insert(hash, tx);
+        }
+
+        self.check_wot_rules(block_stringified, violations)?;
+
+        Ok(())
+    }
+}
+
+/// Rules that only depend on the block itself
+pub(crate) fn check_local_rules(
+    block_stringified: &DubpBlockV10Stringified,
+    block: &DubpBlockV10,
+) -> Vec<RuleViolation> {
+    let mut violations = Vec::new();
+    let mut check = |ok: bool, rule: &'static str, message: &str| {
+        if !ok {
+            violations.push(RuleViolation::new(rule, message));
+        }
+    };
+
+    check(
+        block_stringified.version == 10,
+        "VERSION",
+        "only blocks v10 are supported",
+    );
+    check(
+        block.verify_inner_hash().is_ok(),
+        "INNER_HASH",
+        "the inner hash doesn't match the content of the block",
+    );
+    check(
+        block.verify_signature().is_ok(),
+        "SIGNATURE",
+        "the block is not signed by its issuer",
+    );
+    check(
+        block.verify_hash().is_ok(),
+        "HASH",
+        "the hash doesn't match the signed part of the block",
+    );
+    check(
+        crate::genesis::hash_matches_pow(&block.hash().0, block_stringified.pow_min as usize),
+        "POW",
+        "the hash doesn't match the minimal proof of work",
+    );
+
+    if block_stringified.number == 0 {
+        check(
+            block_stringified.parameters.is_some()
+                && block_stringified.previous_hash.is_none()
+                && block_stringified.previous_issuer.is_none(),
+            "GENESIS_FIELDS",
+            "the genesis block must have parameters and no previous block",
+        );
+        check(
+            block_stringified.time == block_stringified.median_time,
+            "TIME",
+            "the time of the genesis block must be its median time",
+        );
+    } else {
+        check(
+            block_stringified.parameters.is_none()
+                && block_stringified.previous_hash.is_some()
+                && block_stringified.previous_issuer.is_some(),
+            "GENESIS_FIELDS",
+            "only the genesis block has parameters and no previous block",
+        );
+        check(
+            block_stringified.time >= block_stringified.median_time,
+            "TIME",
+            "the time of a block can't be before its median time",
+        );
+    }
+
+    let mut pubkeys = HashSet::new();
+    let mut uids = HashSet::new();
+    for identity in &block_stringified.identities {
+        let fields: Vec<&str> = identity.split(':').collect();
+        check(
+            fields.len() == 4 && pubkeys.insert(fields[0]) && uids.insert(fields[3]),
+            "IDENTITIES_UNIQUE",
+            "an identity is written twice",
+        );
+    }
+
+    let mut certs = HashSet::new();
+    for cert in &block_stringified.certifications {
+        let fields: Vec<&str> = cert.split(':').collect();
+        check(
+            fields.len() == 4 && fields[0] != fields[1] && certs.insert((fields[0], fields[1])),
+            "CERTIFICATIONS_UNIQUE",
+            "a certification is written twice or certifies its issuer",
+        );
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_candidate::tests::forge_block;
+    use crate::genesis::tests::{builder, keypair};
+    use crate::mempool::tests::{server_with_uds, signed_tx, ud_input};
+    use duniter_core::crypto::keys::{KeyPair as _, Signator as _, Signature as _};
+
+    fn genesis() -> anyhow::Result<DubpBlockV10Stringified> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        Ok(builder(&keypairs).build_stringified(&keypairs[0])?)
+    }
+
+    fn violated_rules(result: Result<(), Vec<RuleViolation>>) -> Vec<&'static str> {
+        result
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|violation| violation.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_genesis() -> anyhow::Result<()> {
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        let genesis = genesis()?;
+        assert_eq!(server.validate_block(&genesis), Ok(()));
+
+        // Validation doesn't modify anything
+        assert_eq!(server.get_current_blockstamp(), None);
+
+        server.apply_block(genesis.clone())?;
+        assert_eq!(
+            violated_rules(server.validate_block(&genesis)),
+            vec!["NUMBER"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rules_vectors() -> anyhow::Result<()> {
+        let server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+
+        type Mutation = fn(&mut DubpBlockV10Stringified);
+        let vectors: Vec<(&str, Mutation)> = vec![
+            ("VERSION", |block| block.version = 11),
+            ("INNER_HASH", |block| {
+                block.inner_hash = Some(Hash::default().to_hex())
+            }),
+            ("SIGNATURE", |block| block.nonce += 1),
+            ("POW", |block| block.pow_min = 80),
+            ("GENESIS_FIELDS", |block| block.parameters = None),
+            ("TIME", |block| block.time -= 1),
+            ("IDENTITIES_UNIQUE", |block| {
+                let identity = block.identities[0].clone();
+                block.identities.push(identity)
+            }),
+            ("CERTIFICATIONS_UNIQUE", |block| {
+                let cert = block.certifications[0].clone();
+                block.certifications.push(cert)
+            }),
+            ("NUMBER", |block| block.number = 1),
+            ("MEMBERS_COUNT", |block| block.members_count += 1),
+            ("DIVIDEND", |block| block.dividend = Some(1_000)),
+        ];
+        for (rule, mutation) in vectors {
+            let mut block = genesis()?;
+            mutation(&mut block);
+            let rules = violated_rules(server.validate_block(&block));
+            assert!(rules.contains(&rule), "{}: {:?}", rule, rules);
+        }
+
+        Ok(())
+    }
+
+    /// `compact_doc` with its first field replaced by `pubkey`
+    fn with_pubkey(compact_doc: &str, pubkey: &str) -> String {
+        let mut fields: Vec<&str> = compact_doc.split(':').collect();
+        fields[0] = pubkey;
+        fields.join(":")
+    }
+
+    type BlockMutation = Box<dyn Fn(&mut DubpBlockV10Stringified)>;
+
+    fn mutation(f: impl Fn(&mut DubpBlockV10Stringified) + 'static) -> BlockMutation {
+        Box::new(f)
+    }
+
+    fn assert_violations(
+        server: &DuniterServer,
+        block: &DubpBlockV10Stringified,
+        vectors: Vec<(&str, BlockMutation)>,
+    ) {
+        for (rule, mutation) in vectors {
+            let mut block = block.clone();
+            mutation(&mut block);
+            let rules = violated_rules(server.validate_block(&block));
+            assert!(rules.contains(&rule), "{}: {:?}", rule, rules);
+        }
+    }
+
+    #[test]
+    fn test_block_rules_vectors() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(genesis.clone())?;
+        let block1 = forge_block(&server, &keypairs[0], genesis.time + 300)?;
+        assert_eq!(server.validate_block(&block1), Ok(()));
+
+        let member1 = keypairs[1].public_key().to_base58();
+        let unknown = keypair(4).public_key().to_base58();
+        let identity = genesis.identities[0].clone();
+        let joiner = genesis.joiners[0].clone();
+        let member_joiner = genesis.joiners[1].clone();
+        let cert = genesis.certifications[0].clone();
+        let cert_fields: Vec<String> = cert.split(':').map(ToOwned::to_owned).collect();
+        let idty_sig = identity.split(':').nth(1).unwrap_or_default().to_owned();
+
+        let vectors: Vec<(&str, BlockMutation)> = vec![
+            (
+                "PREVIOUS_HASH",
+                mutation(|block| block.previous_hash = Some(Hash::default().to_hex())),
+            ),
+            ("PREVIOUS_ISSUER", {
+                let member1 = member1.clone();
+                mutation(move |block| block.previous_issuer = Some(member1.clone()))
+            }),
+            ("MEDIAN_TIME", mutation(|block| block.median_time += 1)),
+            ("MONETARY_MASS", mutation(|block| block.monetary_mass += 1)),
+            ("UNIT_BASE", mutation(|block| block.unit_base += 1)),
+            ("POW_MIN", mutation(|block| block.pow_min += 1)),
+            ("ISSUERS_COUNT", mutation(|block| block.issuers_count += 1)),
+            ("ISSUERS_FRAME", mutation(|block| block.issuers_frame += 1)),
+            (
+                "ISSUERS_FRAME_VAR",
+                mutation(|block| block.issuers_frame_var += 1),
+            ),
+            ("IDENTITY_ALREADY_WRITTEN", {
+                let identity = identity.clone();
+                mutation(move |block| block.identities.push(identity.clone()))
+            }),
+            ("ISSUER_NOT_MEMBER", {
+                let unknown = unknown.clone();
+                mutation(move |block| block.issuer = unknown.clone())
+            }),
+            ("JOINER_IDENTITY", {
+                let joiner = with_pubkey(&joiner, &unknown);
+                mutation(move |block| block.joiners.push(joiner.clone()))
+            }),
+            (
+                "JOINER_ALREADY_MEMBER",
+                mutation(move |block| block.joiners.push(member_joiner.clone())),
+            ),
+            ("JOINER_CERTS", {
+                let identity = with_pubkey(&identity, &unknown);
+                let joiner = with_pubkey(&joiner, &unknown);
+                mutation(move |block| {
+                    block.identities.push(identity.clone());
+                    block.joiners.push(joiner.clone());
+                })
+            }),
+            ("ACTIVE_NOT_MEMBER", {
+                let active = with_pubkey(&joiner, &unknown);
+                mutation(move |block| block.actives.push(active.clone()))
+            }),
+            ("LEAVER_NOT_MEMBER", {
+                let leaver = with_pubkey(&joiner, &unknown);
+                mutation(move |block| block.leavers.push(leaver.clone()))
+            }),
+            ("REVOKED", {
+                let revocation = format!("{}:{}", unknown, idty_sig);
+                mutation(move |block| block.revoked.push(revocation.clone()))
+            }),
+            ("REVOKED_NOT_EXCLUDED", {
+                let revocation = format!("{}:{}", member1, idty_sig);
+                mutation(move |block| block.revoked.push(revocation.clone()))
+            }),
+            ("EXCLUDED", {
+                let member1 = member1.clone();
+                mutation(move |block| block.excluded.push(member1.clone()))
+            }),
+            ("CERT_ISSUER_NOT_MEMBER", {
+                let cert = with_pubkey(&cert, &unknown);
+                mutation(move |block| block.certifications.push(cert.clone()))
+            }),
+            ("CERT_RECEIVER_NOT_MEMBER", {
+                let cert = format!(
+                    "{}:{}:{}:{}",
+                    cert_fields[0], unknown, cert_fields[2], cert_fields[3]
+                );
+                mutation(move |block| block.certifications.push(cert.clone()))
+            }),
+        ];
+        assert_violations(&server, &block1, vectors);
+
+        // Proved at pow_min only: the issuer of the previous block has a handicap
+        server.apply_block(block1.clone())?;
+        let personalized = server
+            .get_personalized_difficulty(keypairs[0].public_key())?
+            .ok_or_else(|| anyhow::anyhow!("no blockchain"))?;
+        let mut block =
+            server.generate_block_candidate(keypairs[0].public_key(), block1.time + 300)?;
+        assert!(personalized.difficulty > block.pow_min);
+        let inner_hash = crate::raw_block::inner_hash(&block)?;
+        let signator = keypairs[0].generate_signator();
+        loop {
+            let signed_part = crate::raw_block::signed_part(&inner_hash, block.nonce);
+            let signature = signator.sign(signed_part.as_bytes()).to_base64();
+            let hash = crate::raw_block::block_hash(&signed_part, &signature);
+            if !crate::genesis::hash_matches_pow(&hash, personalized.difficulty as usize) {
+                block.signature = signature;
+                block.hash = Some(hash.to_hex());
+                break;
+            }
+            block.nonce += 1;
+        }
+        assert_eq!(
+            violated_rules(server.validate_block(&block)),
+            vec!["PERSONALIZED_POW"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cert_stock() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let mut genesis_builder = builder(&keypairs);
+        genesis_builder.currency_params.sig_stock = 2;
+        let genesis = genesis_builder.build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(genesis.clone())?;
+        let block1 = forge_block(&server, &keypairs[0], genesis.time + 300)?;
+
+        // The first member already certifies the two others
+        let newcomer = keypair(4).public_key().to_base58();
+        let cert = genesis.certifications[0].clone();
+        let mut fields: Vec<&str> = cert.split(':').collect();
+        fields[1] = &newcomer;
+        let cert = fields.join(":");
+        assert_violations(
+            &server,
+            &block1,
+            vec![(
+                "CERT_STOCK",
+                mutation(move |block| block.certifications.push(cert.clone())),
+            )],
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_txs_rules_vectors() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let server = server_with_uds(&keypairs, 10)?;
+        let tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(keypairs[1].public_key())],
+            &[(keypairs[2].public_key(), 1_000)],
+        );
+        assert!(server
+            .accept_new_tx(tx, keypairs[0].public_key())?
+            .is_accepted());
+        let block = forge_block(&server, &keypairs[0], 1_600_000_600)?;
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(server.validate_block(&block), Ok(()));
+
+        let unbalanced_tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(keypairs[1].public_key())],
+            &[(keypairs[2].public_key(), 999)],
+        )
+        .to_string_object();
+        // Signed by its issuer, but spends the UD of another member
+        let stolen_ud_tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(keypairs[2].public_key())],
+            &[(keypairs[1].public_key(), 1_000)],
+        )
+        .to_string_object();
+        let block_signature = block.signature.clone();
+
+        let vectors: Vec<(&str, BlockMutation)> = vec![
+            (
+                "TX_SOURCES",
+                mutation(|block| {
+                    let tx = block.transactions[0].clone();
+                    block.transactions.push(tx)
+                }),
+            ),
+            (
+                "TX_BLOCKSTAMP",
+                mutation(|block| {
+                    block.transactions[0].blockstamp = format!("1-{}", Hash::default().to_hex());
+                    block.transactions[0].hash = None;
+                }),
+            ),
+            (
+                "TX_SIGNATURES",
+                mutation(move |block| {
+                    block.transactions[0].signatures[0] = block_signature.clone();
+                    block.transactions[0].hash = None;
+                }),
+            ),
+            (
+                "TX_BALANCE",
+                mutation(move |block| block.transactions[0] = unbalanced_tx.clone()),
+            ),
+            (
+                "TX_UNLOCKS",
+                mutation(move |block| block.transactions[0] = stolen_ud_tx.clone()),
+            ),
+        ];
+        assert_violations(&server, &block, vectors);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Global rules of the web of trust: the memberships, certifications, revocations and
+//! exclusions of a block must agree with the identities of bc_v2 and the writes of the wot
+//! index.
+
+use super::RuleViolation;
+use crate::wot_index::{CertWriteDbV1, MembershipWriteDbV1, WotV1DbReadable};
+use crate::*;
+use duniter_core::crypto::keys::PublicKey as _;
+use duniter_core::wot::{
+    data::{rusty::RustyWebOfTrust, WebOfTrust, WotId},
+    operations::distance::{DistanceCalculator, RustyDistanceCalculator, WotDistanceParameters},
+};
+use std::collections::{HashMap, HashSet};
+
+impl DuniterServer {
+    pub(super) fn check_wot_rules(
+        &self,
+        block: &DubpBlockV10Stringified,
+        violations: &mut Vec<RuleViolation>,
+    ) -> DuniterServerResult<()> {
+        let mut check = |ok: bool, rule: &'static str, message: String| {
+            if !ok {
+                violations.push(RuleViolation::new(rule, message));
+            }
+        };
+        let pubkeys = |compact_docs: &[String]| -> Vec<PublicKey> {
+            compact_docs
+                .iter()
+                .filter_map(|doc| crate::wot_index::first_pubkey(doc))
+                .collect()
+        };
+        let new_identities: HashSet<PublicKey> = pubkeys(&block.identities).into_iter().collect();
+        let joiners: HashSet<PublicKey> = pubkeys(&block.joiners).into_iter().collect();
+        let certs: Vec<(PublicKey, PublicKey)> = block
+            .certifications
+            .iter()
+            .filter_map(|cert| crate::wot_index::cert_pubkeys(cert))
+            .collect();
+
+        if let Ok(issuer) = PublicKey::from_base58(&block.issuer) {
+            check(
+                self.is_member(issuer)? || joiners.contains(&issuer),
+                "ISSUER_NOT_MEMBER",
+                format!("issuer {} is not a member", issuer.to_base58()),
+            );
+        }
+
+        for &joiner in &joiners {
+            let idty_opt = self.bc_db.identities().get(&PubKeyKeyV2(joiner))?;
+            check(
+                (idty_opt.is_some() || new_identities.contains(&joiner))
+                    && !self.is_revoked(joiner)?,
+                "JOINER_IDENTITY",
+                format!(
+                    "joiner {} has no identity or is revoked",
+                    joiner.to_base58()
+                ),
+            );
+            check(
+                !idty_opt.map_or(false, |idty| idty.is_member),
+                "JOINER_ALREADY_MEMBER",
+                format!("joiner {} is already a member", joiner.to_base58()),
+            );
+        }
+        for active in pubkeys(&block.actives) {
+            check(
+                self.is_member(active)?,
+                "ACTIVE_NOT_MEMBER",
+                format!(
+                    "{} renews a membership but is not a member",
+                    active.to_base58()
+                ),
+            );
+        }
+        for leaver in pubkeys(&block.leavers) {
+            check(
+                self.is_member(leaver)?,
+                "LEAVER_NOT_MEMBER",
+                format!("leaver {} is not a member", leaver.to_base58()),
+            );
+        }
+        let revoked_pubkeys: HashSet<PublicKey> = pubkeys(&block.revoked).into_iter().collect();
+        for &revoked in &revoked_pubkeys {
+            check(
+                self.bc_db
+                    .identities()
+                    .contains_key(&PubKeyKeyV2(revoked))?
+                    && !self.is_revoked(revoked)?,
+                "REVOKED",
+                format!(
+                    "identity {} is unknown or already revoked",
+                    revoked.to_base58()
+                ),
+            );
+        }
+
+        // DUBP: the members to be kicked are excluded by the next block, as the revoked members
+        let excluded: HashSet<PublicKey> = pubkeys(&block.excluded).into_iter().collect();
+        let to_be_kicked: HashSet<PublicKey> = self.to_be_kicked()?.into_iter().collect();
+        for &member in &to_be_kicked {
+            check(
+                excluded.contains(&member),
+                "TO_BE_KICKED",
+                format!("member {} must be excluded", member.to_base58()),
+            );
+        }
+        for &revoked in &revoked_pubkeys {
+            check(
+                !self.is_member(revoked)? || excluded.contains(&revoked),
+                "REVOKED_NOT_EXCLUDED",
+                format!("revoked member {} must be excluded", revoked.to_base58()),
+            );
+        }
+        for &member in &excluded {
+            check(
+                to_be_kicked.contains(&member) || revoked_pubkeys.contains(&member),
+                "EXCLUDED",
+                format!("{} is neither to be kicked nor revoked", member.to_base58()),
+            );
+        }
+
+        let mut issued_in_block: HashMap<PublicKey, HashSet<PublicKey>> = HashMap::new();
+        for &(issuer, receiver) in &certs {
+            check(
+                self.is_member(issuer)? || joiners.contains(&issuer),
+                "CERT_ISSUER_NOT_MEMBER",
+                format!("certifier {} is not a member", issuer.to_base58()),
+            );
+            check(
+                self.is_member(receiver)? || joiners.contains(&receiver),
+                "CERT_RECEIVER_NOT_MEMBER",
+                format!(
+                    "certified {} is not a member nor a joiner",
+                    receiver.to_base58()
+                ),
+            );
+            issued_in_block.entry(issuer).or_default().insert(receiver);
+        }
+        // A certification written again replaces the previous one
+        for (issuer, receivers) in issued_in_block {
+            let stock: HashSet<PublicKey> = self
+                .get_certs_from(issuer)?
+                .into_iter()
+                .filter(|cert| cert.expires_on > block.median_time)
+                .map(|cert| cert.receiver)
+                .chain(receivers)
+                .collect();
+            check(
+                stock.len() <= self.currency_params.sig_stock,
+                "CERT_STOCK",
+                format!(
+                    "{} would have more than {} valid certifications",
+                    issuer.to_base58(),
+                    self.currency_params.sig_stock
+                ),
+            );
+        }
+
+        if !joiners.is_empty() {
+            let next_wot = self.next_wot(&joiners, &certs, block.median_time)?;
+            for &joiner in &joiners {
+                let certifiers_count = next_wot.certifiers.get(&joiner).map_or(0, HashSet::len);
+                check(
+                    certifiers_count >= self.currency_params.sig_qty,
+                    "JOINER_CERTS",
+                    format!(
+                        "joiner {} needs {} certifications from members",
+                        joiner.to_base58(),
+                        self.currency_params.sig_qty
+                    ),
+                );
+                check(
+                    !next_wot.is_outdistanced(
+                        joiner,
+                        self.currency_params.step_max as u32,
+                        self.currency_params.x_percent,
+                    ),
+                    "JOINER_DISTANCE",
+                    format!("joiner {} is too far from the sentries", joiner.to_base58()),
+                );
+            }
+        }
+
+        Ok(())
+    }
+    pub(crate) fn is_member(&self, pubkey: PublicKey) -> KvResult<bool> {
+        Ok(self
+            .bc_db
+            .identities()
+            .get(&PubKeyKeyV2(pubkey))?
+            .map_or(false, |idty| idty.is_member))
+    }
+    /// DUBP: a member is kicked once its membership is expired or it has less than `sig_qty`
+    /// valid certifications, at the median time of the current block
+    pub(crate) fn to_be_kicked(&self) -> DuniterServerResult<Vec<PublicKey>> {
+        let median_time = if let Some(current) = self.current {
+            current.median_time
+        } else {
+            return Ok(Vec::new());
+        };
+        let members: Vec<PublicKey> = self.bc_db.identities().iter(.., |it| {
+            it.filter_ok(|(_, idty)| idty.is_member)
+                .map_ok(|(PubKeyKeyV2(pubkey), _)| pubkey)
+                .collect::<KvResult<_>>()
+        })?;
+        let mut to_be_kicked = Vec::new();
+        for member in members {
+            let ms_expired = self
+                .get_membership_expiry(member)?
+                .map_or(false, |expires_on| expires_on <= median_time);
+            if ms_expired || self.get_certs_to(member)?.len() < self.currency_params.sig_qty {
+                to_be_kicked.push(member);
+            }
+        }
+        Ok(to_be_kicked)
+    }
+    pub(crate) fn is_revoked(&self, pubkey: PublicKey) -> KvResult<bool> {
+        let revocations: Vec<MembershipWriteDbV1> =
+            crate::wot_index::read_writes(&self.wot_db.revocations(), pubkey)?;
+        Ok(!revocations.is_empty())
+    }
+    /// Web of trust of the members once `joiners` and `certs` are written at `median_time`
+    pub(crate) fn next_wot(
+        &self,
+        joiners: &HashSet<PublicKey>,
+        certs: &[(PublicKey, PublicKey)],
+        median_time: u64,
+    ) -> KvResult<NextWot> {
+        let mut members: HashSet<PublicKey> = self.bc_db.identities().iter(.., |it| {
+            it.filter_ok(|(_, idty)| idty.is_member)
+                .map_ok(|(PubKeyKeyV2(pubkey), _)| pubkey)
+                .collect::<KvResult<_>>()
+        })?;
+        members.extend(joiners);
+
+        let mut certifiers: HashMap<PublicKey, HashSet<PublicKey>> = HashMap::new();
+        for (receiver, writes) in
+            crate::wot_index::read_all_writes::<_, CertWriteDbV1>(&self.wot_db.certs_by_receiver())?
+        {
+            // The last write of a certification decides of its expiry
+            let mut last_writes = HashMap::new();
+            for write in writes {
+                last_writes.insert(write.pubkey, write.written_time);
+            }
+            certifiers.entry(receiver).or_default().extend(
+                last_writes
+                    .into_iter()
+                    .filter(|(_, written_time)| {
+                        written_time + self.currency_params.sig_validity > median_time
+                    })
+                    .map(|(issuer, _)| issuer),
+            );
+        }
+        for &(issuer, receiver) in certs {
+            certifiers.entry(receiver).or_default().insert(issuer);
+        }
+        // Only the certifications between members count
+        certifiers.retain(|receiver, _| members.contains(receiver));
+        for issuers in certifiers.values_mut() {
+            issuers.retain(|issuer| members.contains(issuer));
+        }
+
+        Ok(NextWot {
+            members,
+            certifiers,
+            sig_stock: self.currency_params.sig_stock,
+        })
+    }
+}
+
+/// Members and valid certifications once a block is applied
+pub(crate) struct NextWot {
+    members: HashSet<PublicKey>,
+    /// Issuers of the certifications received by each member
+    pub(crate) certifiers: HashMap<PublicKey, HashSet<PublicKey>>,
+    sig_stock: usize,
+}
+
+impl NextWot {
+    /// DUBP: `x_percent` of the sentries, the members who issued and received at least
+    /// `ceil(members_count ^ (1 / step_max))` certifications, must reach `pubkey` by at most
+    /// `step_max` certifications
+    pub(crate) fn is_outdistanced(&self, pubkey: PublicKey, step_max: u32, x_percent: f64) -> bool {
+        let mut wot = RustyWebOfTrust::new(self.sig_stock);
+        let wot_ids: HashMap<PublicKey, WotId> = self
+            .members
+            .iter()
+            .map(|member| (*member, wot.add_node()))
+            .collect();
+        for (receiver, issuers) in &self.certifiers {
+            for issuer in issuers {
+                wot.add_link(wot_ids[issuer], wot_ids[receiver]);
+            }
+        }
+        let node = match wot_ids.get(&pubkey) {
+            Some(node) => *node,
+            None => return true,
+        };
+        let sentry_requirement = (self.members.len() as f64)
+            .powf(1.0 / f64::from(step_max.max(1)))
+            .ceil() as u32;
+        RustyDistanceCalculator {}
+            .compute_distance(
+                &wot,
+                WotDistanceParameters {
+                    node,
+                    sentry_requirement,
+                    step_max,
+                    x_percent,
+                },
+            )
+            .map_or(true, |distance| distance.outdistanced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::tests::keypair;
+    use duniter_core::crypto::keys::KeyPair as _;
+
+    #[test]
+    fn test_is_outdistanced() {
+        let pubkeys: Vec<PublicKey> = (1..=6).map(|seed| keypair(seed).public_key()).collect();
+        let mut certifiers: HashMap<PublicKey, HashSet<PublicKey>> = HashMap::new();
+        let mut certify = |issuer: usize, receiver: usize| {
+            certifiers
+                .entry(pubkeys[receiver])
+                .or_default()
+                .insert(pubkeys[issuer]);
+        };
+        // Sentries 0 to 3 certify each other, 0 certifies 4 who certifies 5
+        for issuer in 0..4 {
+            for receiver in (0..4).filter(|receiver| *receiver != issuer) {
+                certify(issuer, receiver);
+            }
+        }
+        certify(0, 4);
+        certify(4, 5);
+        let next_wot = NextWot {
+            members: pubkeys.iter().copied().collect(),
+            certifiers,
+            sig_stock: 100,
+        };
+
+        assert!(!next_wot.is_outdistanced(pubkeys[4], 2, 0.8));
+        // Only sentry 0 reaches 5 in two steps
+        assert!(next_wot.is_outdistanced(pubkeys[5], 2, 0.8));
+        assert!(!next_wot.is_outdistanced(pubkeys[5], 2, 0.25));
+        // Unknown member
+        assert!(next_wot.is_outdistanced(keypair(7).public_key(), 2, 0.8));
+    }
+}
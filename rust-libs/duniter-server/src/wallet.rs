@@ -0,0 +1,210 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::*;
+use duniter_core::dbs::{SourceAmountValV2, UdIdV2, U32BE};
+use duniter_core::wallet::prelude::*;
+use duniter_gva_db::{GvaUtxoIdDbV1, GvaV1DbReadable};
+
+/// Unspent source of a wallet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalletSource {
+    Ud {
+        issuer: PublicKey,
+        block_number: BlockNumber,
+        amount: SourceAmount,
+    },
+    Utxo {
+        tx_hash: Hash,
+        output_index: u32,
+        amount: SourceAmount,
+    },
+}
+
+impl WalletSource {
+    pub fn amount(&self) -> SourceAmount {
+        match self {
+            Self::Ud { amount, .. } | Self::Utxo { amount, .. } => *amount,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UtxosPage {
+    pub sources: Vec<WalletSource>,
+    pub has_next_page: bool,
+}
+
+impl DuniterServer {
+    pub fn get_balance(&self, script: &WalletScriptV10) -> DuniterServerResult<SourceAmount> {
+        Ok(self
+            .get_wallet_sources(script)?
+            .iter()
+            .fold(SourceAmount::ZERO, |balance, source| {
+                balance + source.amount()
+            }))
+    }
+    /// Unspent sources of a wallet, UDs first (oldest first) then UTXOs
+    pub fn get_utxos(
+        &self,
+        script: &WalletScriptV10,
+        page: usize,
+        page_size: usize,
+    ) -> DuniterServerResult<UtxosPage> {
+        let sources = self.get_wallet_sources(script)?;
+        let start = page.saturating_mul(page_size);
+        Ok(UtxosPage {
+            has_next_page: sources.len() > start.saturating_add(page_size),
+            sources: sources.into_iter().skip(start).take(page_size).collect(),
+        })
+    }
+    fn get_wallet_sources(&self, script: &WalletScriptV10) -> KvResult<Vec<WalletSource>> {
+        let mut sources = Vec::new();
+
+        // Only a simple signature script can own UDs
+        if let WalletSubScriptV10::Single(WalletConditionV10::Sig(issuer)) = script.root {
+            let uds_reval = self.bc_db.uds_reval().iter(.., |it| {
+                it.map_ok(|(U32BE(block_number), ud_amount)| (block_number, ud_amount.0))
+                    .collect::<KvResult<BTreeMap<_, _>>>()
+            })?;
+            self.bc_db.uds().iter(
+                UdIdV2(issuer, BlockNumber(0))..=UdIdV2(issuer, BlockNumber(u32::MAX)),
+                |it| {
+                    it.keys().try_for_each(|ud_id_res| {
+                        let UdIdV2(_, block_number) = ud_id_res?;
+                        let amount = uds_reval
+                            .range(..=block_number.0)
+                            .next_back()
+                            .map_or(SourceAmount::ZERO, |(_, ud_amount)| *ud_amount);
+                        sources.push(WalletSource::Ud {
+                            issuer,
+                            block_number,
+                            amount,
+                        });
+                        Ok::<_, KvError>(())
+                    })
+                },
+            )?;
+        }
+
+        // gva_v1 indexes the UTXOs by script then by written block
+        let gva_db_ro = duniter_gva_indexer::get_gva_db_ro(self.profile_path_opt.as_deref());
+        let (k_min, k_max) =
+            GvaUtxoIdDbV1::script_interval(Hash::compute(script.to_string().as_bytes()));
+        gva_db_ro.gva_utxos().iter(k_min..k_max, |it| {
+            it.try_for_each(|entry_res| {
+                let (utxo_id, SourceAmountValV2(amount)) = entry_res?;
+                sources.push(WalletSource::Utxo {
+                    tx_hash: utxo_id.get_tx_hash(),
+                    output_index: utxo_id.get_output_index() as u32,
+                    amount,
+                });
+                Ok::<_, KvError>(())
+            })
+        })?;
+
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_wallet() -> anyhow::Result<()> {
+        let server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        let script = WalletScriptV10::single(WalletConditionV10::Sig(PublicKey::default()));
+
+        assert_eq!(server.get_balance(&script)?, SourceAmount::ZERO);
+        assert_eq!(
+            server.get_utxos(&script, 0, 10)?,
+            UtxosPage {
+                sources: vec![],
+                has_next_page: false,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_funded_wallet() -> anyhow::Result<()> {
+        use crate::block_candidate::tests::forge_block;
+        use crate::genesis::tests::keypair;
+        use crate::mempool::tests::{server_with_uds, signed_tx, ud_input};
+        use duniter_core::crypto::keys::KeyPair as _;
+
+        // The memory gva_v1 db is shared by the tests, these wallets are only funded here
+        let keypairs: Vec<_> = (51..=53).map(keypair).collect();
+        let mut server = server_with_uds(&keypairs, 10)?;
+        let script =
+            |i: usize| WalletScriptV10::single(WalletConditionV10::Sig(keypairs[i].public_key()));
+        let ud = |i: usize| WalletSource::Ud {
+            issuer: keypairs[i].public_key(),
+            block_number: BlockNumber(1),
+            amount: SourceAmount::new(1_000, 0),
+        };
+        assert_eq!(server.get_balance(&script(1))?, SourceAmount::new(1_000, 0));
+
+        // The UD of the second member goes to the third one and back to its change
+        let tx = signed_tx(
+            &server,
+            &keypairs[1],
+            &[ud_input(keypairs[1].public_key())],
+            &[
+                (keypairs[2].public_key(), 400),
+                (keypairs[1].public_key(), 600),
+            ],
+        );
+        assert!(server
+            .accept_new_tx(tx.clone(), keypairs[0].public_key())?
+            .is_accepted());
+        let block = forge_block(&server, &keypairs[0], 1_600_000_600)?;
+        assert_eq!(block.transactions.len(), 1);
+        server.apply_block(block)?;
+
+        let utxo = |output_index: u32, amount: i64| WalletSource::Utxo {
+            tx_hash: tx.get_hash(),
+            output_index,
+            amount: SourceAmount::new(amount, 0),
+        };
+        assert_eq!(server.get_balance(&script(1))?, SourceAmount::new(600, 0));
+        assert_eq!(
+            server.get_utxos(&script(1), 0, 10)?,
+            UtxosPage {
+                sources: vec![utxo(1, 600)],
+                has_next_page: false,
+            }
+        );
+        assert_eq!(server.get_balance(&script(2))?, SourceAmount::new(1_400, 0));
+        assert_eq!(
+            server.get_utxos(&script(2), 0, 1)?,
+            UtxosPage {
+                sources: vec![ud(2)],
+                has_next_page: true,
+            }
+        );
+        assert_eq!(
+            server.get_utxos(&script(2), 1, 1)?,
+            UtxosPage {
+                sources: vec![utxo(0, 400)],
+                has_next_page: false,
+            }
+        );
+
+        Ok(())
+    }
+}
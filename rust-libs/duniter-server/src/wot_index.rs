@@ -0,0 +1,301 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Certifications and memberships written in the blockchain.
+//!
+//! bc_v2 only keeps the identities. This index keeps, for each public key, every certification,
+//! every membership (join or renewal) and the revocation written in a block, with the number of
+//! this block.
+//! Reverting a block removes exactly what it wrote and applying a block twice writes nothing
+//! more, so a block can be replayed after a crash.
+
+use crate::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+db_schema!(
+    WotV1,
+    [
+        [
+            "certs_by_issuer",
+            CertsByIssuer,
+            PubKeyKeyV2,
+            CertsWritesDbV1
+        ],
+        [
+            "certs_by_receiver",
+            CertsByReceiver,
+            PubKeyKeyV2,
+            CertsWritesDbV1
+        ],
+        [
+            "memberships",
+            Memberships,
+            PubKeyKeyV2,
+            MembershipsWritesDbV1
+        ],
+        [
+            "revocations",
+            Revocations,
+            PubKeyKeyV2,
+            MembershipsWritesDbV1
+        ],
+    ]
+);
+
+/// Write of a certification, from its issuer or from its receiver
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CertWriteDbV1 {
+    /// Receiver in `certs_by_issuer`, issuer in `certs_by_receiver`
+    pub pubkey: PublicKey,
+    pub written_block: u32,
+    pub written_time: u64,
+}
+
+/// Write of a membership (join or renewal) or of a revocation
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct MembershipWriteDbV1 {
+    pub written_block: u32,
+    pub written_time: u64,
+}
+
+/// Writes of a public key, from the oldest block
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct WritesDbV1<T>(pub Vec<T>);
+
+pub type CertsWritesDbV1 = WritesDbV1<CertWriteDbV1>;
+pub type MembershipsWritesDbV1 = WritesDbV1<MembershipWriteDbV1>;
+
+impl<T: Serialize> ValueAsBytes for WritesDbV1<T> {
+    fn as_bytes<R, F: FnMut(&[u8]) -> KvResult<R>>(&self, mut f: F) -> KvResult<R> {
+        let bytes = bincode::serialize(&self.0).map_err(|e| KvError::DeserError(e.into()))?;
+        f(bytes.as_ref())
+    }
+}
+
+impl<T: DeserializeOwned> FromBytes for WritesDbV1<T> {
+    type Err = bincode::Error;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Self::Err> {
+        Ok(WritesDbV1(bincode::deserialize(bytes)?))
+    }
+}
+
+#[cfg(feature = "explorer")]
+impl<T: DeserializeOwned + Serialize> ExplorableValue for WritesDbV1<T> {
+    fn from_explorer_str(source: &str) -> Result<Self, FromExplorerValueErr> {
+        serde_json::from_str(source).map_err(|e| FromExplorerValueErr(e.into()))
+    }
+    fn to_explorer_json(&self) -> KvResult<serde_json::Value> {
+        serde_json::to_value(self).map_err(|e| KvError::DeserError(e.into()))
+    }
+}
+
+pub(crate) fn open(profile_path_opt: Option<&Path>) -> KvResult<WotV1Db<FileBackend>> {
+    WotV1Db::<FileBackend>::open(FileBackend::gen_backend_conf(
+        WotV1Db::<FileBackend>::NAME,
+        profile_path_opt,
+    ))
+}
+
+/// Writes of `pubkey` in `col`, from the oldest block
+pub(crate) fn read_writes<C: DbCollectionRo<K = PubKeyKeyV2, V = WritesDbV1<T>>, T>(
+    col: &C,
+    pubkey: PublicKey,
+) -> KvResult<Vec<T>> {
+    Ok(col
+        .get(&PubKeyKeyV2(pubkey))?
+        .map(|WritesDbV1(writes)| writes)
+        .unwrap_or_default())
+}
+
+/// Index the certifications and the memberships written by `block`
+pub(crate) fn apply_block(wot_db: &WotV1Db<FileBackend>, block: &DubpBlockV10) -> KvResult<()> {
+    let block = block.to_string_object();
+    let written_block = block.number as u32;
+    let written_time = block.median_time;
+
+    for (issuer, receiver) in block
+        .certifications
+        .iter()
+        .filter_map(|cert| cert_pubkeys(cert))
+    {
+        let write = |pubkey| CertWriteDbV1 {
+            pubkey,
+            written_block,
+            written_time,
+        };
+        push_write(&wot_db.certs_by_issuer_write(), issuer, write(receiver))?;
+        push_write(&wot_db.certs_by_receiver_write(), receiver, write(issuer))?;
+    }
+    for membership in block.joiners.iter().chain(block.actives.iter()) {
+        if let Some(pubkey) = first_pubkey(membership) {
+            push_write(
+                &wot_db.memberships_write(),
+                pubkey,
+                MembershipWriteDbV1 {
+                    written_block,
+                    written_time,
+                },
+            )?;
+        }
+    }
+    for revocation in &block.revoked {
+        if let Some(pubkey) = first_pubkey(revocation) {
+            push_write(
+                &wot_db.revocations_write(),
+                pubkey,
+                MembershipWriteDbV1 {
+                    written_block,
+                    written_time,
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the certifications and the memberships written by `block`
+pub(crate) fn revert_block(wot_db: &WotV1Db<FileBackend>, block: &DubpBlockV10) -> KvResult<()> {
+    let block = block.to_string_object();
+    let written_block = block.number as u32;
+
+    for (issuer, receiver) in block
+        .certifications
+        .iter()
+        .filter_map(|cert| cert_pubkeys(cert))
+    {
+        remove_writes(
+            &wot_db.certs_by_issuer_write(),
+            issuer,
+            |write: &CertWriteDbV1| write.written_block == written_block,
+        )?;
+        remove_writes(
+            &wot_db.certs_by_receiver_write(),
+            receiver,
+            |write: &CertWriteDbV1| write.written_block == written_block,
+        )?;
+    }
+    for membership in block.joiners.iter().chain(block.actives.iter()) {
+        if let Some(pubkey) = first_pubkey(membership) {
+            remove_writes(
+                &wot_db.memberships_write(),
+                pubkey,
+                |write: &MembershipWriteDbV1| write.written_block == written_block,
+            )?;
+        }
+    }
+    for revocation in &block.revoked {
+        if let Some(pubkey) = first_pubkey(revocation) {
+            remove_writes(
+                &wot_db.revocations_write(),
+                pubkey,
+                |write: &MembershipWriteDbV1| write.written_block == written_block,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Issuer and receiver of a certification in compact format `issuer:receiver:block:sig`
+pub(crate) fn cert_pubkeys(cert: &str) -> Option<(PublicKey, PublicKey)> {
+    let mut fields = cert.split(':');
+    let issuer = PublicKey::from_base58(fields.next()?).ok()?;
+    let receiver = PublicKey::from_base58(fields.next()?).ok()?;
+    Some((issuer, receiver))
+}
+
+/// Public key of a membership in compact format `pubkey:sig:ms_blockstamp:idty_blockstamp:uid`
+/// or of a revocation in compact format `pubkey:sig`
+pub(crate) fn first_pubkey(compact_doc: &str) -> Option<PublicKey> {
+    PublicKey::from_base58(compact_doc.split(':').next()?).ok()
+}
+
+/// Writes of every public key of `col`
+pub(crate) fn read_all_writes<C, T>(col: &C) -> KvResult<Vec<(PublicKey, Vec<T>)>>
+where
+    C: DbCollectionRo<K = PubKeyKeyV2, V = WritesDbV1<T>>,
+{
+    col.iter(.., |it| {
+        it.map_ok(|(PubKeyKeyV2(pubkey), WritesDbV1(writes))| (pubkey, writes))
+            .collect()
+    })
+}
+
+fn push_write<C, T>(col: &C, pubkey: PublicKey, write: T) -> KvResult<()>
+where
+    C: DbCollectionRw<K = PubKeyKeyV2, V = WritesDbV1<T>>
+        + DbCollectionRo<K = PubKeyKeyV2, V = WritesDbV1<T>>,
+    T: PartialEq,
+{
+    let mut writes = read_writes(col, pubkey)?;
+    if !writes.contains(&write) {
+        writes.push(write);
+        col.upsert(PubKeyKeyV2(pubkey), WritesDbV1(writes))?;
+    }
+    Ok(())
+}
+
+fn remove_writes<C, T>(col: &C, pubkey: PublicKey, f: impl Fn(&T) -> bool) -> KvResult<()>
+where
+    C: DbCollectionRw<K = PubKeyKeyV2, V = WritesDbV1<T>>
+        + DbCollectionRo<K = PubKeyKeyV2, V = WritesDbV1<T>>,
+{
+    let mut writes = read_writes(col, pubkey)?;
+    let len = writes.len();
+    writes.retain(|write| !f(write));
+    if writes.is_empty() {
+        col.remove(PubKeyKeyV2(pubkey))?;
+    } else if writes.len() != len {
+        col.upsert(PubKeyKeyV2(pubkey), WritesDbV1(writes))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::tests::{builder, keypair};
+
+    #[test]
+    fn test_apply_and_revert_block() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build(&keypairs[0])?;
+        let wot_db = open(None)?;
+        let pubkey = keypairs[0].public_key();
+
+        apply_block(&wot_db, &genesis)?;
+        // Applied twice, the block writes nothing more
+        apply_block(&wot_db, &genesis)?;
+        let certs: Vec<CertWriteDbV1> = read_writes(&wot_db.certs_by_issuer(), pubkey)?;
+        assert_eq!(certs.len(), 2);
+        let certs: Vec<CertWriteDbV1> = read_writes(&wot_db.certs_by_receiver(), pubkey)?;
+        assert_eq!(certs.len(), 2);
+        let memberships: Vec<MembershipWriteDbV1> = read_writes(&wot_db.memberships(), pubkey)?;
+        assert_eq!(
+            memberships,
+            vec![MembershipWriteDbV1 {
+                written_block: 0,
+                written_time: 1_600_000_000,
+            }]
+        );
+
+        revert_block(&wot_db, &genesis)?;
+        assert_eq!(wot_db.certs_by_issuer().count()?, 0);
+        assert_eq!(wot_db.certs_by_receiver().count()?, 0);
+        assert_eq!(wot_db.memberships().count()?, 0);
+
+        Ok(())
+    }
+}
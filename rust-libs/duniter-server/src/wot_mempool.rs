@@ -0,0 +1,469 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pending identities, memberships, certifications and revocations, in the compact format of
+//! blocks.
+//!
+//! The documents are checked by the WoT sandboxes of the caller before being added here. The
+//! server selects among them the documents of the next block, and removes the documents
+//! written by each applied block.
+
+use crate::wot_index::{cert_pubkeys, first_pubkey};
+use crate::*;
+use std::collections::{HashMap, HashSet};
+
+db_schema!(
+    WotMpV1,
+    [
+        ["identities", Identities, PubKeyKeyV2, String],
+        ["memberships_in", MembershipsIn, PubKeyKeyV2, String],
+        ["memberships_out", MembershipsOut, PubKeyKeyV2, String],
+        ["certifications", Certifications, String, String],
+        ["revocations", Revocations, PubKeyKeyV2, String],
+    ]
+);
+
+/// Pending WoT documents that can be written together in the next block
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PendingWotDocuments {
+    pub(crate) identities: Vec<String>,
+    pub(crate) joiners: Vec<String>,
+    pub(crate) actives: Vec<String>,
+    pub(crate) leavers: Vec<String>,
+    pub(crate) certifications: Vec<String>,
+    pub(crate) revoked: Vec<String>,
+    /// Public keys of the members to be kicked and of the revoked members
+    pub(crate) excluded: Vec<String>,
+}
+
+impl PendingWotDocuments {
+    pub(crate) fn lines_count(&self) -> usize {
+        self.identities.len()
+            + self.joiners.len()
+            + self.actives.len()
+            + self.leavers.len()
+            + self.certifications.len()
+            + self.revoked.len()
+            + self.excluded.len()
+    }
+}
+
+pub(crate) fn open(profile_path_opt: Option<&Path>) -> KvResult<WotMpV1Db<FileBackend>> {
+    WotMpV1Db::<FileBackend>::open(FileBackend::gen_backend_conf(
+        WotMpV1Db::<FileBackend>::NAME,
+        profile_path_opt,
+    ))
+}
+
+/// Remove the pending documents written by `block`
+pub(crate) fn remove_written(
+    wot_mp_db: &WotMpV1Db<FileBackend>,
+    block: &DubpBlockV10,
+) -> KvResult<()> {
+    let block = block.to_string_object();
+    for pubkey in block
+        .identities
+        .iter()
+        .filter_map(|idty| first_pubkey(idty))
+    {
+        wot_mp_db.identities_write().remove(PubKeyKeyV2(pubkey))?;
+    }
+    for pubkey in block
+        .joiners
+        .iter()
+        .chain(block.actives.iter())
+        .filter_map(|ms| first_pubkey(ms))
+    {
+        wot_mp_db
+            .memberships_in_write()
+            .remove(PubKeyKeyV2(pubkey))?;
+    }
+    for pubkey in block.leavers.iter().filter_map(|ms| first_pubkey(ms)) {
+        wot_mp_db
+            .memberships_out_write()
+            .remove(PubKeyKeyV2(pubkey))?;
+    }
+    for (issuer, receiver) in block
+        .certifications
+        .iter()
+        .filter_map(|cert| cert_pubkeys(cert))
+    {
+        wot_mp_db
+            .certifications_write()
+            .remove(cert_key(issuer, receiver))?;
+    }
+    for pubkey in block.revoked.iter().filter_map(|revoc| first_pubkey(revoc)) {
+        wot_mp_db.revocations_write().remove(PubKeyKeyV2(pubkey))?;
+    }
+    Ok(())
+}
+
+fn cert_key(issuer: PublicKey, receiver: PublicKey) -> String {
+    format!("{}:{}", issuer.to_base58(), receiver.to_base58())
+}
+
+fn parse_pubkey(compact_doc: &str) -> DuniterServerResult<PublicKey> {
+    first_pubkey(compact_doc).ok_or_else(|| {
+        DuniterServerError::Validation(format!("invalid compact document {}", compact_doc))
+    })
+}
+
+fn read_all(
+    col: &impl DbCollectionRo<K = PubKeyKeyV2, V = String>,
+) -> KvResult<Vec<(PublicKey, String)>> {
+    col.iter(.., |it| {
+        it.map_ok(|(PubKeyKeyV2(pubkey), doc)| (pubkey, doc))
+            .collect::<KvResult<_>>()
+    })
+}
+
+impl DuniterServer {
+    /// Identity in compact format `pubkey:sig:blockstamp:uid`, replacing the pending identity
+    /// of the same public key
+    pub fn add_pending_identity(&self, compact_idty: String) -> DuniterServerResult<()> {
+        let pubkey = parse_pubkey(&compact_idty)?;
+        self.wot_mp_db
+            .identities_write()
+            .upsert(PubKeyKeyV2(pubkey), compact_idty)?;
+        Ok(())
+    }
+    /// Membership in compact format `pubkey:sig:ms_blockstamp:idty_blockstamp:uid`, a join or a
+    /// renewal if `is_in`, a leave otherwise
+    pub fn add_pending_membership(
+        &self,
+        compact_ms: String,
+        is_in: bool,
+    ) -> DuniterServerResult<()> {
+        let pubkey = parse_pubkey(&compact_ms)?;
+        if is_in {
+            self.wot_mp_db
+                .memberships_in_write()
+                .upsert(PubKeyKeyV2(pubkey), compact_ms)?;
+        } else {
+            self.wot_mp_db
+                .memberships_out_write()
+                .upsert(PubKeyKeyV2(pubkey), compact_ms)?;
+        }
+        Ok(())
+    }
+    /// Certification in compact format `issuer:receiver:block_number:sig`
+    pub fn add_pending_cert(&self, compact_cert: String) -> DuniterServerResult<()> {
+        let (issuer, receiver) = cert_pubkeys(&compact_cert).ok_or_else(|| {
+            DuniterServerError::Validation(format!(
+                "invalid compact certification {}",
+                compact_cert
+            ))
+        })?;
+        self.wot_mp_db
+            .certifications_write()
+            .upsert(cert_key(issuer, receiver), compact_cert)?;
+        Ok(())
+    }
+    /// Revocation in compact format `pubkey:sig`
+    pub fn add_pending_revocation(&self, compact_revoc: String) -> DuniterServerResult<()> {
+        let pubkey = parse_pubkey(&compact_revoc)?;
+        self.wot_mp_db
+            .revocations_write()
+            .upsert(PubKeyKeyV2(pubkey), compact_revoc)?;
+        Ok(())
+    }
+    /// Pending documents of the block following the current one, at `median_time`.
+    ///
+    /// A joiner is selected with its identity and its certifications only if the block gives
+    /// it `sig_qty` certifications from members and brings it close enough to the sentries.
+    /// Removing a joiner can make other joiners fail, the selection is repeated until all the
+    /// remaining joiners pass.
+    ///
+    /// The members to be kicked and the revoked members are excluded: they neither renew nor
+    /// leave, and their certifications are not written.
+    pub(crate) fn select_pending_wot(
+        &self,
+        median_time: u64,
+    ) -> DuniterServerResult<PendingWotDocuments> {
+        // New identities, the first one of each uid
+        let mut new_identities = HashMap::new();
+        let mut uids = HashSet::new();
+        for (pubkey, idty) in read_all(&self.wot_mp_db.identities())? {
+            let uid = idty.split(':').nth(3).unwrap_or_default().to_owned();
+            if !self.bc_db.identities().contains_key(&PubKeyKeyV2(pubkey))?
+                && !self.bc_db.uids_index().contains_key(&uid)?
+                && uids.insert(uid)
+            {
+                new_identities.insert(pubkey, idty);
+            }
+        }
+
+        let mut revoked = Vec::new();
+        let mut revoking = HashSet::new();
+        for (pubkey, revoc) in read_all(&self.wot_mp_db.revocations())? {
+            if self.bc_db.identities().contains_key(&PubKeyKeyV2(pubkey))?
+                && !self.is_revoked(pubkey)?
+            {
+                revoked.push(revoc);
+                revoking.insert(pubkey);
+            }
+        }
+        let mut excluded: HashSet<PublicKey> = self.to_be_kicked()?.into_iter().collect();
+        for &pubkey in &revoking {
+            if self.is_member(pubkey)? {
+                excluded.insert(pubkey);
+            }
+        }
+
+        let mut leavers = Vec::new();
+        let mut leaving = HashSet::new();
+        for (pubkey, ms) in read_all(&self.wot_mp_db.memberships_out())? {
+            if self.is_member(pubkey)? && !excluded.contains(&pubkey) {
+                leavers.push(ms);
+                leaving.insert(pubkey);
+            }
+        }
+        let mut actives = Vec::new();
+        let mut joiners = HashMap::new();
+        for (pubkey, ms) in read_all(&self.wot_mp_db.memberships_in())? {
+            if self.is_member(pubkey)? {
+                if !leaving.contains(&pubkey) && !excluded.contains(&pubkey) {
+                    actives.push(ms);
+                }
+            } else if new_identities.contains_key(&pubkey)
+                || (self.bc_db.identities().contains_key(&PubKeyKeyV2(pubkey))?
+                    && !self.is_revoked(pubkey)?
+                    && !revoking.contains(&pubkey))
+            {
+                joiners.insert(pubkey, ms);
+            }
+        }
+
+        let mut pending_certs = Vec::new();
+        let mut is_member_by_pubkey = HashMap::new();
+        let mut valid_receivers: HashMap<PublicKey, HashSet<PublicKey>> = HashMap::new();
+        for cert in self
+            .wot_mp_db
+            .certifications()
+            .iter(.., |it| it.values().collect::<KvResult<Vec<_>>>())?
+        {
+            if let Some((issuer, receiver)) = cert_pubkeys(&cert) {
+                for &pubkey in &[issuer, receiver] {
+                    if !is_member_by_pubkey.contains_key(&pubkey) {
+                        is_member_by_pubkey.insert(pubkey, self.is_member(pubkey)?);
+                    }
+                }
+                if !valid_receivers.contains_key(&issuer) {
+                    let receivers = self
+                        .get_certs_from(issuer)?
+                        .into_iter()
+                        .filter(|cert| cert.expires_on > median_time)
+                        .map(|cert| cert.receiver)
+                        .collect();
+                    valid_receivers.insert(issuer, receivers);
+                }
+                pending_certs.push((issuer, receiver, cert));
+            }
+        }
+
+        loop {
+            let in_wot = |pubkey: &PublicKey| {
+                (is_member_by_pubkey.get(pubkey).copied().unwrap_or_default()
+                    && !excluded.contains(pubkey))
+                    || joiners.contains_key(pubkey)
+            };
+            let mut stocks = HashMap::new();
+            let mut certs = Vec::new();
+            for (issuer, receiver, cert) in &pending_certs {
+                let issuer_receivers = &valid_receivers[issuer];
+                // A certification still valid can't be written again
+                if issuer == receiver
+                    || !in_wot(issuer)
+                    || !in_wot(receiver)
+                    || issuer_receivers.contains(receiver)
+                {
+                    continue;
+                }
+                let stock = stocks.entry(*issuer).or_insert(issuer_receivers.len());
+                if *stock < self.currency_params.sig_stock {
+                    *stock += 1;
+                    certs.push((*issuer, *receiver, cert));
+                }
+            }
+
+            let joiners_pubkeys: HashSet<PublicKey> = joiners.keys().copied().collect();
+            let rejected: Vec<PublicKey> = if joiners_pubkeys.is_empty() {
+                Vec::new()
+            } else {
+                let certs_pubkeys: Vec<(PublicKey, PublicKey)> = certs
+                    .iter()
+                    .map(|(issuer, receiver, _)| (*issuer, *receiver))
+                    .collect();
+                let next_wot = self.next_wot(&joiners_pubkeys, &certs_pubkeys, median_time)?;
+                joiners_pubkeys
+                    .iter()
+                    .copied()
+                    .filter(|joiner| {
+                        next_wot.certifiers.get(joiner).map_or(0, HashSet::len)
+                            < self.currency_params.sig_qty
+                            || next_wot.is_outdistanced(
+                                *joiner,
+                                self.currency_params.step_max as u32,
+                                self.currency_params.x_percent,
+                            )
+                    })
+                    .collect()
+            };
+
+            if rejected.is_empty() {
+                let sorted = |mut docs: Vec<String>| {
+                    docs.sort_unstable();
+                    docs
+                };
+                return Ok(PendingWotDocuments {
+                    identities: sorted(
+                        new_identities
+                            .into_iter()
+                            .filter(|(pubkey, _)| joiners.contains_key(pubkey))
+                            .map(|(_, idty)| idty)
+                            .collect(),
+                    ),
+                    joiners: sorted(joiners.into_iter().map(|(_, ms)| ms).collect()),
+                    actives: sorted(actives),
+                    leavers: sorted(leavers),
+                    certifications: sorted(
+                        certs.into_iter().map(|(_, _, cert)| cert.clone()).collect(),
+                    ),
+                    revoked: sorted(revoked),
+                    excluded: sorted(excluded.iter().map(PublicKey::to_base58).collect()),
+                });
+            }
+            for joiner in rejected {
+                joiners.remove(&joiner);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::genesis::tests::{builder, keypair};
+    use crate::genesis::{genesis_blockstamp, GenesisCert, GenesisMember};
+    use duniter_core::crypto::keys::{ed25519::Ed25519KeyPair, KeyPair as _};
+
+    fn compact_idty(newcomer: &GenesisMember) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            newcomer.pubkey.to_base58(),
+            newcomer.idty_sig.to_base64(),
+            genesis_blockstamp(),
+            newcomer.uid
+        )
+    }
+
+    pub(crate) fn compact_join(newcomer: &GenesisMember) -> String {
+        format!(
+            "{}:{}:{}:{}:{}",
+            newcomer.pubkey.to_base58(),
+            newcomer.membership_sig.to_base64(),
+            genesis_blockstamp(),
+            genesis_blockstamp(),
+            newcomer.uid
+        )
+    }
+
+    fn compact_cert(issuer: &Ed25519KeyPair, receiver: &GenesisMember) -> String {
+        let cert = GenesisCert::sign("test", issuer, receiver);
+        format!(
+            "{}:{}:0:{}",
+            cert.issuer.to_base58(),
+            cert.receiver.to_base58(),
+            cert.sig.to_base64()
+        )
+    }
+
+    /// Add the identity and the join of `newcomer` to the pending documents of `server`, with a
+    /// certification from each of `certifiers`
+    pub(crate) fn add_pending_newcomer(
+        server: &DuniterServer,
+        newcomer: &GenesisMember,
+        certifiers: &[Ed25519KeyPair],
+    ) -> DuniterServerResult<()> {
+        server.add_pending_identity(compact_idty(newcomer))?;
+        server.add_pending_membership(compact_join(newcomer), true)?;
+        for certifier in certifiers {
+            server.add_pending_cert(compact_cert(certifier, newcomer))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_pending_wot() -> anyhow::Result<()> {
+        let keypairs: Vec<_> = (1..=3).map(keypair).collect();
+        let genesis = builder(&keypairs).build_stringified(&keypairs[0])?;
+        let mut server = DuniterServer::test(DuniterCoreConf::default(), DuniterMode::Start)?;
+        server.apply_block(genesis.clone())?;
+        assert!(server.add_pending_identity("invalid".to_owned()).is_err());
+
+        // The first newcomer gets 2 certifications from members, the second one only 1
+        let newcomer1 = GenesisMember::sign("test", "newcomer1", &keypair(4));
+        let newcomer2 = GenesisMember::sign("test", "newcomer2", &keypair(5));
+        for newcomer in &[&newcomer1, &newcomer2] {
+            server.add_pending_identity(compact_idty(newcomer))?;
+            server.add_pending_membership(compact_join(newcomer), true)?;
+        }
+        let certs1 = vec![
+            compact_cert(&keypairs[0], &newcomer1),
+            compact_cert(&keypairs[1], &newcomer1),
+        ];
+        for cert in &certs1 {
+            server.add_pending_cert(cert.clone())?;
+        }
+        server.add_pending_cert(compact_cert(&keypairs[2], &newcomer2))?;
+        // Written and still valid
+        server.add_pending_cert(genesis.certifications[0].clone())?;
+
+        let wot = server.select_pending_wot(genesis.median_time)?;
+        assert_eq!(wot.identities, vec![compact_idty(&newcomer1)]);
+        assert_eq!(wot.joiners, vec![compact_join(&newcomer1)]);
+        let mut certs1 = certs1;
+        certs1.sort_unstable();
+        assert_eq!(wot.certifications, certs1);
+        assert!(wot.actives.is_empty());
+        assert!(wot.leavers.is_empty());
+
+        // The documents written by a block are no longer pending
+        let block =
+            crate::block_candidate::tests::forge_block(&server, &keypairs[0], genesis.time + 300)?;
+        assert_eq!(block.joiners, vec![compact_join(&newcomer1)]);
+        assert_eq!(server.validate_block(&block), Ok(()));
+        server.apply_block(block)?;
+        let wot = server.select_pending_wot(genesis.median_time + 300)?;
+        assert!(wot.identities.is_empty());
+        assert!(wot.joiners.is_empty());
+        assert!(wot.certifications.is_empty());
+        assert_eq!(server.wot_mp_db.identities().count()?, 1);
+
+        // A revoked member is excluded by the same block
+        assert!(server.add_pending_revocation("invalid".to_owned()).is_err());
+        let revocation = format!(
+            "{}:{}",
+            newcomer1.pubkey.to_base58(),
+            newcomer1.idty_sig.to_base64()
+        );
+        server.add_pending_revocation(revocation.clone())?;
+        let wot = server.select_pending_wot(genesis.median_time + 300)?;
+        assert_eq!(wot.revoked, vec![revocation]);
+        assert_eq!(wot.excluded, vec![newcomer1.pubkey.to_base58()]);
+
+        Ok(())
+    }
+}
@@ -21,8 +21,11 @@
     unused_import_braces
 )]
 
+pub mod network;
+
 #[cfg(test)]
 mod tests {
+    use crate::network::TestNetwork;
     use duniter_core::documents::transaction::TransactionDocumentV10Builder;
     use duniter_core::{
         common::prelude::*,
@@ -34,7 +37,8 @@ mod tests {
 
     #[test]
     fn test_txs_history() -> anyhow::Result<()> {
-        let server = DuniterServer::start(
+        let clock = SimulatedClock::new(1_600_000_000);
+        let server = DuniterServer::start_with_clock(
             DuniterCoreConf {
                 self_key_pair: Ed25519KeyPair::generate_random()
                     .expect("fail to gen random keypair"),
@@ -44,6 +48,7 @@ mod tests {
             DuniterMode::Start,
             None,
             "test",
+            std::sync::Arc::new(clock.clone()),
         )?;
 
         let tx = TransactionDocumentV10Builder {
@@ -59,17 +64,85 @@ mod tests {
         }
         .build_with_signature(smallvec![]);
 
-        let received_time = chrono::offset::Utc::now().timestamp();
+        let received_time = clock.now();
         server.add_pending_tx_force(tx.clone())?;
 
         let txs_history = server.get_transactions_history(PublicKey::default())?;
 
         tx.get_hash();
-        assert_eq!(txs_history.sending, vec![(tx, received_time)]);
+        assert_eq!(txs_history.sending, vec![(tx.clone(), received_time)]);
+
+        let txs_history_page =
+            server.get_transactions_history_page(PublicKey::default(), None, None, 10)?;
+        assert_eq!(txs_history_page.pending_sent, vec![(tx, received_time)]);
+        assert!(txs_history_page.pending_received.is_empty());
+        assert!(txs_history_page.next_cursor.is_none());
+
+        let txs_history_page = server.get_transactions_history_page(
+            PublicKey::default(),
+            Some(TxsHistoryRange::Blocks { from: 0, to: 10 }),
+            None,
+            10,
+        )?;
+        assert!(txs_history_page.pending_sent.is_empty());
+
+        // The transaction expires one transaction window after its received time
+        clock.advance(604_800);
+        server.trim_expired_txs()?;
+        assert_eq!(server.get_pending_txs(None, 0)?.len(), 1);
+        clock.advance(1);
+        server.trim_expired_txs()?;
+        assert_eq!(server.get_pending_txs(None, 0)?.len(), 0);
 
+        server.add_pending_tx_force(tx)?;
         server.remove_all_pending_txs()?;
 
-        assert_eq!(server.get_pending_txs(0, 0)?.len(), 0);
+        assert_eq!(server.get_pending_txs(None, 0)?.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_convergence() -> anyhow::Result<()> {
+        let mut network = TestNetwork::start(3)?;
+
+        for node in 0..3 {
+            network.clock.advance(300);
+            network.forge_block(node)?;
+            network.deliver_all()?;
+        }
+
+        assert!(network.is_converged());
+        assert_eq!(
+            network.current_blockstamps()[0].map(|blockstamp| blockstamp.number),
+            Some(BlockNumber(3))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_fork_resolution() -> anyhow::Result<()> {
+        let mut network = TestNetwork::start(3)?;
+
+        // Node 2 is isolated and forges its own branch
+        network.disconnect(0, 2);
+        network.disconnect(1, 2);
+        network.clock.advance(300);
+        network.forge_block(0)?;
+        network.forge_block(2)?;
+        network.clock.advance(300);
+        let best = network.forge_block(0)?;
+        network.deliver_all()?;
+        assert!(!network.is_converged());
+
+        // Once reconnected, node 2 switches to the longest branch
+        network.connect(0, 2);
+        network.connect(1, 2);
+        network.rebroadcast_blocks(0);
+        network.deliver_all()?;
+        assert!(network.is_converged());
+        assert_eq!(network.current_blockstamps()[2], Some(best));
 
         Ok(())
     }
@@ -0,0 +1,265 @@
+//  Copyright (C) 2020 Éloïs SANCHEZ.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Local test network: N in-process servers exchanging blocks, transactions, peers and HEADs
+//! through an in-memory bus, without any real network.
+
+use duniter_core::block::DubpBlockV10Stringified;
+use duniter_core::common::{currency_params::CurrencyParameters, prelude::*};
+use duniter_core::crypto::keys::{
+    ed25519::{Ed25519KeyPair, KeyPairFromSeed32Generator},
+    KeyPair as _,
+};
+use duniter_core::crypto::seeds::Seed32;
+use duniter_core::documents::{prelude::*, transaction::TransactionDocumentV10};
+use duniter_server::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+pub const TEST_CURRENCY: &str = "test_net";
+const TEST_PARAMETERS: &str = "0.0488:86400:1000:432000:100:5259600:63115200:1:5259600:5259600:0.8:31557600:5:24:300:12:0.67:1600000000:1600000000:15778800";
+const GENESIS_TIME: i64 = 1_600_000_000;
+
+#[derive(Clone, Debug)]
+pub enum NetworkMessage {
+    Block(DubpBlockV10Stringified),
+    Tx(TransactionDocumentV10),
+    Peer(PeerCardDbV1),
+    Heads(Vec<(DunpNodeIdV1Db, DunpHeadDbV1)>),
+}
+
+pub struct TestNode {
+    pub server: DuniterServer,
+    pub keypair: Ed25519KeyPair,
+    _profile: tempfile::TempDir,
+}
+
+impl TestNode {
+    pub fn pubkey(&self) -> PublicKey {
+        self.keypair.public_key()
+    }
+}
+
+pub struct TestNetwork {
+    /// Clock shared by all the nodes, only moves when the test advances it
+    pub clock: SimulatedClock,
+    pub nodes: Vec<TestNode>,
+    /// Every block forged in the network, by hash
+    blocks: HashMap<Hash, DubpBlockV10Stringified>,
+    /// Blocks forged by each node with `forge_block`, in order
+    forged: Vec<Vec<Hash>>,
+    /// Links that drop all messages, `(a, b)` with `a < b`
+    cut_links: HashSet<(usize, usize)>,
+    /// Messages in flight `(from, to, message)`
+    queue: VecDeque<(usize, usize, NetworkMessage)>,
+}
+
+impl TestNetwork {
+    /// Start `nodes_count` nodes (at least 2), all initial members of a new currency whose
+    /// genesis block is issued by node 0 and applied by every node.
+    pub fn start(nodes_count: usize) -> anyhow::Result<Self> {
+        let clock = SimulatedClock::new(GENESIS_TIME);
+        let keypairs: Vec<Ed25519KeyPair> = (0..nodes_count)
+            .map(|i| KeyPairFromSeed32Generator::generate(Seed32::new([i as u8 + 1; 32])))
+            .collect();
+
+        let genesis = genesis_block(&keypairs, clock.now() as u64)?;
+
+        let mut nodes = Vec::with_capacity(nodes_count);
+        for keypair in keypairs {
+            let profile = tempfile::tempdir()?;
+            let mut server = DuniterServer::start_with_clock(
+                DuniterCoreConf {
+                    self_key_pair: keypair.clone(),
+                    txs_mempool_size: 200,
+                },
+                TEST_CURRENCY.to_owned(),
+                DuniterMode::Start,
+                Some(profile.path()),
+                "test",
+                Arc::new(clock.clone()),
+            )?;
+            server.receive_block(genesis.clone())?;
+            nodes.push(TestNode {
+                server,
+                keypair,
+                _profile: profile,
+            });
+        }
+
+        let genesis_hash = nodes[0]
+            .server
+            .get_current_blockstamp()
+            .ok_or_else(|| anyhow::anyhow!("genesis block not applied"))?
+            .hash
+            .0;
+        let mut blocks = HashMap::new();
+        blocks.insert(genesis_hash, genesis);
+
+        Ok(TestNetwork {
+            clock,
+            nodes,
+            blocks,
+            forged: vec![Vec::new(); nodes_count],
+            cut_links: HashSet::new(),
+            queue: VecDeque::new(),
+        })
+    }
+    /// Drop all messages between `a` and `b` until `connect` is called
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        self.cut_links.insert(link(a, b));
+    }
+    pub fn connect(&mut self, a: usize, b: usize) {
+        self.cut_links.remove(&link(a, b));
+    }
+    pub fn send(&mut self, from: usize, to: usize, message: NetworkMessage) {
+        if from != to && !self.cut_links.contains(&link(from, to)) {
+            self.queue.push_back((from, to, message));
+        }
+    }
+    pub fn broadcast(&mut self, from: usize, message: NetworkMessage) {
+        for to in 0..self.nodes.len() {
+            self.send(from, to, message.clone());
+        }
+    }
+    /// Forge a block on top of the current block of `node` at the current time of the clock,
+    /// apply it locally and broadcast it.
+    pub fn forge_block(&mut self, node: usize) -> anyhow::Result<Blockstamp> {
+        let candidate = self.nodes[node]
+            .server
+            .generate_block_candidate(self.nodes[node].pubkey(), self.clock.now() as u64)?;
+        // Proved at the personalized difficulty of the issuer, not only at pow_min
+        let difficulty = self.nodes[node]
+            .server
+            .get_personalized_difficulty(self.nodes[node].pubkey())?
+            .map_or(candidate.pow_min, |personalized| personalized.difficulty);
+        let block = prove_block(
+            candidate,
+            &self.nodes[node].keypair,
+            difficulty as usize,
+            PowConf {
+                cpu: 1.0,
+                nb_cores: 1,
+                prefix: 0,
+            },
+        )?
+        .wait()
+        .ok_or_else(|| anyhow::anyhow!("proof of node {} cancelled", node))?;
+
+        match self.nodes[node].server.receive_block(block.clone())? {
+            ForkChoice::Applied => (),
+            fork_choice => return Err(anyhow::anyhow!("block not applied: {:?}", fork_choice)),
+        }
+        let blockstamp = self.nodes[node]
+            .server
+            .get_current_blockstamp()
+            .ok_or_else(|| anyhow::anyhow!("node {} has no blockchain", node))?;
+        self.blocks.insert(blockstamp.hash.0, block.clone());
+        self.forged[node].push(blockstamp.hash.0);
+        self.broadcast(node, NetworkMessage::Block(block));
+
+        Ok(blockstamp)
+    }
+    /// Broadcast again all the blocks forged by `node`, to resynchronize after a partition
+    pub fn rebroadcast_blocks(&mut self, node: usize) {
+        let blocks: Vec<DubpBlockV10Stringified> = self.forged[node]
+            .iter()
+            .map(|hash| self.blocks[hash].clone())
+            .collect();
+        for block in blocks {
+            self.broadcast(node, NetworkMessage::Block(block));
+        }
+    }
+    /// Deliver messages until the bus is empty. New blocks and accepted transactions are
+    /// relayed to the neighbors of the receiver. Return the number of delivered messages.
+    pub fn deliver_all(&mut self) -> anyhow::Result<usize> {
+        let mut delivered = 0;
+        while let Some((_from, to, message)) = self.queue.pop_front() {
+            delivered += 1;
+            let relay = match message {
+                NetworkMessage::Block(ref block) => !matches!(
+                    self.nodes[to].server.receive_block(block.clone())?,
+                    ForkChoice::AlreadyKnown
+                ),
+                NetworkMessage::Tx(ref tx) => {
+                    let server_pubkey = self.nodes[to].pubkey();
+                    self.nodes[to]
+                        .server
+                        .accept_new_tx(tx.clone(), server_pubkey)?
+                        .is_accepted()
+                }
+                NetworkMessage::Peer(ref peer) => {
+                    self.nodes[to].server.save_peer(peer.clone())?;
+                    false
+                }
+                NetworkMessage::Heads(ref heads) => {
+                    self.nodes[to].server.receive_new_heads(heads.clone())?;
+                    false
+                }
+            };
+            if relay {
+                self.broadcast(to, message);
+            }
+        }
+        Ok(delivered)
+    }
+    pub fn current_blockstamps(&self) -> Vec<Option<Blockstamp>> {
+        self.nodes
+            .iter()
+            .map(|node| node.server.get_current_blockstamp())
+            .collect()
+    }
+    /// All nodes are on the same current block
+    pub fn is_converged(&self) -> bool {
+        let currents = self.current_blockstamps();
+        currents.windows(2).all(|pair| pair[0] == pair[1])
+    }
+}
+
+fn link(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+/// Genesis block where every node is a member certified by all the others
+fn genesis_block(
+    keypairs: &[Ed25519KeyPair],
+    time: u64,
+) -> anyhow::Result<DubpBlockV10Stringified> {
+    let members: Vec<GenesisMember> = keypairs
+        .iter()
+        .enumerate()
+        .map(|(i, keypair)| GenesisMember::sign(TEST_CURRENCY, &format!("node{}", i), keypair))
+        .collect();
+    let mut certs = Vec::new();
+    for issuer in keypairs {
+        for receiver in &members {
+            if issuer.public_key() != receiver.pubkey {
+                certs.push(GenesisCert::sign(TEST_CURRENCY, issuer, receiver));
+            }
+        }
+    }
+    let currency_params: CurrencyParameters =
+        parse_genesis_parameters(TEST_CURRENCY, TEST_PARAMETERS).map_err(anyhow::Error::msg)?;
+
+    Ok(GenesisBlockBuilder {
+        currency: TEST_CURRENCY.to_owned(),
+        currency_params,
+        time,
+        pow_min: 0,
+        members,
+        certs,
+    }
+    .build_stringified(&keypairs[0])?)
+}